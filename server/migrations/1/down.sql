@@ -0,0 +1,14 @@
+DROP TABLE IF EXISTS task_stats CASCADE;
+DROP TABLE IF EXISTS evaluations CASCADE;
+DROP TABLE IF EXISTS answers CASCADE;
+DROP TABLE IF EXISTS assignments CASCADE;
+DROP TABLE IF EXISTS groups_users_join CASCADE;
+DROP TABLE IF EXISTS groups CASCADE;
+ALTER TABLE capsules DROP COLUMN IF EXISTS sound_track;
+ALTER TABLE capsules DROP COLUMN IF EXISTS duration_ms;
+ALTER TABLE capsules DROP COLUMN IF EXISTS webcam_settings;
+ALTER TABLE users DROP COLUMN IF EXISTS last_visited;
+ALTER TABLE users DROP COLUMN IF EXISTS member_since;
+DROP TYPE IF EXISTS task_stat_type;
+DROP TYPE IF EXISTS assignment_state;
+DROP TYPE IF EXISTS participant_role;
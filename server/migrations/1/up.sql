@@ -0,0 +1,78 @@
+-- The initial migration predates the groups, the assignments and the task statistics. Deployed
+-- databases may already have this schema, so every statement is idempotent.
+
+DO $$ BEGIN
+    CREATE TYPE participant_role AS ENUM ('student', 'teacher');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE assignment_state AS ENUM ('preparation', 'prepared', 'working', 'evaluation', 'finished');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE task_stat_type AS ENUM ('production', 'publication');
+EXCEPTION
+    WHEN duplicate_object THEN NULL;
+END $$;
+
+ALTER TYPE task_status ADD VALUE IF NOT EXISTS 'disabled' BEFORE 'idle';
+ALTER TYPE task_status ADD VALUE IF NOT EXISTS 'waiting' AFTER 'idle';
+ALTER TYPE task_status ADD VALUE IF NOT EXISTS 'failed' AFTER 'done';
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS member_since TIMESTAMP;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS last_visited TIMESTAMP;
+
+ALTER TABLE capsules ADD COLUMN IF NOT EXISTS webcam_settings JSON NOT NULL
+    DEFAULT '{"type": "pip", "anchor": "bottom_left", "opacity": 1.0, "position": [4, 4], "size": [533, 400], "keycolor": null}';
+ALTER TABLE capsules ALTER COLUMN webcam_settings DROP DEFAULT;
+ALTER TABLE capsules ADD COLUMN IF NOT EXISTS duration_ms INT NOT NULL DEFAULT 0;
+ALTER TABLE capsules ALTER COLUMN duration_ms DROP DEFAULT;
+ALTER TABLE capsules ADD COLUMN IF NOT EXISTS sound_track JSON;
+
+CREATE TABLE IF NOT EXISTS groups (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS groups_users_join (
+    id SERIAL PRIMARY KEY,
+    groups_id INT NOT NULL REFERENCES groups (id) ON DELETE CASCADE,
+    users_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    participant_role participant_role NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS assignments (
+    id SERIAL PRIMARY KEY,
+    criteria VARCHAR NOT NULL,
+    subject INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    answer_template INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    "group" INT NOT NULL REFERENCES groups (id) ON DELETE CASCADE,
+    state assignment_state NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS answers (
+    id SERIAL PRIMARY KEY,
+    assignment INT NOT NULL REFERENCES assignments (id) ON DELETE CASCADE,
+    capsule INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    finished BOOL NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS evaluations (
+    id SERIAL PRIMARY KEY,
+    answer INT NOT NULL REFERENCES answers (id) ON DELETE CASCADE,
+    reviewer INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    scores VARCHAR NOT NULL,
+    capsule INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS task_stats (
+    id SERIAL PRIMARY KEY,
+    ty task_stat_type NOT NULL,
+    trigger TIMESTAMP NOT NULL,
+    start TIMESTAMP,
+    "end" TIMESTAMP
+);
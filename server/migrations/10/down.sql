@@ -0,0 +1,7 @@
+ALTER TABLE answers DROP COLUMN reminders_sent;
+ALTER TABLE answers DROP COLUMN extension;
+ALTER TABLE answers DROP COLUMN submitted;
+ALTER TABLE answers DROP COLUMN late;
+ALTER TABLE assignments DROP COLUMN closes;
+ALTER TABLE assignments DROP COLUMN due;
+ALTER TABLE assignments DROP COLUMN opens;
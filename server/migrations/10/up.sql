@@ -0,0 +1,15 @@
+ALTER TABLE assignments ADD COLUMN opens TIMESTAMP;
+
+ALTER TABLE assignments ADD COLUMN due TIMESTAMP;
+
+ALTER TABLE assignments ADD COLUMN closes TIMESTAMP;
+
+ALTER TABLE answers ADD COLUMN late BOOL NOT NULL DEFAULT FALSE;
+ALTER TABLE answers ALTER COLUMN late DROP DEFAULT;
+
+ALTER TABLE answers ADD COLUMN submitted TIMESTAMP;
+
+ALTER TABLE answers ADD COLUMN extension TIMESTAMP;
+
+ALTER TABLE answers ADD COLUMN reminders_sent INT NOT NULL DEFAULT 0;
+ALTER TABLE answers ALTER COLUMN reminders_sent DROP DEFAULT;
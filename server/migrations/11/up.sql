@@ -0,0 +1,12 @@
+ALTER TYPE audit_action ADD VALUE 'group_invite_created' AFTER 'participant_removed';
+
+ALTER TYPE audit_action ADD VALUE 'group_invite_revoked' AFTER 'group_invite_created';
+
+CREATE TABLE group_invites (
+    id SERIAL PRIMARY KEY,
+    code VARCHAR NOT NULL UNIQUE,
+    "group" INT NOT NULL REFERENCES groups (id) ON DELETE CASCADE,
+    role participant_role NOT NULL,
+    created TIMESTAMP NOT NULL,
+    expires TIMESTAMP
+);
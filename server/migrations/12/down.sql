@@ -0,0 +1,5 @@
+ALTER TABLE evaluations ADD COLUMN IF NOT EXISTS scores VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE assignments ADD COLUMN IF NOT EXISTS criteria VARCHAR NOT NULL DEFAULT '';
+DROP TABLE scores CASCADE;
+ALTER TABLE evaluations DROP COLUMN done;
+DROP TABLE criterions CASCADE;
@@ -0,0 +1,73 @@
+CREATE TABLE criterions (
+    id SERIAL PRIMARY KEY,
+    assignment INT NOT NULL REFERENCES assignments (id) ON DELETE CASCADE,
+    position INT NOT NULL,
+    label VARCHAR NOT NULL,
+    description VARCHAR NOT NULL,
+    max_points DOUBLE PRECISION NOT NULL,
+    weight DOUBLE PRECISION NOT NULL
+);
+
+ALTER TABLE evaluations ADD COLUMN done BOOL NOT NULL DEFAULT FALSE;
+ALTER TABLE evaluations ALTER COLUMN done DROP DEFAULT;
+
+CREATE TABLE scores (
+    id SERIAL PRIMARY KEY,
+    evaluation INT NOT NULL REFERENCES evaluations (id) ON DELETE CASCADE,
+    criterion INT NOT NULL REFERENCES criterions (id) ON DELETE CASCADE,
+    points DOUBLE PRECISION NOT NULL
+);
+
+-- The criteria used to be stored as newline separated labels in assignments.criteria, and the
+-- scores as newline separated integers in evaluations.scores.
+CREATE TEMPORARY TABLE legacy_criteria AS
+    SELECT assignment, ROW_NUMBER() OVER (PARTITION BY assignment ORDER BY n) - 1 AS position, label
+    FROM (
+        SELECT assignments.id AS assignment, n, regexp_replace(line, '^\s+|\s+$', '', 'g') AS label
+        FROM assignments, regexp_split_to_table(assignments.criteria, E'\n') WITH ORDINALITY AS t (line, n)
+    ) AS lines
+    WHERE label <> '';
+
+-- The lines that are not integers are ignored.
+CREATE TEMPORARY TABLE legacy_scores AS
+    SELECT evaluation, assignment, ROW_NUMBER() OVER (PARTITION BY evaluation ORDER BY n) - 1 AS position, points
+    FROM (
+        SELECT evaluations.id AS evaluation, answers.assignment, n, trim(line)::INT::DOUBLE PRECISION AS points
+        FROM evaluations
+        JOIN answers ON answers.id = evaluations.answer,
+        regexp_split_to_table(evaluations.scores, E'\n') WITH ORDINALITY AS t (line, n)
+        WHERE trim(line) ~ '^[+-]?[0-9]{1,9}$'
+    ) AS lines;
+
+-- The maximum number of points is 10, unless higher scores were given, so that the existing scores
+-- stay valid.
+INSERT INTO criterions (assignment, position, label, description, max_points, weight)
+    SELECT assignment, position, label, '', GREATEST(10, (
+        SELECT MAX(points) FROM legacy_scores
+        WHERE legacy_scores.assignment = legacy_criteria.assignment
+        AND legacy_scores.position = legacy_criteria.position
+    )), 1
+    FROM legacy_criteria
+    ORDER BY assignment, position;
+
+-- Only the evaluations that have a score for each criterion are kept, the others are left to do.
+CREATE TEMPORARY TABLE legacy_evaluations AS
+    SELECT evaluation FROM legacy_scores
+    GROUP BY evaluation, assignment
+    HAVING COUNT(*) = (SELECT COUNT(*) FROM legacy_criteria WHERE legacy_criteria.assignment = legacy_scores.assignment);
+
+INSERT INTO scores (evaluation, criterion, points)
+    SELECT legacy_scores.evaluation, criterions.id, legacy_scores.points
+    FROM legacy_scores
+    JOIN legacy_evaluations ON legacy_evaluations.evaluation = legacy_scores.evaluation
+    JOIN criterions ON criterions.assignment = legacy_scores.assignment AND criterions.position = legacy_scores.position
+    ORDER BY legacy_scores.evaluation, legacy_scores.position;
+
+UPDATE evaluations SET done = TRUE WHERE id IN (SELECT evaluation FROM legacy_evaluations);
+
+DROP TABLE legacy_evaluations;
+DROP TABLE legacy_scores;
+DROP TABLE legacy_criteria;
+
+ALTER TABLE assignments DROP COLUMN criteria;
+ALTER TABLE evaluations DROP COLUMN scores;
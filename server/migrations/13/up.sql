@@ -0,0 +1,13 @@
+CREATE TABLE assignment_templates (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    owner INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    subject INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    answer_template INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    criteria JSON NOT NULL,
+    peer_reviews INT NOT NULL,
+    anonymous_reviews BOOL NOT NULL,
+    due_after INT,
+    closes_after INT,
+    review_duration INT
+);
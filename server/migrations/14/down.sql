@@ -0,0 +1,2 @@
+ALTER TABLE capsules DROP COLUMN gos_thumbnails;
+ALTER TABLE capsules DROP COLUMN thumbnails_generated;
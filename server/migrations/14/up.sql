@@ -0,0 +1,4 @@
+ALTER TABLE capsules ADD COLUMN thumbnails_generated BOOL NOT NULL DEFAULT false;
+ALTER TABLE capsules ALTER COLUMN thumbnails_generated DROP DEFAULT;
+ALTER TABLE capsules ADD COLUMN gos_thumbnails JSON NOT NULL DEFAULT '[]';
+ALTER TABLE capsules ALTER COLUMN gos_thumbnails DROP DEFAULT;
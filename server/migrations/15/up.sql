@@ -0,0 +1 @@
+ALTER TABLE capsules ADD COLUMN publication_profile VARCHAR;
@@ -0,0 +1,3 @@
+DROP TABLE exports CASCADE;
+ALTER TABLE capsules DROP COLUMN allow_download;
+DROP TYPE export_format;
@@ -0,0 +1,16 @@
+CREATE TYPE export_format AS ENUM ('mp4', 'mp3', 'm4a', 'scorm');
+
+ALTER TYPE task_stat_type ADD VALUE 'export' AFTER 'publication';
+
+ALTER TABLE capsules ADD COLUMN allow_download BOOL NOT NULL DEFAULT false;
+ALTER TABLE capsules ALTER COLUMN allow_download DROP DEFAULT;
+
+CREATE TABLE exports (
+    id SERIAL PRIMARY KEY,
+    capsule INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    format export_format NOT NULL,
+    height INT,
+    status task_status NOT NULL,
+    pid INT,
+    created TIMESTAMP NOT NULL
+);
@@ -0,0 +1,10 @@
+CREATE TABLE quiz_answers (
+    id SERIAL PRIMARY KEY,
+    capsule INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    quiz VARCHAR NOT NULL,
+    session VARCHAR NOT NULL,
+    user_id INT REFERENCES users (id) ON DELETE CASCADE,
+    option INT NOT NULL,
+    correct BOOL NOT NULL,
+    answered TIMESTAMP NOT NULL
+);
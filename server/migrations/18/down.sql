@@ -0,0 +1,3 @@
+DROP TABLE comments CASCADE;
+ALTER TABLE capsules DROP COLUMN comment_visibility;
+DROP TYPE comment_visibility;
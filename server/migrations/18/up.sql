@@ -0,0 +1,14 @@
+CREATE TYPE comment_visibility AS ENUM ('disabled', 'collaborators', 'viewers');
+
+ALTER TABLE capsules ADD COLUMN comment_visibility comment_visibility;
+
+CREATE TABLE comments (
+    id SERIAL PRIMARY KEY,
+    capsule INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    author INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    parent INT REFERENCES comments (id) ON DELETE CASCADE,
+    time INT NOT NULL,
+    content VARCHAR NOT NULL,
+    hidden BOOL NOT NULL,
+    created TIMESTAMP NOT NULL
+);
@@ -0,0 +1,7 @@
+ALTER TABLE capsules ADD COLUMN legacy_project VARCHAR;
+UPDATE capsules SET legacy_project = projects.name FROM projects WHERE projects.id = capsules.project;
+ALTER TABLE capsules DROP COLUMN project;
+ALTER TABLE capsules RENAME COLUMN legacy_project TO project;
+ALTER TABLE capsules ALTER COLUMN project SET NOT NULL;
+DROP TABLE projects_users_join CASCADE;
+DROP TABLE projects CASCADE;
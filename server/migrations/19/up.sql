@@ -0,0 +1,48 @@
+CREATE TABLE projects (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    description VARCHAR NOT NULL,
+    capsule_order JSON NOT NULL,
+    last_modified TIMESTAMP NOT NULL
+);
+
+CREATE TABLE projects_users_join (
+    id SERIAL PRIMARY KEY,
+    projects_id INT NOT NULL REFERENCES projects (id) ON DELETE CASCADE,
+    users_id INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    role role NOT NULL
+);
+
+-- The capsules used to store the name of their project as text. A project is created for each name
+-- used by each owner, capsules without owner get a project without collaborators.
+ALTER TABLE capsules RENAME COLUMN project TO legacy_project;
+ALTER TABLE capsules ADD COLUMN project INT REFERENCES projects (id) ON DELETE CASCADE;
+
+CREATE TEMPORARY TABLE legacy_projects AS
+    SELECT id AS capsule, legacy_project AS name, (
+        SELECT MIN(users_id) FROM capsules_users_join
+        WHERE capsules_id = capsules.id AND role = 'owner'
+    ) AS owner
+    FROM capsules;
+
+CREATE TEMPORARY TABLE legacy_names AS
+    SELECT nextval('projects_id_seq')::INT AS id, owner, name
+    FROM (SELECT DISTINCT owner, name FROM legacy_projects) AS names;
+
+INSERT INTO projects (id, name, description, capsule_order, last_modified)
+    SELECT id, name, '', '[]', NOW() FROM legacy_names ORDER BY id;
+
+INSERT INTO projects_users_join (projects_id, users_id, role)
+    SELECT id, owner, 'owner' FROM legacy_names WHERE owner IS NOT NULL ORDER BY id;
+
+UPDATE capsules SET project = legacy_names.id
+    FROM legacy_projects
+    JOIN legacy_names ON legacy_names.name = legacy_projects.name
+    AND legacy_names.owner IS NOT DISTINCT FROM legacy_projects.owner
+    WHERE capsules.id = legacy_projects.capsule;
+
+DROP TABLE legacy_names;
+DROP TABLE legacy_projects;
+
+ALTER TABLE capsules ALTER COLUMN project SET NOT NULL;
+ALTER TABLE capsules DROP COLUMN legacy_project;
@@ -0,0 +1,7 @@
+CREATE TABLE login_lockouts (
+    id SERIAL PRIMARY KEY,
+    key VARCHAR NOT NULL,
+    failures INT NOT NULL,
+    date TIMESTAMP NOT NULL,
+    locked_until TIMESTAMP NOT NULL
+);
@@ -0,0 +1,2 @@
+DROP TABLE audit_logs CASCADE;
+DROP TYPE audit_action;
@@ -0,0 +1,14 @@
+CREATE TYPE audit_action AS ENUM ('capsule_deleted', 'privacy_changed', 'capsule_published', 'capsule_unpublished', 'collaborator_added', 'role_changed', 'collaborator_removed', 'group_created', 'group_deleted', 'participant_added', 'participant_removed', 'assignment_created', 'assignment_deleted', 'assignment_state_changed', 'answer_validated', 'user_deleted', 'user_deleted_by_admin', 'user_invited', 'password_changed', 'email_change_requested', 'login_lockout');
+
+CREATE TABLE audit_logs (
+    id SERIAL PRIMARY KEY,
+    date TIMESTAMP NOT NULL,
+    actor_id INT,
+    actor VARCHAR,
+    action audit_action NOT NULL,
+    target_capsule INT,
+    target_user INT,
+    target_group INT,
+    before JSON,
+    after JSON
+);
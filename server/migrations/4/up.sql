@@ -0,0 +1,14 @@
+ALTER TYPE audit_action ADD VALUE 'share_token_created' AFTER 'collaborator_removed';
+
+ALTER TYPE audit_action ADD VALUE 'share_token_revoked' AFTER 'share_token_created';
+
+CREATE TABLE share_tokens (
+    id SERIAL PRIMARY KEY,
+    token VARCHAR NOT NULL UNIQUE,
+    capsule INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    created TIMESTAMP NOT NULL,
+    expires TIMESTAMP,
+    max_views INT,
+    views INT NOT NULL,
+    hashed_password VARCHAR
+);
@@ -0,0 +1,2 @@
+ALTER TABLE capsules ADD COLUMN embed_origins JSON NOT NULL DEFAULT '[]';
+ALTER TABLE capsules ALTER COLUMN embed_origins DROP DEFAULT;
@@ -0,0 +1,9 @@
+CREATE TABLE views (
+    id SERIAL PRIMARY KEY,
+    session VARCHAR NOT NULL UNIQUE,
+    capsule INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    user_id INT REFERENCES users (id) ON DELETE CASCADE,
+    started TIMESTAMP NOT NULL,
+    last_seen TIMESTAMP NOT NULL,
+    watched JSON NOT NULL
+);
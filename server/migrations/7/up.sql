@@ -0,0 +1,9 @@
+CREATE TABLE watch_progresss (
+    id SERIAL PRIMARY KEY,
+    "user" INT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    capsule INT NOT NULL REFERENCES capsules (id) ON DELETE CASCADE,
+    watched JSON NOT NULL,
+    position INT NOT NULL,
+    updated TIMESTAMP NOT NULL,
+    UNIQUE ("user", capsule)
+);
@@ -0,0 +1,12 @@
+DO $$ BEGIN
+    IF EXISTS (SELECT 1 FROM evaluations WHERE feedback_capsule IS NULL) THEN
+        RAISE EXCEPTION 'evaluations % have no feedback capsule',
+            (SELECT string_agg(id::text, ', ') FROM evaluations WHERE feedback_capsule IS NULL);
+    END IF;
+END $$;
+ALTER TABLE evaluations DROP CONSTRAINT evaluations_feedback_capsule_fkey;
+ALTER TABLE evaluations RENAME COLUMN feedback_capsule TO capsule;
+ALTER TABLE evaluations ALTER COLUMN capsule SET NOT NULL;
+ALTER TABLE evaluations ADD CONSTRAINT evaluations_capsule_fkey
+    FOREIGN KEY (capsule) REFERENCES capsules (id) ON DELETE CASCADE;
+ALTER TABLE answers DROP COLUMN student;
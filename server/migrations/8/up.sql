@@ -0,0 +1,26 @@
+ALTER TYPE audit_action ADD VALUE 'answer_evaluated' AFTER 'answer_validated';
+
+-- The student of an answer is the user that can write its capsule, the owner is the teacher.
+ALTER TABLE answers ADD COLUMN student INT REFERENCES users (id) ON DELETE CASCADE;
+UPDATE answers SET student = COALESCE(
+    (SELECT users_id FROM capsules_users_join WHERE capsules_id = answers.capsule AND role = 'write' LIMIT 1),
+    (SELECT users_id FROM capsules_users_join WHERE capsules_id = answers.capsule AND role = 'owner' LIMIT 1)
+);
+
+-- Answers whose student cannot be found are not deleted: the migration stops so that they can be
+-- fixed by hand, by giving write access to their capsule to their student.
+DO $$ BEGIN
+    IF EXISTS (SELECT 1 FROM answers WHERE student IS NULL) THEN
+        RAISE EXCEPTION 'answers % have no student: give write access to their capsule to their student',
+            (SELECT string_agg(id::text, ', ') FROM answers WHERE student IS NULL);
+    END IF;
+END $$;
+
+ALTER TABLE answers ALTER COLUMN student SET NOT NULL;
+
+-- The feedback capsule is optional, it keeps its foreign key but is unset when the capsule is deleted.
+ALTER TABLE evaluations RENAME COLUMN capsule TO feedback_capsule;
+ALTER TABLE evaluations ALTER COLUMN feedback_capsule DROP NOT NULL;
+ALTER TABLE evaluations DROP CONSTRAINT evaluations_capsule_fkey;
+ALTER TABLE evaluations ADD CONSTRAINT evaluations_feedback_capsule_fkey
+    FOREIGN KEY (feedback_capsule) REFERENCES capsules (id) ON DELETE SET NULL;
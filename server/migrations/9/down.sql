@@ -0,0 +1,4 @@
+ALTER TABLE evaluations DROP COLUMN peer;
+ALTER TABLE assignments DROP COLUMN review_deadline;
+ALTER TABLE assignments DROP COLUMN anonymous_reviews;
+ALTER TABLE assignments DROP COLUMN peer_reviews;
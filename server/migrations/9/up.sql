@@ -0,0 +1,11 @@
+ALTER TABLE assignments ADD COLUMN peer_reviews INT NOT NULL DEFAULT 0;
+ALTER TABLE assignments ALTER COLUMN peer_reviews DROP DEFAULT;
+
+ALTER TABLE assignments ADD COLUMN anonymous_reviews BOOL NOT NULL DEFAULT FALSE;
+ALTER TABLE assignments ALTER COLUMN anonymous_reviews DROP DEFAULT;
+
+ALTER TABLE assignments ADD COLUMN review_deadline TIMESTAMP;
+
+-- The existing evaluations were all made by teachers.
+ALTER TABLE evaluations ADD COLUMN peer BOOL NOT NULL DEFAULT FALSE;
+ALTER TABLE evaluations ALTER COLUMN peer DROP DEFAULT;
@@ -0,0 +1,343 @@
+//! This module contains the content addressed store that lets capsules share identical files.
+//!
+//! Files of capsules can be hard links to blobs named after the hash of their content, stored in
+//! the `blobs` directory of the data path. The number of links of a blob, minus the one of the
+//! store, is the number of files that use it, so the file system keeps the refcount for us: a
+//! blob whose only remaining link is the one of the store is garbage and can be removed.
+//!
+//! Shared files must never be written in place, which is why the files that are rewritten by the
+//! production are detached before it starts.
+
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use tokio::fs::{copy, create_dir_all, hard_link, metadata, read_dir, remove_file, rename};
+use tokio::task::spawn_blocking;
+
+use uuid::Uuid;
+
+use rocket::http::Status;
+
+use crate::config::Config;
+use crate::storage::walk;
+use crate::{Error, Result};
+
+/// Computes the hex encoded sha256 of the content of a file.
+async fn hash(path: &Path) -> Result<String> {
+    let path = path.to_owned();
+
+    spawn_blocking(move || -> Result<String> {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 1 << 16];
+
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+
+        Ok(hasher
+            .finalize()
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect::<String>())
+    })
+    .await
+    .map_err(|_| Error(Status::InternalServerError))?
+}
+
+/// Returns a path next to a file that can be used to replace it atomically.
+fn temporary_path(path: &Path) -> Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .and_then(|x| x.to_str())
+        .ok_or(Error(Status::InternalServerError))?;
+
+    Ok(path.with_file_name(format!(".{}.{}", file_name, Uuid::new_v4())))
+}
+
+/// The store of blobs shared between capsules.
+pub struct BlobStore {
+    /// The directory containing the blobs.
+    root: PathBuf,
+}
+
+impl BlobStore {
+    /// Creates the blob store of the data path of the config.
+    pub fn new(config: &Config) -> BlobStore {
+        BlobStore {
+            root: config.data_path.join("blobs"),
+        }
+    }
+
+    /// Returns the path of the blob for a hash.
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.root.join(&hash[..2]).join(hash)
+    }
+
+    /// Makes a file a link to the blob of its content, creating the blob if needed.
+    ///
+    /// If a blob with the same content already exists, the file is replaced by a link to it so
+    /// that the content is only stored once.
+    async fn intern(&self, path: &Path) -> Result<()> {
+        let file = metadata(path).await?;
+        let blob = self.blob_path(&hash(path).await?);
+
+        create_dir_all(blob.parent().ok_or(Error(Status::InternalServerError))?).await?;
+
+        match hard_link(path, &blob).await {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        let existing = metadata(&blob).await?;
+        if existing.ino() != file.ino() || existing.dev() != file.dev() {
+            let tmp = temporary_path(path)?;
+            hard_link(&blob, &tmp).await?;
+            rename(&tmp, path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Makes a file share the content of another one, instead of copying it.
+    ///
+    /// The file is copied if it cannot be linked, for example if the store is on another device.
+    pub async fn link(&self, orig: &Path, dest: &Path) -> Result<()> {
+        // A file with several links is already in the store, there is no need to hash it again.
+        if metadata(orig).await?.nlink() == 1 {
+            if let Err(e) = self.intern(orig).await {
+                warn!("Failed to add {} to the blob store: {}", orig.display(), e);
+                copy(orig, dest).await?;
+                return Ok(());
+            }
+        }
+
+        if hard_link(orig, dest).await.is_err() {
+            copy(orig, dest).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Links every file of a directory into another directory.
+    pub async fn link_dir(&self, orig: &Path, dest: &Path) -> Result<()> {
+        create_dir_all(dest).await?;
+
+        let mut iter = read_dir(orig).await?;
+        while let Some(entry) = iter.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                self.link(&entry.path(), &dest.join(entry.file_name()))
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gives a file its own copy of its content if it is shared, so it can be written in place.
+    pub async fn detach(&self, path: &Path) -> Result<()> {
+        match metadata(path).await {
+            Ok(meta) if meta.is_file() && meta.nlink() > 1 => {
+                let tmp = temporary_path(path)?;
+                copy(path, &tmp).await?;
+                rename(&tmp, path).await?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Detaches every file of a directory and of its subdirectories.
+    pub async fn detach_dir(&self, dir: &Path) -> Result<()> {
+        for (path, _) in walk(dir).await? {
+            self.detach(&path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the blobs that are no longer used by any file.
+    ///
+    /// Returns the number of blobs removed and the number of bytes freed.
+    pub async fn gc(&self) -> Result<(usize, u64)> {
+        let mut removed = 0;
+        let mut freed = 0;
+
+        let mut prefixes = match read_dir(&self.root).await {
+            Ok(iter) => iter,
+            Err(_) => return Ok((0, 0)),
+        };
+
+        while let Some(prefix) = prefixes.next_entry().await? {
+            if !prefix.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let mut blobs = read_dir(prefix.path()).await?;
+            while let Some(blob) = blobs.next_entry().await? {
+                let meta = blob.metadata().await?;
+                if meta.is_file() && meta.nlink() == 1 {
+                    remove_file(blob.path()).await?;
+                    removed += 1;
+                    freed += meta.len();
+                }
+            }
+        }
+
+        Ok((removed, freed))
+    }
+}
+
+/// Computes the disk usage of a directory in bytes, splitting the size of shared files evenly
+/// between the files that share them.
+pub async fn disk_usage(path: &Path) -> Result<u64> {
+    let mut total = 0;
+    let mut dirs = vec![path.to_owned()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut iter = read_dir(&dir).await?;
+
+        while let Some(entry) = iter.next_entry().await? {
+            let meta = entry.metadata().await?;
+
+            if meta.is_dir() {
+                dirs.push(entry.path());
+            } else if meta.nlink() > 1 {
+                // One of the links is the one of the store.
+                total += meta.len() / (meta.nlink() - 1);
+            } else {
+                total += meta.len();
+            }
+        }
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+    use std::path::PathBuf;
+
+    use tokio::fs::{create_dir_all, metadata, read, remove_dir_all, remove_file, write};
+
+    use uuid::Uuid;
+
+    use super::{disk_usage, BlobStore};
+
+    /// Creates an empty directory and a blob store inside it.
+    async fn setup() -> (PathBuf, BlobStore) {
+        let dir = std::env::temp_dir().join(format!("polymny-blobs-{}", Uuid::new_v4()));
+        create_dir_all(dir.join("files")).await.unwrap();
+        let store = BlobStore {
+            root: dir.join("blobs"),
+        };
+        (dir, store)
+    }
+
+    #[rocket::async_test]
+    async fn intern_shares_identical_files() {
+        let (dir, store) = setup().await;
+        let a = dir.join("files/a");
+        let b = dir.join("files/b");
+        write(&a, b"content").await.unwrap();
+        write(&b, b"content").await.unwrap();
+
+        store.intern(&a).await.unwrap();
+        store.intern(&b).await.unwrap();
+
+        let (a, b) = (metadata(&a).await.unwrap(), metadata(&b).await.unwrap());
+        assert_eq!(a.ino(), b.ino());
+        assert_eq!(a.nlink(), 3);
+
+        remove_dir_all(dir).await.unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn detach_gives_its_own_copy() {
+        let (dir, store) = setup().await;
+        let orig = dir.join("files/orig");
+        let dest = dir.join("files/dest");
+        write(&orig, b"content").await.unwrap();
+
+        store.link(&orig, &dest).await.unwrap();
+        assert_eq!(
+            metadata(&orig).await.unwrap().ino(),
+            metadata(&dest).await.unwrap().ino()
+        );
+
+        store.detach(&dest).await.unwrap();
+        let (orig_meta, dest_meta) = (
+            metadata(&orig).await.unwrap(),
+            metadata(&dest).await.unwrap(),
+        );
+        assert_ne!(orig_meta.ino(), dest_meta.ino());
+        assert_eq!(dest_meta.nlink(), 1);
+        assert_eq!(orig_meta.nlink(), 2);
+        assert_eq!(read(&dest).await.unwrap(), b"content");
+
+        remove_dir_all(dir).await.unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn detach_dir_detaches_nested_files() {
+        let (dir, store) = setup().await;
+        let orig = dir.join("files/orig");
+        let nested = dir.join("files/capsule/assets/nested");
+        write(&orig, b"content").await.unwrap();
+        create_dir_all(nested.parent().unwrap()).await.unwrap();
+        store.link(&orig, &nested).await.unwrap();
+
+        store.detach_dir(&dir.join("files/capsule")).await.unwrap();
+        let (orig_meta, nested_meta) = (
+            metadata(&orig).await.unwrap(),
+            metadata(&nested).await.unwrap(),
+        );
+        assert_ne!(orig_meta.ino(), nested_meta.ino());
+        assert_eq!(nested_meta.nlink(), 1);
+        assert_eq!(read(&nested).await.unwrap(), b"content");
+
+        store.detach_dir(&dir.join("files/missing")).await.unwrap();
+
+        remove_dir_all(dir).await.unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn gc_only_removes_unused_blobs() {
+        let (dir, store) = setup().await;
+        let used = dir.join("files/used");
+        let unused = dir.join("files/unused");
+        write(&used, b"used").await.unwrap();
+        write(&unused, b"unused").await.unwrap();
+
+        store.intern(&used).await.unwrap();
+        store.intern(&unused).await.unwrap();
+        remove_file(&unused).await.unwrap();
+
+        assert_eq!(store.gc().await.unwrap(), (1, 6));
+        assert_eq!(store.gc().await.unwrap(), (0, 0));
+        assert_eq!(metadata(&used).await.unwrap().nlink(), 2);
+
+        remove_dir_all(dir).await.unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn disk_usage_splits_shared_files() {
+        let (dir, store) = setup().await;
+        let orig = dir.join("files/orig");
+        write(&orig, b"0123456789").await.unwrap();
+        store.link(&orig, &dir.join("files/copy")).await.unwrap();
+
+        assert_eq!(disk_usage(&dir.join("files")).await.unwrap(), 10);
+
+        remove_dir_all(dir).await.unwrap();
+    }
+}
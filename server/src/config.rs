@@ -1,5 +1,6 @@
 //! This module contains the struct useful for the configuration.
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
@@ -7,6 +8,9 @@ use serde::{Deserialize, Serialize};
 use rocket::figment::Figment;
 use rocket::Phase;
 
+use crate::db::capsule::Privacy;
+use crate::db::comment::CommentVisibility;
+use crate::db::user::Plan;
 use crate::mailer::Mailer;
 
 fn default_premium_only() -> bool {
@@ -100,6 +104,131 @@ fn default_harsh_length() -> usize {
     0
 }
 
+fn default_login_free_attempts() -> u32 {
+    3
+}
+
+fn default_login_max_attempts() -> u32 {
+    10
+}
+
+fn default_login_account_max_attempts() -> u32 {
+    100
+}
+
+fn default_login_lockout_duration() -> u64 {
+    900
+}
+
+fn default_login_persist_lockouts() -> bool {
+    false
+}
+
+fn default_reset_max_requests() -> u32 {
+    5
+}
+
+fn default_roster_max_invitations() -> u32 {
+    100
+}
+
+fn default_quiz_max_answers() -> u32 {
+    500
+}
+
+fn default_view_max_beacons() -> u32 {
+    3000
+}
+
+fn default_comment_max_notifications() -> u32 {
+    20
+}
+
+fn default_segment_token_duration() -> u64 {
+    21600
+}
+
+fn default_assignment_reminders() -> Vec<u64> {
+    vec![24]
+}
+
+fn default_storage() -> StorageConfig {
+    StorageConfig::Local
+}
+
+fn default_s3_region() -> String {
+    String::from("us-east-1")
+}
+
+fn default_s3_path_style() -> bool {
+    true
+}
+
+fn default_s3_working_copy_lifetime() -> u64 {
+    86400
+}
+
+fn default_content_language() -> String {
+    String::from("fr")
+}
+
+fn default_publication_profiles() -> BTreeMap<String, PublicationProfile> {
+    let rendition = |name: &str, height, video_bitrate, audio_bitrate| Rendition {
+        name: String::from(name),
+        height,
+        video_bitrate,
+        audio_bitrate,
+    };
+
+    let standard = vec![
+        rendition("audio", None, 0, 64),
+        rendition("360p", Some(360), 800, 96),
+        rendition("480p", Some(480), 1400, 128),
+        rendition("720p", Some(720), 2800, 128),
+    ];
+
+    let mut hd = standard.clone();
+    hd.push(rendition("1080p", Some(1080), 5000, 192));
+
+    let mut profiles = BTreeMap::new();
+
+    profiles.insert(
+        String::from("standard"),
+        PublicationProfile {
+            codec: VideoCodec::H264,
+            segment_duration: 6,
+            renditions: standard,
+        },
+    );
+
+    profiles.insert(
+        String::from("hd"),
+        PublicationProfile {
+            codec: VideoCodec::H264,
+            segment_duration: 6,
+            renditions: hd,
+        },
+    );
+
+    profiles
+}
+
+fn default_plan_publication_profiles() -> PlanPublicationProfiles {
+    PlanPublicationProfiles {
+        free: vec![String::from("standard")],
+        premium_lvl1: vec![String::from("hd"), String::from("standard")],
+        admin: vec![String::from("hd"), String::from("standard")],
+    }
+}
+
+fn default_privacy_comment_visibility() -> PrivacyCommentVisibility {
+    PrivacyCommentVisibility {
+        public: CommentVisibility::Collaborators,
+        unlisted: CommentVisibility::Collaborators,
+        private: CommentVisibility::Viewers,
+    }
+}
+
 /// The databases of the server.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Databases {
@@ -114,6 +243,212 @@ pub struct Database {
     pub url: String,
 }
 
+/// The backend in which the files of the capsules are stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// The files are stored in the data path.
+    Local,
+
+    /// The files are stored in a bucket of an S3 compatible object storage.
+    S3(S3Config),
+}
+
+/// The config of an S3 compatible object storage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    /// The name of the bucket.
+    pub bucket: String,
+
+    /// The url of the object storage.
+    pub endpoint: String,
+
+    /// The region of the bucket.
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+
+    /// The access key of the object storage.
+    pub access_key: String,
+
+    /// The secret key of the object storage.
+    pub secret_key: String,
+
+    /// Whether the bucket is in the path of the urls rather than in their domain.
+    #[serde(default = "default_s3_path_style")]
+    pub path_style: bool,
+
+    /// Number of seconds after which the unused working copy of a capsule is removed from the
+    /// data path.
+    #[serde(default = "default_s3_working_copy_lifetime")]
+    pub working_copy_lifetime: u64,
+}
+
+/// The codec of the videos of a publication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    /// H.264, supported by every browser.
+    H264,
+
+    /// AV1, lighter but slower to encode and not supported by older devices.
+    Av1,
+
+    /// VP9, not supported by older Apple devices.
+    Vp9,
+}
+
+impl VideoCodec {
+    /// Returns the name of the codec given to the publication script.
+    pub fn name(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264",
+            VideoCodec::Av1 => "av1",
+            VideoCodec::Vp9 => "vp9",
+        }
+    }
+
+    /// Returns the RFC 6381 name of the video codec, as written in HLS playlists.
+    pub fn rfc6381(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "avc1.640028",
+            VideoCodec::Av1 => "av01.0.08M.08",
+            VideoCodec::Vp9 => "vp09.00.40.08",
+        }
+    }
+}
+
+/// A rendition of a published video.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rendition {
+    /// The name of the rendition, which is also the name of its directory in the publication.
+    pub name: String,
+
+    /// The height of the video, none for an audio only rendition.
+    #[serde(default)]
+    pub height: Option<u32>,
+
+    /// The bitrate of the video in kbit/s, ignored for an audio only rendition.
+    #[serde(default)]
+    pub video_bitrate: u32,
+
+    /// The bitrate of the audio in kbit/s.
+    pub audio_bitrate: u32,
+}
+
+impl Rendition {
+    /// Returns the argument describing the rendition for the publication script.
+    pub fn script_arg(&self) -> String {
+        match self.height {
+            Some(height) => format!(
+                "{}:{}:{}:{}",
+                self.name, height, self.video_bitrate, self.audio_bitrate
+            ),
+            None => format!("{}:audio:0:{}", self.name, self.audio_bitrate),
+        }
+    }
+}
+
+/// A publication profile, describing how the videos are encoded when they are published.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublicationProfile {
+    /// The codec of the videos.
+    pub codec: VideoCodec,
+
+    /// The duration of the segments in seconds.
+    pub segment_duration: u32,
+
+    /// The renditions of the videos.
+    pub renditions: Vec<Rendition>,
+}
+
+impl PublicationProfile {
+    /// Checks that the profile can be given to the publication script, returns the reason why it
+    /// cannot otherwise.
+    ///
+    /// The renditions are given to the script as colon separated fields and their names are the
+    /// names of their directories, so the names are restricted to letters, digits, dashes and
+    /// underscores.
+    pub fn check(&self) -> Result<(), String> {
+        if self.segment_duration == 0 {
+            return Err(String::from("the segment duration must be positive"));
+        }
+
+        if self.renditions.is_empty() {
+            return Err(String::from("there must be at least one rendition"));
+        }
+
+        let mut names = BTreeSet::new();
+
+        for rendition in &self.renditions {
+            let valid_name = !rendition.name.is_empty()
+                && rendition
+                    .name
+                    .chars()
+                    .all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '_');
+
+            if !valid_name {
+                return Err(format!("invalid rendition name {:?}", rendition.name));
+            }
+
+            if !names.insert(&rendition.name) {
+                return Err(format!("duplicate rendition {}", rendition.name));
+            }
+
+            if rendition.height == Some(0) {
+                return Err(format!("rendition {} has a null height", rendition.name));
+            }
+
+            if rendition.height.is_some() && rendition.video_bitrate == 0 {
+                return Err(format!(
+                    "rendition {} has a null video bitrate",
+                    rendition.name
+                ));
+            }
+
+            if rendition.audio_bitrate == 0 {
+                return Err(format!(
+                    "rendition {} has a null audio bitrate",
+                    rendition.name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The publication profiles allowed for each plan.
+///
+/// The first profile of a plan is the one used for the capsules that have not chosen one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanPublicationProfiles {
+    /// The profiles allowed for free users.
+    pub free: Vec<String>,
+
+    /// The profiles allowed for premium users.
+    pub premium_lvl1: Vec<String>,
+
+    /// The profiles allowed for admins.
+    pub admin: Vec<String>,
+}
+
+/// The visibility of the comments of the capsules that have not chosen one, for each privacy.
+///
+/// By default, the comments of private capsules, which are shared with a known audience such as a
+/// class, are read by every viewer, while those of public and unlisted capsules are only read by
+/// their collaborators.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrivacyCommentVisibility {
+    /// The visibility of the comments of public capsules.
+    pub public: CommentVisibility,
+
+    /// The visibility of the comments of unlisted capsules.
+    pub unlisted: CommentVisibility,
+
+    /// The visibility of the comments of private capsules.
+    pub private: CommentVisibility,
+}
+
 /// The config of the server.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -216,6 +551,86 @@ pub struct Config {
     /// Disk quota for admin account
     #[serde(default = "default_quota_disk_admin")]
     pub quota_disk_admin: usize,
+
+    /// Number of failed login attempts allowed before delays are applied.
+    #[serde(default = "default_login_free_attempts")]
+    pub login_free_attempts: u32,
+
+    /// Number of failed login attempts after which the account or ip is locked.
+    #[serde(default = "default_login_max_attempts")]
+    pub login_max_attempts: u32,
+
+    /// Number of failed login attempts from any ip after which the account is locked.
+    #[serde(default = "default_login_account_max_attempts")]
+    pub login_account_max_attempts: u32,
+
+    /// Duration of a lockout, in seconds.
+    #[serde(default = "default_login_lockout_duration")]
+    pub login_lockout_duration: u64,
+
+    /// Whether lockouts should be checked against the database, so that they survive restarts and
+    /// are shared between instances.
+    #[serde(default = "default_login_persist_lockouts")]
+    pub login_persist_lockouts: bool,
+
+    /// Number of password resets that can be requested for an email or from an ip during the
+    /// lockout duration.
+    #[serde(default = "default_reset_max_requests")]
+    pub reset_max_requests: u32,
+
+    /// Number of accounts a user can create by importing rosters during the lockout duration.
+    #[serde(default = "default_roster_max_invitations")]
+    pub roster_max_invitations: u32,
+
+    /// Number of answers to quizzes accepted from an ip address during the lockout duration.
+    #[serde(default = "default_quiz_max_answers")]
+    pub quiz_max_answers: u32,
+
+    /// Number of beacons of the player accepted from an ip address during the lockout duration.
+    #[serde(default = "default_view_max_beacons")]
+    pub view_max_beacons: u32,
+
+    /// Number of notifications of new comments a user can send to the owner of a capsule during
+    /// the lockout duration.
+    #[serde(default = "default_comment_max_notifications")]
+    pub comment_max_notifications: u32,
+
+    /// Secret used to sign the urls of video segments.
+    ///
+    /// It must be shared with the other host and with any file server that validates the urls.
+    pub segment_secret: Option<String>,
+
+    /// Duration of validity of the signed urls of video segments, in seconds.
+    #[serde(default = "default_segment_token_duration")]
+    pub segment_token_duration: u64,
+
+    /// Delays before the deadline of an assignment at which students who have not submitted their
+    /// answer are reminded, in hours.
+    #[serde(default = "default_assignment_reminders")]
+    pub assignment_reminders: Vec<u64>,
+
+    /// The backend in which the files of the capsules are stored.
+    ///
+    /// Whatever the backend, the data path is used as a working copy by the media pipeline.
+    #[serde(default = "default_storage")]
+    pub storage: StorageConfig,
+
+    /// The language of the videos, given to the podcast applications and used for the default
+    /// titles of the chapters and of the slides.
+    #[serde(default = "default_content_language")]
+    pub content_language: String,
+
+    /// The publication profiles, by name.
+    #[serde(default = "default_publication_profiles")]
+    pub publication_profiles: BTreeMap<String, PublicationProfile>,
+
+    /// The publication profiles allowed for each plan.
+    #[serde(default = "default_plan_publication_profiles")]
+    pub plan_publication_profiles: PlanPublicationProfiles,
+
+    /// The default visibility of the comments for each privacy.
+    #[serde(default = "default_privacy_comment_visibility")]
+    pub comment_visibility: PrivacyCommentVisibility,
 }
 
 impl Config {
@@ -236,6 +651,124 @@ impl Config {
             mailer.root = config.root.clone();
         }
 
+        // The configured profiles complete the default ones rather than replacing them.
+        for (name, profile) in default_publication_profiles() {
+            config.publication_profiles.entry(name).or_insert(profile);
+        }
+
+        for (name, profile) in &config.publication_profiles {
+            if let Err(e) = profile.check() {
+                panic!("Invalid publication profile {}: {}", name, e);
+            }
+        }
+
+        for plan in [Plan::Free, Plan::PremiumLvl1, Plan::Admin] {
+            let allowed = config.allowed_publication_profiles(plan);
+
+            if allowed.is_empty() {
+                panic!("No publication profile allowed for plan {:?}", plan);
+            }
+
+            for name in allowed {
+                if !config.publication_profiles.contains_key(name) {
+                    panic!("Unknown publication profile {} for plan {:?}", name, plan);
+                }
+            }
+        }
+
         config
     }
+
+    /// Returns the names of the publication profiles allowed for a plan.
+    pub fn allowed_publication_profiles(&self, plan: Plan) -> &[String] {
+        match plan {
+            Plan::Free => &self.plan_publication_profiles.free,
+            Plan::PremiumLvl1 => &self.plan_publication_profiles.premium_lvl1,
+            Plan::Admin => &self.plan_publication_profiles.admin,
+        }
+    }
+
+    /// Returns the publication profile to use for a capsule whose owner has a given plan.
+    ///
+    /// The profile chosen for the capsule is used if the plan allows it, otherwise the default
+    /// profile of the plan is used, so that a capsule never keeps a profile its owner lost.
+    pub fn publication_profile(
+        &self,
+        plan: Plan,
+        chosen: Option<&str>,
+    ) -> Option<(&str, &PublicationProfile)> {
+        let allowed = self.allowed_publication_profiles(plan);
+
+        let name = chosen
+            .filter(|x| allowed.iter().any(|y| y == x))
+            .or_else(|| allowed.first().map(|x| x.as_str()))?;
+
+        self.publication_profiles
+            .get_key_value(name)
+            .map(|(name, profile)| (name.as_str(), profile))
+    }
+
+    /// Returns the visibility of the comments of a capsule with a given privacy, the one chosen for
+    /// the capsule if any.
+    pub fn comment_visibility(
+        &self,
+        privacy: Privacy,
+        chosen: Option<CommentVisibility>,
+    ) -> CommentVisibility {
+        chosen.unwrap_or(match privacy {
+            Privacy::Public => self.comment_visibility.public,
+            Privacy::Unlisted => self.comment_visibility.unlisted,
+            Privacy::Private => self.comment_visibility.private,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_publication_profiles, PublicationProfile, Rendition, VideoCodec};
+
+    /// Creates a profile with a single video rendition.
+    fn profile(name: &str, height: Option<u32>, video_bitrate: u32) -> PublicationProfile {
+        PublicationProfile {
+            codec: VideoCodec::H264,
+            segment_duration: 6,
+            renditions: vec![Rendition {
+                name: String::from(name),
+                height,
+                video_bitrate,
+                audio_bitrate: 128,
+            }],
+        }
+    }
+
+    #[test]
+    fn default_profiles_are_valid() {
+        for profile in default_publication_profiles().values() {
+            assert_eq!(profile.check(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn invalid_profiles_are_refused() {
+        assert_eq!(profile("720p", Some(720), 2800).check(), Ok(()));
+        assert_eq!(profile("audio", None, 0).check(), Ok(()));
+
+        assert!(profile("", Some(720), 2800).check().is_err());
+        assert!(profile("720p:x", Some(720), 2800).check().is_err());
+        assert!(profile("../720p", Some(720), 2800).check().is_err());
+        assert!(profile("720p", Some(0), 2800).check().is_err());
+        assert!(profile("720p", Some(720), 0).check().is_err());
+
+        let mut duplicate = profile("720p", Some(720), 2800);
+        duplicate.renditions.push(duplicate.renditions[0].clone());
+        assert!(duplicate.check().is_err());
+
+        let mut empty = profile("720p", Some(720), 2800);
+        empty.renditions.clear();
+        assert!(empty.check().is_err());
+
+        let mut segments = profile("720p", Some(720), 2800);
+        segments.segment_duration = 0;
+        assert!(segments.check().is_err());
+    }
 }
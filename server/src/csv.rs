@@ -0,0 +1,131 @@
+//! This module contains helpers to write CSV files.
+
+/// Escapes a field of a CSV file, quoting it if needed.
+///
+/// Fields that a spreadsheet would evaluate as a formula are prefixed with a quote, so that user
+/// provided text such as names cannot inject formulas.
+pub fn escape(field: &str) -> String {
+    let formula =
+        field.starts_with(['=', '+', '-', '@', '\t', '\r']) && field.parse::<f64>().is_err();
+
+    let field = if formula {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+/// Formats a row of a CSV file, including its line ending.
+pub fn row<S: AsRef<str>>(fields: &[S]) -> String {
+    let mut row = fields
+        .iter()
+        .map(|x| escape(x.as_ref()))
+        .collect::<Vec<_>>()
+        .join(",");
+    row.push_str("\r\n");
+    row
+}
+
+/// Parses a CSV file into its rows, skipping the empty lines.
+///
+/// Fields can be separated by commas or semicolons, and quoted fields can contain separators,
+/// escaped quotes and line breaks.
+pub fn parse(input: &str) -> Vec<Vec<String>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = input.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            _ if quoted => field.push(c),
+            ',' | ';' => row.push(std::mem::take(&mut field)),
+            '\r' => (),
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|x| !x.trim().is_empty()) {
+                    rows.push(std::mem::take(&mut row));
+                } else {
+                    row.clear();
+                }
+            }
+            _ => field.push(c),
+        }
+    }
+
+    row.push(field);
+    if row.iter().any(|x| !x.trim().is_empty()) {
+        rows.push(row);
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, parse, row};
+
+    #[test]
+    fn fields_are_quoted_when_needed() {
+        assert_eq!(escape("Alice"), "Alice");
+        assert_eq!(escape("Doe, Alice"), "\"Doe, Alice\"");
+        assert_eq!(escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(escape("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn formulas_are_not_evaluated() {
+        assert_eq!(escape("=SUM(A1:A3)"), "'=SUM(A1:A3)");
+        assert_eq!(escape("+33 6 12 34 56 78"), "'+33 6 12 34 56 78");
+        assert_eq!(escape("@cmd"), "'@cmd");
+        assert_eq!(escape("=1,2"), "\"'=1,2\"");
+
+        // Negative numbers are numbers, not formulas.
+        assert_eq!(escape("-12.5"), "-12.5");
+    }
+
+    #[test]
+    fn rows_end_with_crlf() {
+        assert_eq!(row(&["a", "b,c", "=1+1"]), "a,\"b,c\",'=1+1\r\n");
+        assert_eq!(row::<&str>(&[]), "\r\n");
+    }
+
+    #[test]
+    fn parse_reads_what_row_writes() {
+        let rows = vec![
+            vec!["Name", "Email", "Grade"],
+            vec!["Doe, Alice", "alice@example.com", "12.5"],
+            vec!["Bob \"The\" Builder", "bob@example.com", "multi\nline"],
+        ];
+
+        let csv = rows.iter().map(|x| row(x)).collect::<String>();
+        assert_eq!(parse(&csv), rows);
+    }
+
+    #[test]
+    fn parse_accepts_spreadsheet_exports() {
+        let input = "\u{feff}name;email\r\n\r\n;\nAlice;alice@example.com\nBob,\"bob@example.com\"";
+
+        assert_eq!(
+            parse(input),
+            vec![
+                vec!["name", "email"],
+                vec!["Alice", "alice@example.com"],
+                vec!["Bob", "bob@example.com"],
+            ]
+        );
+    }
+}
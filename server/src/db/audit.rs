@@ -0,0 +1,312 @@
+//! This module contains the audit log, that records security and data relevant actions.
+//!
+//! The audit log is append only: entries are never modified nor deleted, and they reference
+//! capsules, users and groups by id rather than by foreign key so that they survive the deletion
+//! of their targets.
+
+use chrono::{NaiveDateTime, Utc};
+
+use ergol::prelude::*;
+use ergol::tokio_postgres::types::Json;
+
+use serde::{Deserialize, Serialize};
+
+use rocket::serde::json::{json, Value};
+
+use crate::db::capsule::Capsule;
+use crate::db::user::User;
+use crate::{Db, Result, HARSH};
+
+/// The number of entries in a page of the audit log.
+const PAGE_SIZE: usize = 50;
+
+/// The different actions that are recorded in the audit log.
+#[derive(Debug, Copy, Clone, PgEnum, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// A capsule was deleted.
+    CapsuleDeleted,
+
+    /// The privacy of a capsule was changed.
+    PrivacyChanged,
+
+    /// A capsule was published.
+    CapsulePublished,
+
+    /// A capsule was unpublished.
+    CapsuleUnpublished,
+
+    /// A user was invited to collaborate on a capsule.
+    CollaboratorAdded,
+
+    /// The role of a collaborator was changed.
+    RoleChanged,
+
+    /// A collaborator was removed from a capsule, or left it.
+    CollaboratorRemoved,
+
+    /// A share token was created for a capsule.
+    ShareTokenCreated,
+
+    /// A share token was revoked.
+    ShareTokenRevoked,
+
+    /// A group was created.
+    GroupCreated,
+
+    /// A group was deleted.
+    GroupDeleted,
+
+    /// A participant was added to a group.
+    ParticipantAdded,
+
+    /// A participant was removed from a group.
+    ParticipantRemoved,
+
+    /// A code to join a group was created.
+    GroupInviteCreated,
+
+    /// A code to join a group was revoked.
+    GroupInviteRevoked,
+
+    /// An assignment was created.
+    AssignmentCreated,
+
+    /// An assignment was deleted.
+    AssignmentDeleted,
+
+    /// The state of an assignment was changed.
+    AssignmentStateChanged,
+
+    /// A student submitted their answer.
+    AnswerValidated,
+
+    /// A teacher evaluated an answer.
+    AnswerEvaluated,
+
+    /// A user deleted their account.
+    UserDeleted,
+
+    /// An administrator deleted a user.
+    UserDeletedByAdmin,
+
+    /// An administrator invited a user.
+    UserInvited,
+
+    /// A user changed their password.
+    PasswordChanged,
+
+    /// A user requested to change their email address.
+    EmailChangeRequested,
+
+    /// An account or an ip address was locked after too many failed login attempts.
+    LoginLockout,
+}
+
+/// The targets of an audited action.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Target {
+    /// The id of the capsule targeted by the action, if any.
+    pub capsule: Option<i32>,
+
+    /// The id of the user targeted by the action, if any.
+    pub user: Option<i32>,
+
+    /// The id of the group targeted by the action, if any.
+    pub group: Option<i32>,
+}
+
+impl Target {
+    /// Creates a target that consists of a capsule.
+    pub fn capsule(id: i32) -> Target {
+        Target {
+            capsule: Some(id),
+            ..Target::default()
+        }
+    }
+
+    /// Creates a target that consists of a user.
+    pub fn user(id: i32) -> Target {
+        Target {
+            user: Some(id),
+            ..Target::default()
+        }
+    }
+
+    /// Creates a target that consists of a group.
+    pub fn group(id: i32) -> Target {
+        Target {
+            group: Some(id),
+            ..Target::default()
+        }
+    }
+
+    /// Adds a user to the target.
+    pub fn with_user(self, id: i32) -> Target {
+        Target {
+            user: Some(id),
+            ..self
+        }
+    }
+}
+
+/// An entry of the audit log.
+#[ergol]
+pub struct AuditLog {
+    /// The id of the entry.
+    #[id]
+    pub id: i32,
+
+    /// The moment the action was performed.
+    pub date: NaiveDateTime,
+
+    /// The id of the user that performed the action, if any.
+    pub actor_id: Option<i32>,
+
+    /// The username of the user that performed the action, at the time of the action.
+    pub actor: Option<String>,
+
+    /// The action that was performed.
+    pub action: AuditAction,
+
+    /// The id of the capsule targeted by the action, if any.
+    pub target_capsule: Option<i32>,
+
+    /// The id of the user targeted by the action, if any.
+    pub target_user: Option<i32>,
+
+    /// The id of the group targeted by the action, if any.
+    pub target_group: Option<i32>,
+
+    /// A summary of the target before the action.
+    pub before: Option<Json<Value>>,
+
+    /// A summary of the target after the action.
+    pub after: Option<Json<Value>>,
+}
+
+/// The filters that can be applied when searching the audit log.
+#[derive(Debug, Default, Clone)]
+pub struct AuditFilter {
+    /// Only keeps the entries performed by this user.
+    pub actor: Option<i32>,
+
+    /// Only keeps the entries that target this capsule.
+    pub capsule: Option<i32>,
+
+    /// Only keeps the entries that target this user.
+    pub user: Option<i32>,
+
+    /// Only keeps the entries that target this group.
+    pub group: Option<i32>,
+
+    /// Only keeps the entries that happened after this date.
+    pub from: Option<NaiveDateTime>,
+
+    /// Only keeps the entries that happened before this date.
+    pub to: Option<NaiveDateTime>,
+}
+
+impl AuditLog {
+    /// Appends an entry to the audit log.
+    pub async fn record(
+        actor: Option<&User>,
+        action: AuditAction,
+        target: Target,
+        before: Option<Value>,
+        after: Option<Value>,
+        db: &Db,
+    ) -> Result<()> {
+        AuditLog::create(
+            Utc::now().naive_utc(),
+            actor.map(|x| x.id),
+            actor.map(|x| x.username.clone()),
+            action,
+            target.capsule,
+            target.user,
+            target.group,
+            before.map(Json),
+            after.map(Json),
+        )
+        .save(&db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns a page of the entries that match the filter, most recent first.
+    pub async fn search(filter: &AuditFilter, page: i32, db: &Db) -> Result<Vec<AuditLog>> {
+        let mut filters = vec![];
+
+        if let Some(actor) = filter.actor {
+            filters.push(audit_log::actor_id::eq(Some(actor)));
+        }
+
+        if let Some(capsule) = filter.capsule {
+            filters.push(audit_log::target_capsule::eq(Some(capsule)));
+        }
+
+        if let Some(user) = filter.user {
+            filters.push(audit_log::target_user::eq(Some(user)));
+        }
+
+        if let Some(group) = filter.group {
+            filters.push(audit_log::target_group::eq(Some(group)));
+        }
+
+        if let Some(from) = filter.from {
+            filters.push(audit_log::date::geq(from));
+        }
+
+        if let Some(to) = filter.to {
+            filters.push(audit_log::date::leq(to));
+        }
+
+        let query = match filters.into_iter().reduce(|acc, x| acc.and(x)) {
+            Some(filter) => AuditLog::select().filter(filter),
+            None => AuditLog::select(),
+        };
+
+        Ok(query
+            .order_by(audit_log::id::descend())
+            .limit(PAGE_SIZE)
+            .offset((page.max(0) as usize) * PAGE_SIZE)
+            .execute(&db)
+            .await?)
+    }
+
+    /// Returns a json representation of the entry.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "date": self.date.timestamp(),
+            "actor_id": self.actor_id,
+            "actor": self.actor,
+            "action": self.action,
+            "target_capsule": self.target_capsule.map(|x| HARSH.encode(x)),
+            "target_user": self.target_user,
+            "target_group": self.target_group,
+            "before": self.before.as_ref().map(|x| &x.0),
+            "after": self.after.as_ref().map(|x| &x.0),
+        })
+    }
+}
+
+/// Returns a summary of a capsule to store in the audit log.
+pub async fn capsule_summary(capsule: &Capsule, db: &Db) -> Result<Value> {
+    Ok(json!({
+        "name": capsule.name,
+        "project": capsule.project(&db).await?.name,
+        "privacy": capsule.privacy,
+        "published": capsule.published,
+    }))
+}
+
+/// Returns a summary of a user to store in the audit log.
+pub fn user_summary(user: &User) -> Value {
+    json!({
+        "username": user.username,
+        "email": user.email,
+        "plan": user.plan,
+    })
+}
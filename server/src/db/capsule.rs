@@ -15,8 +15,12 @@ use tungstenite::Message;
 use rocket::http::Status;
 use rocket::serde::json::{json, Value};
 
+use crate::config::Config;
+use crate::db::comment::CommentVisibility;
+use crate::db::project::Project;
 use crate::db::task_status::TaskStatus;
 use crate::db::user::User;
+use crate::storage;
 use crate::websockets::WebSockets;
 use crate::{Db, Error, Result, HARSH};
 
@@ -210,6 +214,37 @@ pub struct Event {
     pub extra_time: Option<i32>,
 }
 
+/// A multiple choice question asked to the viewers during the video.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quiz {
+    /// The id of the quiz, that identifies its answers.
+    pub id: Uuid,
+
+    /// The question.
+    pub question: String,
+
+    /// The options among which the viewer chooses.
+    pub options: Vec<String>,
+
+    /// The index of the correct option.
+    pub answer: usize,
+
+    /// The moment the video pauses to ask the question, in ms from the start of the gos.
+    pub time: i32,
+}
+
+impl Quiz {
+    /// Returns true if the quiz can be asked: it has a question, at least two options and its
+    /// answer is one of them.
+    pub fn is_valid(&self) -> bool {
+        !self.question.trim().is_empty()
+            && self.options.len() >= 2
+            && self.options.iter().all(|x| !x.trim().is_empty())
+            && self.answer < self.options.len()
+            && self.time >= 0
+    }
+}
+
 /// Options for audio/video fade in and fade out
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Fade {
@@ -262,6 +297,10 @@ pub struct Gos {
     /// Video/audio fade options
     #[serde(default)]
     pub fade: Fade,
+
+    /// The quizzes asked during the gos.
+    #[serde(default)]
+    pub quizzes: Vec<Quiz>,
 }
 
 impl Gos {
@@ -273,8 +312,92 @@ impl Gos {
             events: vec![],
             webcam_settings: None,
             fade: Fade::none(),
+            quizzes: vec![],
         }
     }
+
+    /// Returns the moments, in ms relative to the start of the gos, at which each slide is shown.
+    ///
+    /// Each item is the index of the slide in the gos with its start and end. A slide can appear
+    /// several times if the author went back to it during the record.
+    pub fn slide_spans(&self) -> Vec<(usize, i32, i32)> {
+        self.slide_spans_with(|_| SLIDE_DEFAULT_DURATION_MS)
+    }
+
+    /// Returns the moments at which each slide is shown, like `slide_spans`, with the duration of
+    /// the extra videos of the slides given by a function.
+    pub fn slide_spans_with<F: FnMut(Uuid) -> i32>(
+        &self,
+        mut extra_duration: F,
+    ) -> Vec<(usize, i32, i32)> {
+        // Gos without record show each slide for a fixed duration, or for the duration of its
+        // extra video, as done by the production script.
+        if self.record.is_none() {
+            let mut start = 0;
+
+            return self
+                .slides
+                .iter()
+                .enumerate()
+                .map(|(i, slide)| {
+                    let duration = match slide.extra {
+                        Some(extra) => extra_duration(extra),
+                        None => SLIDE_DEFAULT_DURATION_MS,
+                    };
+
+                    start += duration;
+                    (i, start - duration, start)
+                })
+                .collect();
+        }
+
+        let mut spans = vec![];
+        let mut current = 0;
+        let mut start = 0;
+
+        for event in &self.events {
+            match event.ty {
+                EventType::NextSlide | EventType::PreviousSlide => {
+                    spans.push((current, start, event.time));
+                    start = event.time;
+
+                    current = match event.ty {
+                        EventType::NextSlide => current + 1,
+                        _ => current.saturating_sub(1),
+                    };
+                }
+                EventType::End => {
+                    spans.push((current, start, event.time));
+                    return spans;
+                }
+                _ => (),
+            }
+        }
+
+        // Robustness in case of a missing end event.
+        let end = self.events.last().map(|x| x.time).unwrap_or(start);
+        spans.push((current, start, end.max(start)));
+        spans
+    }
+}
+
+/// The duration during which a slide is shown when its gos has no record.
+pub const SLIDE_DEFAULT_DURATION_MS: i32 = 3000;
+
+/// A slide in the timeline of a produced video.
+#[derive(Debug, Clone, Serialize)]
+pub struct SlideSpan {
+    /// The index of the gos of the slide.
+    pub gos: usize,
+
+    /// The index of the slide in its gos.
+    pub slide: usize,
+
+    /// The moment the slide appears in the video, in ms.
+    pub start: i32,
+
+    /// The moment the slide disappears from the video, in ms.
+    pub end: i32,
 }
 
 /// Privacy settings for a video.
@@ -299,8 +422,9 @@ pub struct Capsule {
     #[id]
     pub id: i32,
 
-    /// The project name.
-    pub project: String,
+    /// The project of the capsule.
+    #[many_to_one(capsules)]
+    pub project: Project,
 
     /// The name of the capsule.
     pub name: String,
@@ -344,27 +468,48 @@ pub struct Capsule {
     /// duration of produced video in ms
     pub duration_ms: i32,
 
+    /// Whether the poster, the preview and the thumbnails track of the produced video have been
+    /// generated.
+    pub thumbnails_generated: bool,
+
+    /// The indices of the gos whose thumbnail has been generated.
+    pub gos_thumbnails: Json<Vec<i32>>,
+
     /// The sound track of the capsule.
     pub sound_track: Option<Json<SoundTrack>>,
 
+    /// The origins of the websites allowed to embed the video, any website can embed it if empty.
+    pub embed_origins: Json<Vec<String>>,
+
+    /// The publication profile chosen for the capsule, the default one of the plan of its owner if
+    /// none.
+    pub publication_profile: Option<String>,
+
+    /// Whether the viewers of the video can download it.
+    pub allow_download: bool,
+
+    /// Who can read the comments of the video, the default of its privacy if none.
+    pub comment_visibility: Option<CommentVisibility>,
+
     /// The user that has rights on the capsule.
     #[many_to_many(capsules, Role)]
     pub users: User,
 }
 
 impl Capsule {
-    /// Creates a new capsule.
-    pub async fn new<P: Into<String>, Q: Into<String>>(
+    /// Creates a new capsule in the project of the owner with a given name, which is created if
+    /// needed.
+    pub async fn new<P: AsRef<str>, Q: Into<String>>(
         project: P,
         name: Q,
         owner: &User,
         db: &Db,
     ) -> Result<Capsule> {
-        let project = project.into();
+        let project = Project::find_or_create(project.as_ref(), owner, db).await?;
         let name = name.into();
 
         let capsule = Capsule::create(
-            project,
+            &project,
             name,
             TaskStatus::Idle,
             None,
@@ -379,6 +524,12 @@ impl Capsule {
             Utc::now().naive_utc(),
             0,
             0,
+            false,
+            Json(vec![]),
+            None,
+            Json(vec![]),
+            None,
+            false,
             None,
         )
         .save(&db)
@@ -389,6 +540,114 @@ impl Capsule {
         Ok(capsule)
     }
 
+    /// Creates a copy of the capsule owned by another user, sharing its files.
+    ///
+    /// A capsule cannot be copied while it is being produced, since the production writes its
+    /// files in place.
+    pub async fn duplicate<Q: Into<String>>(
+        &self,
+        name: Q,
+        owner: &User,
+        config: &Config,
+        db: &Db,
+    ) -> Result<Capsule> {
+        if self.produced == TaskStatus::Running {
+            return Err(Error(Status::Conflict));
+        }
+
+        let project = self.project(db).await?;
+        let mut new = Capsule::new(&project.name, name, owner, db).await?;
+
+        new.privacy = self.privacy.clone();
+        new.produced = self.produced;
+        new.structure = self.structure.clone();
+        new.webcam_settings = self.webcam_settings.clone();
+        new.sound_track = self.sound_track.clone();
+        new.duration_ms = self.duration_ms;
+        new.thumbnails_generated = self.thumbnails_generated;
+        new.gos_thumbnails = self.gos_thumbnails.clone();
+        new.publication_profile = self.publication_profile.clone();
+        new.allow_download = self.allow_download;
+        new.comment_visibility = self.comment_visibility;
+
+        storage::open(config)?
+            .copy_dir(&format!("{}", self.id), &format!("{}", new.id))
+            .await?;
+
+        new.set_changed();
+        new.save(&db).await?;
+
+        Ok(new)
+    }
+
+    /// Returns the value of the Content-Security-Policy header that restricts which websites can
+    /// embed the video, or None if any website can embed it.
+    pub fn frame_ancestors(&self) -> Option<String> {
+        if self.embed_origins.0.is_empty() {
+            None
+        } else {
+            Some(format!(
+                "frame-ancestors 'self' {}",
+                self.embed_origins.0.join(" ")
+            ))
+        }
+    }
+
+    /// Returns the timeline of the slides of the produced video.
+    ///
+    /// Durations of extra videos are unknown to the server, so slides of gos without record are
+    /// assumed to last the default duration.
+    pub fn slide_timeline(&self) -> Vec<SlideSpan> {
+        self.slide_timeline_with(|_| SLIDE_DEFAULT_DURATION_MS)
+    }
+
+    /// Returns the timeline of the slides of the produced video, with the duration of the extra
+    /// videos of the slides given by a function.
+    pub fn slide_timeline_with<F: FnMut(Uuid) -> i32>(
+        &self,
+        mut extra_duration: F,
+    ) -> Vec<SlideSpan> {
+        let mut timeline = vec![];
+        let mut offset = 0;
+
+        for (gos_index, gos) in self.structure.0.iter().enumerate() {
+            let spans = gos.slide_spans_with(&mut extra_duration);
+            let mut gos_end = 0;
+
+            for (slide, start, end) in spans {
+                timeline.push(SlideSpan {
+                    gos: gos_index,
+                    slide,
+                    start: offset + start,
+                    end: offset + end,
+                });
+                gos_end = gos_end.max(end);
+            }
+
+            offset += gos_end;
+        }
+
+        timeline
+    }
+
+    /// Returns the quizzes of the capsule with the index of their gos.
+    pub fn quizzes(&self) -> Vec<(usize, &Quiz)> {
+        self.structure
+            .0
+            .iter()
+            .enumerate()
+            .flat_map(|(index, gos)| gos.quizzes.iter().map(move |quiz| (index, quiz)))
+            .collect()
+    }
+
+    /// Returns the quiz of the capsule with an id.
+    pub fn quiz(&self, id: Uuid) -> Option<&Quiz> {
+        self.quizzes()
+            .into_iter()
+            .map(|(_, quiz)| quiz)
+            .find(|quiz| quiz.id == id)
+    }
+
     /// Sets the last modified to now.
     pub fn set_changed(&mut self) {
         self.last_modified = Utc::now().naive_utc();
@@ -396,28 +655,88 @@ impl Capsule {
 
     /// Returns a json representation of the capsule.
     pub async fn to_json(&self, role: Role, db: &Db) -> Result<Value> {
-        let users = self
-            .users(&db)
-            .await?
-            .into_iter()
-            .map(|(x, role)| {
+        let project = self.project(&db).await?;
+
+        // The users of an answer are hidden from the students that can only read it, so that they
+        // do not learn who wrote it or who reviews it.
+        let users = if role < Role::Write && !self.subject_answers(&db).await?.is_empty() {
+            vec![]
+        } else {
+            self.users(&db)
+                .await?
+                .into_iter()
+                .map(|(x, role)| {
+                    json!({
+                        "username": x.username,
+                        "role": role,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Share tokens are secrets, only the collaborators that can edit the capsule see them.
+        let share_tokens = if role >= Role::Write {
+            self.share_tokens(&db)
+                .await?
+                .iter()
+                .map(|x| x.to_json())
+                .collect::<Vec<_>>()
+        } else {
+            vec![]
+        };
+
+        // The thumbnails are generated with the production, the gos ones may be missing if the
+        // capsule has been produced gos by gos, and the others if it was produced before they
+        // existed. The gos that have no thumbnail get a null one.
+        let hash = HARSH.encode(self.id);
+        let thumbnails = if self.produced == TaskStatus::Done {
+            let gos = (0..self.structure.0.len() as i32)
+                .map(|i| {
+                    if self.gos_thumbnails.0.contains(&i) {
+                        json!(format!("/data/{}/tmp/gos_{}.jpg", hash, i))
+                    } else {
+                        Value::Null
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            if self.thumbnails_generated {
                 json!({
-                    "username": x.username,
-                    "role": role,
+                    "poster": format!("/data/{}/tmp/poster.jpg", hash),
+                    "preview": format!("/data/{}/tmp/preview.jpg", hash),
+                    "preview_track": format!("/data/{}/tmp/thumbnails.vtt", hash),
+                    "gos": gos,
                 })
-            })
-            .collect::<Vec<_>>();
+            } else {
+                json!({ "gos": gos })
+            }
+        } else {
+            Value::Null
+        };
+
+        // The students that read a subject must not find the answers of its quizzes.
+        let mut structure = json!(self.structure.0);
+        if role < Role::Write {
+            for gos in structure.as_array_mut().into_iter().flatten() {
+                for quiz in gos["quizzes"].as_array_mut().into_iter().flatten() {
+                    if let Some(quiz) = quiz.as_object_mut() {
+                        quiz.remove("answer");
+                    }
+                }
+            }
+        }
 
         Ok(json!({
-            "id": HARSH.encode(self.id),
+            "id": hash,
             "name": self.name,
-            "project": self.project,
+            "project": project.name,
+            "project_id": HARSH.encode(project.id),
             "role": role,
             "video_uploaded": self.video_uploaded,
             "produced": self.produced,
             "published": self.published,
             "privacy": self.privacy,
-            "structure": self.structure.0,
+            "structure": structure,
             "webcam_settings": self.webcam_settings.0,
             "last_modified": self.last_modified.timestamp(),
             "users": users,
@@ -425,6 +744,12 @@ impl Capsule {
             "disk_usage": self.disk_usage,
             "duration_ms": self.duration_ms,
             "sound_track": self.sound_track.as_ref().map(|x| &x.0),
+            "embed_origins": self.embed_origins.0,
+            "publication_profile": self.publication_profile,
+            "allow_download": self.allow_download,
+            "comment_visibility": self.comment_visibility,
+            "share_tokens": share_tokens,
+            "thumbnails": thumbnails,
         }))
     }
 
@@ -0,0 +1,111 @@
+//! This module contains the timed comments and questions of the viewers of published capsules.
+//!
+//! A comment is attached to a moment of the video, and the replies to a comment form its thread.
+//! Who can read the comments of a capsule depends on its comment visibility, which defaults to the
+//! one configured for its privacy.
+
+use chrono::{NaiveDateTime, Utc};
+
+use ergol::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+use rocket::serde::json::{json, Value};
+
+use crate::db::capsule::Capsule;
+use crate::db::user::User;
+use crate::{Db, Result};
+
+/// The maximum length of a comment, in characters.
+pub const COMMENT_MAX_LENGTH: usize = 2000;
+
+/// Who can read the comments of a capsule.
+#[derive(PgEnum, Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentVisibility {
+    /// Comments are disabled.
+    Disabled,
+
+    /// Comments are only read by the collaborators that can edit the capsule, each viewer only sees
+    /// their own threads and the replies to them.
+    Collaborators,
+
+    /// Comments are read by every viewer of the video.
+    Viewers,
+}
+
+/// A comment on a moment of a published capsule.
+#[ergol]
+pub struct Comment {
+    /// The id of the comment.
+    #[id]
+    pub id: i32,
+
+    /// The capsule that is commented.
+    #[many_to_one(comments)]
+    pub capsule: Capsule,
+
+    /// The author of the comment.
+    #[many_to_one(comments)]
+    pub author: User,
+
+    /// The id of the comment this comment replies to, if any.
+    ///
+    /// Threads have a single level: the replies to a reply belong to the thread of its parent.
+    pub parent: Option<i32>,
+
+    /// The moment of the video that is commented, in ms.
+    pub time: i32,
+
+    /// The content of the comment.
+    pub content: String,
+
+    /// Whether the comment was hidden by a moderator.
+    pub hidden: bool,
+
+    /// The moment the comment was posted.
+    pub created: NaiveDateTime,
+}
+
+impl Comment {
+    /// Creates and saves a new comment.
+    pub async fn new(
+        capsule: &Capsule,
+        author: &User,
+        parent: Option<&Comment>,
+        time: i32,
+        content: String,
+        db: &Db,
+    ) -> Result<Comment> {
+        // A reply is attached to the root of the thread, at the moment of the thread.
+        let (parent, time) = match parent {
+            Some(parent) => (Some(parent.parent.unwrap_or(parent.id)), parent.time),
+            None => (None, time),
+        };
+
+        Ok(Comment::create(
+            capsule,
+            author,
+            parent,
+            time,
+            content,
+            false,
+            Utc::now().naive_utc(),
+        )
+        .save(&db)
+        .await?)
+    }
+
+    /// Returns a json representation of the comment, without its replies.
+    pub fn to_json(&self, author: &User) -> Value {
+        json!({
+            "id": self.id,
+            "author": author.username,
+            "parent": self.parent,
+            "time": self.time as f64 / 1000.0,
+            "content": self.content,
+            "hidden": self.hidden,
+            "created": self.created.timestamp(),
+        })
+    }
+}
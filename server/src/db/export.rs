@@ -0,0 +1,180 @@
+//! This module contains the exports that let the videos be downloaded and watched offline.
+
+use chrono::{NaiveDateTime, Utc};
+
+use ergol::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+use tokio::fs::remove_dir_all;
+
+use rocket::serde::json::{json, Value};
+
+use crate::config::Config;
+use crate::db::capsule::Capsule;
+use crate::db::task_status::TaskStatus;
+use crate::storage::{self, capsule_key};
+use crate::{Db, Result};
+
+/// The formats in which a capsule can be exported.
+#[derive(PgEnum, Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// A compressed MP4 video.
+    Mp4,
+
+    /// An MP3 audio file.
+    Mp3,
+
+    /// An M4A audio file.
+    M4a,
+
+    /// A SCORM package with the video and its subtitles, to import in a learning management system.
+    Scorm,
+}
+
+impl ExportFormat {
+    /// Returns the name of the format given to the export script.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Mp4 => "mp4",
+            ExportFormat::Mp3 => "mp3",
+            ExportFormat::M4a => "m4a",
+            ExportFormat::Scorm => "scorm",
+        }
+    }
+
+    /// Returns the extension of the files of the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Scorm => "zip",
+            x => x.name(),
+        }
+    }
+
+    /// Returns true if the format contains a video, whose height can be chosen.
+    pub fn has_video(self) -> bool {
+        matches!(self, ExportFormat::Mp4 | ExportFormat::Scorm)
+    }
+}
+
+/// An export of the video of a capsule, generated on demand.
+#[ergol]
+pub struct Export {
+    /// The id of the export.
+    #[id]
+    pub id: i32,
+
+    /// The capsule that is exported.
+    #[many_to_one(exports)]
+    pub capsule: Capsule,
+
+    /// The format of the export.
+    pub format: ExportFormat,
+
+    /// The height of the video of the export, none for audio formats.
+    pub height: Option<i32>,
+
+    /// The status of the generation of the export.
+    pub status: TaskStatus,
+
+    /// The pid of the generation of the export, if it is running.
+    pub pid: Option<i32>,
+
+    /// The moment the export was requested.
+    pub created: NaiveDateTime,
+}
+
+impl Export {
+    /// Creates and saves a new export of a capsule.
+    pub async fn new(
+        capsule: &Capsule,
+        format: ExportFormat,
+        height: Option<i32>,
+        db: &Db,
+    ) -> Result<Export> {
+        Ok(Export::create(
+            capsule,
+            format,
+            height,
+            TaskStatus::Waiting,
+            None,
+            Utc::now().naive_utc(),
+        )
+        .save(&db)
+        .await?)
+    }
+
+    /// Marks as failed the exports whose generation was interrupted by a restart of the server, so
+    /// that they are generated again the next time they are requested.
+    pub async fn fail_interrupted(db: &Db) -> Result<()> {
+        let exports = Export::select()
+            .filter(
+                export::status::eq(TaskStatus::Waiting).or(export::status::eq(TaskStatus::Running)),
+            )
+            .execute(&db)
+            .await?;
+
+        for mut export in exports {
+            export.status = TaskStatus::Failed;
+            export.pid = None;
+            export.save(&db).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the exports of a capsule, when its video changes.
+    pub async fn clear(capsule: &Capsule, config: &Config, db: &Db) -> Result<()> {
+        for export in capsule.exports(&db).await? {
+            export.delete(&db).await?;
+        }
+
+        storage::open(config)?
+            .delete_dir(&capsule_key(capsule.id, "exports")?)
+            .await?;
+
+        let path = config
+            .data_path
+            .join(format!("{}", capsule.id))
+            .join("exports");
+
+        remove_dir_all(path).await.ok();
+
+        Ok(())
+    }
+
+    /// Returns the path of the file of the export, relative to the directory of the capsule.
+    pub fn path(&self) -> String {
+        format!("exports/{}.{}", self.id, self.format.extension())
+    }
+
+    /// Returns the name under which the file of the export is downloaded.
+    pub fn file_name(&self, capsule: &Capsule) -> String {
+        let mut name = capsule
+            .name
+            .chars()
+            .map(|x| if x.is_ascii_alphanumeric() { x } else { '_' })
+            .collect::<String>();
+
+        if name.trim_matches('_').is_empty() {
+            name = String::from("capsule");
+        }
+
+        match self.height {
+            Some(height) => format!("{}_{}p.{}", name, height, self.format.extension()),
+            None => format!("{}.{}", name, self.format.extension()),
+        }
+    }
+
+    /// Returns a json representation of the export.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "format": self.format,
+            "height": self.height,
+            "status": self.status,
+            "created": self.created.timestamp(),
+        })
+    }
+}
@@ -1,7 +1,16 @@
 //! This module helps us deal with students groups.
 
+use std::collections::HashMap;
+
 use futures::future::try_join_all;
 
+use chrono::{Duration, NaiveDateTime, Utc};
+
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
 use serde::{Deserialize, Serialize};
 
 use ergol::prelude::*;
@@ -14,8 +23,11 @@ use tokio::fs::create_dir_all;
 
 use crate::command::export_slides;
 use crate::config::Config;
+use crate::db::audit::{AuditAction, AuditLog, Target};
 use crate::db::capsule::{Capsule, Fade, Gos, Role, Slide};
 use crate::db::user::User;
+use crate::db::view::WatchProgress;
+use crate::websockets::WebSockets;
 use crate::{Db, Error, Result, HARSH};
 
 /// The different levels of authorization a user can have.
@@ -45,8 +57,19 @@ pub struct Group {
 }
 
 impl Group {
+    /// Returns true if the user is a teacher of the group.
+    pub async fn is_teacher(&self, user: &User, db: &Db) -> Result<bool> {
+        Ok(self
+            .participants(&db)
+            .await?
+            .iter()
+            .any(|(p, role)| p.id == user.id && *role == ParticipantRole::Teacher))
+    }
+
     /// Serializes the group with its participants.
-    pub async fn to_json(&self, db: &Db) -> Result<Value> {
+    ///
+    /// The watch progress of the students is only given to the teachers of the group.
+    pub async fn to_json(&self, user: &User, db: &Db) -> Result<Value> {
         let participants = self.participants(db).await?;
         let participants = participants
             .into_iter()
@@ -62,16 +85,99 @@ impl Group {
         let assignments = self.assignments(db).await?;
         let assignments = assignments
             .iter()
-            .map(|assignment| assignment.to_json(db))
+            .map(|assignment| assignment.to_json(user, db))
             .collect::<Vec<_>>();
         let assignments = try_join_all(assignments).await?;
 
-        Ok(json!({
+        let mut json = json!({
             "id": self.id,
             "name": self.name,
             "participants": participants,
             "assignments": assignments
-        }))
+        });
+
+        // Only the teachers can see the codes to join the group.
+        if self.is_teacher(user, db).await? {
+            json["invites"] = Value::Array(
+                self.invites(db)
+                    .await?
+                    .iter()
+                    .filter(|x| x.is_valid())
+                    .map(GroupInvite::to_json)
+                    .collect(),
+            );
+        }
+
+        Ok(json)
+    }
+}
+
+/// The length of the codes of group invites.
+const INVITE_CODE_LENGTH: usize = 10;
+
+/// A code that lets users join a group by themselves.
+#[ergol]
+pub struct GroupInvite {
+    /// The id of the invite.
+    #[id]
+    pub id: i32,
+
+    /// The code that users must give to join the group.
+    #[unique]
+    pub code: String,
+
+    /// The group that can be joined with the code.
+    #[many_to_one(invites)]
+    pub group: Group,
+
+    /// The role given to the users joining the group.
+    pub role: ParticipantRole,
+
+    /// The moment the invite was created.
+    pub created: NaiveDateTime,
+
+    /// The moment after which the invite can no longer be used, if any.
+    pub expires: Option<NaiveDateTime>,
+}
+
+impl GroupInvite {
+    /// Creates and saves a new invite for a group.
+    pub async fn new(
+        group: &Group,
+        role: ParticipantRole,
+        expires: Option<NaiveDateTime>,
+        db: &Db,
+    ) -> Result<GroupInvite> {
+        let rng = OsRng {};
+        let code = rng
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(INVITE_CODE_LENGTH)
+            .collect::<String>();
+
+        Ok(
+            GroupInvite::create(code, group, role, Utc::now().naive_utc(), expires)
+                .save(&db)
+                .await?,
+        )
+    }
+
+    /// Returns true if the invite has not expired.
+    pub fn is_valid(&self) -> bool {
+        self.expires
+            .map(|x| x > Utc::now().naive_utc())
+            .unwrap_or(true)
+    }
+
+    /// Serializes the invite.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "code": self.code,
+            "role": self.role,
+            "created": self.created.timestamp(),
+            "expires": self.expires.map(|x| x.timestamp()),
+        })
     }
 }
 
@@ -95,6 +201,22 @@ pub enum AssignmentState {
     Finished,
 }
 
+impl AssignmentState {
+    /// Returns true if an assignment in this state can move to the next state.
+    pub fn can_transition_to(self, next: AssignmentState) -> bool {
+        use AssignmentState::*;
+
+        matches!(
+            (self, next),
+            (Preparation, Prepared)
+                | (Preparation, Working)
+                | (Prepared, Working)
+                | (Working, Evaluation)
+                | (Evaluation, Finished)
+        )
+    }
+}
+
 /// An assignment that a teacher will give to a group of students.
 #[ergol]
 pub struct Assignment {
@@ -102,9 +224,6 @@ pub struct Assignment {
     #[id]
     pub id: i32,
 
-    /// The criteria for the evaluation of the assignment, seperated by new lines.
-    pub criteria: String,
-
     /// The subject that contains the subject of the assignment.
     #[many_to_one(assignments)]
     pub subject: Capsule,
@@ -119,34 +238,507 @@ pub struct Assignment {
 
     /// The state of the assignment.
     pub state: AssignmentState,
+
+    /// The number of students that review each answer, peer review is disabled if zero.
+    pub peer_reviews: i32,
+
+    /// Whether students reviewing each other are anonymous to each other.
+    pub anonymous_reviews: bool,
+
+    /// The moment after which peer reviews can no longer be submitted, if any.
+    pub review_deadline: Option<NaiveDateTime>,
+
+    /// The moment the assignment is automatically started, if any.
+    pub opens: Option<NaiveDateTime>,
+
+    /// The moment the answers are due, answers submitted later are flagged as late.
+    pub due: Option<NaiveDateTime>,
+
+    /// The moment the assignment is automatically closed and its evaluation opened, if any.
+    pub closes: Option<NaiveDateTime>,
 }
 
 impl Assignment {
-    /// Creates a new criterion and adds it to the assignment.
-    pub async fn add_criterion(&mut self, criterion: &str, db: &Db) -> Result<()> {
-        self.criteria += &format!(
-            "{}{}",
-            if self.criteria.is_empty() { "" } else { "\n" },
-            criterion
-        );
+    /// Creates a new criterion and adds it at the end of the rubric of the assignment.
+    pub async fn add_criterion(
+        &self,
+        label: &str,
+        description: &str,
+        max_points: f64,
+        weight: f64,
+        db: &Db,
+    ) -> Result<Criterion> {
+        if label.trim().is_empty() || !(max_points > 0.0) || !(weight >= 0.0) {
+            return Err(Error(Status::BadRequest));
+        }
+
+        let position = self.criteria(&db).await?.len() as i32;
+
+        Ok(Criterion::create(
+            self,
+            position,
+            label.trim().to_string(),
+            description.trim().to_string(),
+            max_points,
+            weight,
+        )
+        .save(&db)
+        .await?)
+    }
+
+    /// Returns the criteria of the assignment, in order.
+    pub async fn rubric(&self, db: &Db) -> Result<Vec<Criterion>> {
+        let mut criteria = self.criteria(&db).await?;
+        criteria.sort_by_key(|x| (x.position, x.id));
+        Ok(criteria)
+    }
+
+    /// Moves the assignment to a new state, checking that the transition is allowed.
+    ///
+    /// The actor is None when the transition is triggered by the scheduler.
+    pub async fn transition(
+        &mut self,
+        next: AssignmentState,
+        actor: Option<&User>,
+        db: &Db,
+    ) -> Result<()> {
+        if !self.state.can_transition_to(next) {
+            return Err(Error(Status::BadRequest));
+        }
+
+        let before = self.state;
+        self.state = next;
         self.save(&db).await?;
+
+        AuditLog::record(
+            actor,
+            AuditAction::AssignmentStateChanged,
+            Target::group(self.group(&db).await?.id),
+            Some(json!({ "assignment": self.id, "state": before })),
+            Some(json!({ "assignment": self.id, "state": self.state })),
+            db,
+        )
+        .await?;
+
         Ok(())
     }
 
+    /// Starts the assignment: the students get read access to the subject and their own copy of
+    /// the answer template.
+    ///
+    /// The copies are owned by the actor, or by a teacher of the group when the assignment is
+    /// started by the scheduler.
+    pub async fn start(
+        &mut self,
+        actor: Option<&User>,
+        config: &Config,
+        socks: &WebSockets,
+        db: &Db,
+    ) -> Result<()> {
+        if !self.state.can_transition_to(AssignmentState::Working) {
+            return Err(Error(Status::BadRequest));
+        }
+
+        let participants = self.group(db).await?.participants(db).await?;
+
+        let teacher = participants
+            .iter()
+            .find(|(_, role)| *role == ParticipantRole::Teacher)
+            .map(|(user, _)| user);
+
+        let owner = actor.or(teacher).ok_or(Error(Status::BadRequest))?;
+
+        let subject = self.subject(db).await?;
+        let template = self.answer_template(db).await?;
+
+        // Prepare answers and make subject read for students
+        for (student, role) in &participants {
+            if *role == ParticipantRole::Teacher {
+                continue;
+            }
+
+            subject.add_user(student, Role::Read, db).await?;
+
+            // The name of the copy does not tell who wrote it, since it can be peer reviewed.
+            let new = template
+                .duplicate(&template.name, owner, config, db)
+                .await?;
+
+            new.add_user(student, Role::Write, db).await?;
+            new.notify_change(db, socks).await?;
+
+            Answer::new(self, &new, student).save(db).await?;
+        }
+
+        self.transition(AssignmentState::Working, actor, db).await
+    }
+
+    /// Opens the evaluation of the assignment.
+    ///
+    /// Students can no longer edit the answers that were not submitted yet, which stay unsubmitted:
+    /// they cannot be submitted once the assignment leaves the working state. Peer reviews are
+    /// assigned if enabled, only for the submitted answers.
+    pub async fn open_evaluation(&mut self, actor: Option<&User>, db: &Db) -> Result<()> {
+        if !self.state.can_transition_to(AssignmentState::Evaluation) {
+            return Err(Error(Status::BadRequest));
+        }
+
+        for answer in self.answers(db).await? {
+            if answer.finished {
+                continue;
+            }
+
+            let capsule = answer.capsule(db).await?;
+            for (participant, role) in capsule.users(db).await? {
+                if role == Role::Write {
+                    capsule.remove_user(&participant, db).await?;
+                    capsule.add_user(&participant, Role::Read, db).await?;
+                }
+            }
+        }
+
+        self.transition(AssignmentState::Evaluation, actor, db)
+            .await?;
+        self.assign_peer_reviews(db).await
+    }
+
+    /// Returns the deadline of an answer, taking its extension into account.
+    pub fn deadline_for(&self, answer: &Answer) -> Option<NaiveDateTime> {
+        answer.extension.or(self.due)
+    }
+
+    /// Returns the moment the assignment must be closed, which is postponed by the extensions that
+    /// end after the closing date.
+    pub async fn closing_time(&self, db: &Db) -> Result<Option<NaiveDateTime>> {
+        let closes = match self.closes {
+            Some(closes) => closes,
+            None => return Ok(None),
+        };
+
+        Ok(Some(
+            self.answers(db)
+                .await?
+                .iter()
+                .filter_map(|x| x.extension)
+                .fold(closes, |x, y| x.max(y)),
+        ))
+    }
+
+    /// Returns true if peer reviews can still be submitted.
+    pub fn accepts_peer_reviews(&self) -> bool {
+        self.peer_reviews > 0
+            && self.state == AssignmentState::Evaluation
+            && self
+                .review_deadline
+                .map(|x| x > Utc::now().naive_utc())
+                .unwrap_or(true)
+    }
+
+    /// Randomly assigns the submitted answers to student reviewers.
+    ///
+    /// Students are shuffled and each answer is given to the students that follow its author, so that
+    /// every student reviews the same number of answers and never reviews their own. Reviewers get
+    /// read access to the answers they review.
+    pub async fn assign_peer_reviews(&self, db: &Db) -> Result<()> {
+        if self.peer_reviews <= 0 {
+            return Ok(());
+        }
+
+        let mut answers = vec![];
+        for answer in self.answers(&db).await? {
+            if answer.finished {
+                let student = answer.student(&db).await?;
+                answers.push((answer, student));
+            }
+        }
+
+        answers.shuffle(&mut rand::thread_rng());
+
+        let reviewers = peer_reviewers(answers.len(), self.peer_reviews as usize);
+
+        for ((answer, _), reviewers) in answers.iter().zip(reviewers) {
+            let capsule = answer.capsule(&db).await?;
+
+            for index in reviewers {
+                let (_, reviewer) = &answers[index];
+                capsule.add_user(reviewer, Role::Read, &db).await?;
+                Evaluation::create(answer, reviewer, false, None, true)
+                    .save(&db)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the students of the group with their progress watching the subject.
+    pub async fn watch_progress(&self, db: &Db) -> Result<Vec<(User, Option<WatchProgress>)>> {
+        let subject = self.subject(&db).await?;
+        let mut progress = WatchProgress::get_all(&subject, db).await?;
+        let mut students = vec![];
+
+        for (participant, role) in self.group(&db).await?.participants(&db).await? {
+            if role != ParticipantRole::Student {
+                continue;
+            }
+
+            let found = progress.remove(&participant.id);
+            students.push((participant, found));
+        }
+
+        Ok(students)
+    }
+
     /// Returns a JSON value representing the assignment.
-    pub async fn to_json(&self, db: &Db) -> Result<Value> {
+    ///
+    /// The watch progress of the students is only given to the teachers of the group.
+    pub async fn to_json(&self, user: &User, db: &Db) -> Result<Value> {
         let answers = self.answers(&db).await?;
         let answers = answers.iter().map(|x| x.to_json(db));
         let answers = futures::future::try_join_all(answers).await?;
 
-        Ok(json!({
+        let subject = self.subject(&db).await?;
+        let group = self.group(&db).await?;
+
+        let criteria = self
+            .rubric(&db)
+            .await?
+            .iter()
+            .map(Criterion::to_json)
+            .collect::<Vec<_>>();
+
+        let mut json = json!({
             "id": self.id,
-            "subject": HARSH.encode(self.subject(&db).await?.id),
+            "subject": HARSH.encode(subject.id),
             "answer_template": HARSH.encode(self.answer_template(&db).await?.id),
-            "group": self.group(&db).await?.id,
-            "criteria": self.criteria.split("\n").collect::<Vec<_>>(),
+            "group": group.id,
+            "criteria": criteria,
             "state": self.state,
             "answers": answers,
+            "peer_reviews": self.peer_reviews,
+            "anonymous_reviews": self.anonymous_reviews,
+            "review_deadline": self.review_deadline.map(|x| x.timestamp()),
+            "opens": self.opens.map(|x| x.timestamp()),
+            "due": self.due.map(|x| x.timestamp()),
+            "closes": self.closes.map(|x| x.timestamp()),
+        });
+
+        if group.is_teacher(user, db).await? {
+            let progress = self
+                .watch_progress(db)
+                .await?
+                .into_iter()
+                .map(|(student, progress)| {
+                    json!({
+                        "username": student.username,
+                        "watched": progress.as_ref().map(|x| x.ratio(subject.duration_ms)).unwrap_or(0.0),
+                        "position": progress.as_ref().map(|x| x.position).unwrap_or(0),
+                        "updated": progress.as_ref().map(|x| x.updated.timestamp()),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            json["watch_progress"] = json!(progress);
+        }
+
+        Ok(json)
+    }
+}
+
+/// A criterion of the rubric used to evaluate the answers to an assignment.
+#[ergol]
+pub struct Criterion {
+    /// The id of the criterion.
+    #[id]
+    pub id: i32,
+
+    /// The assignment whose answers are evaluated against this criterion.
+    #[many_to_one(criteria)]
+    pub assignment: Assignment,
+
+    /// The position of the criterion in the rubric.
+    pub position: i32,
+
+    /// The short name of the criterion.
+    pub label: String,
+
+    /// A longer explanation of what is expected, possibly empty.
+    pub description: String,
+
+    /// The maximum number of points that can be given for this criterion.
+    pub max_points: f64,
+
+    /// The weight of the criterion in the total of an evaluation.
+    pub weight: f64,
+}
+
+impl Criterion {
+    /// JSON representation of the criterion.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "label": self.label,
+            "description": self.description,
+            "max_points": self.max_points,
+            "weight": self.weight,
+        })
+    }
+}
+
+/// Returns the weighted total of points given in the order of a rubric.
+pub fn weighted_total(rubric: &[Criterion], points: &[f64]) -> f64 {
+    rubric
+        .iter()
+        .zip(points)
+        .map(|(criterion, points)| criterion.weight * points)
+        .sum()
+}
+
+/// A criterion of an assignment template, copied into the rubric of the assignments created from
+/// the template.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateCriterion {
+    /// The short name of the criterion.
+    pub label: String,
+
+    /// A longer explanation of what is expected, possibly empty.
+    pub description: String,
+
+    /// The maximum number of points that can be given for this criterion.
+    pub max_points: f64,
+
+    /// The weight of the criterion in the total of an evaluation.
+    pub weight: f64,
+}
+
+/// An assignment that a teacher can give to several groups.
+///
+/// Each group gets its own assignment, with its own copies of the subject and answer template.
+#[ergol]
+pub struct AssignmentTemplate {
+    /// The id of the template.
+    #[id]
+    pub id: i32,
+
+    /// The name of the template.
+    pub name: String,
+
+    /// The teacher that created the template.
+    #[many_to_one(assignment_templates)]
+    pub owner: User,
+
+    /// The capsule that contains the subject of the assignment.
+    #[many_to_one(subject_templates)]
+    pub subject: Capsule,
+
+    /// The capsule that serves as a template for answers.
+    #[many_to_one(answer_template_templates)]
+    pub answer_template: Capsule,
+
+    /// The rubric of the assignments.
+    pub criteria: EJson<Vec<TemplateCriterion>>,
+
+    /// The number of students that review each answer, peer review is disabled if zero.
+    pub peer_reviews: i32,
+
+    /// Whether students reviewing each other are anonymous to each other.
+    pub anonymous_reviews: bool,
+
+    /// The number of hours between the opening of an assignment and its due date, if any.
+    pub due_after: Option<i32>,
+
+    /// The number of hours between the opening of an assignment and its closing, if any.
+    pub closes_after: Option<i32>,
+
+    /// The number of hours students have to review each other after the closing, if any.
+    pub review_duration: Option<i32>,
+}
+
+impl AssignmentTemplate {
+    /// Creates an assignment from the template for a group.
+    ///
+    /// The subject and answer template are copied for the group and owned by the teacher. The
+    /// dates are computed from the opening date, and left empty if there is none.
+    pub async fn instantiate(
+        &self,
+        group: &Group,
+        opens: Option<NaiveDateTime>,
+        teacher: &User,
+        config: &Config,
+        db: &Db,
+    ) -> Result<Assignment> {
+        let subject = self.subject(&db).await?;
+        let subject = subject
+            .duplicate(
+                format!("{} ({})", subject.name, group.name),
+                teacher,
+                config,
+                db,
+            )
+            .await?;
+
+        let answer_template = self.answer_template(&db).await?;
+        let answer_template = answer_template
+            .duplicate(
+                format!("{} ({})", answer_template.name, group.name),
+                teacher,
+                config,
+                db,
+            )
+            .await?;
+
+        let after = |hours: Option<i32>, from: Option<NaiveDateTime>| match (hours, from) {
+            (Some(hours), Some(from)) => Some(from + Duration::hours(hours as i64)),
+            _ => None,
+        };
+
+        let due = after(self.due_after, opens);
+        let closes = after(self.closes_after, opens);
+        let review_deadline = after(self.review_duration, closes);
+
+        let assignment = Assignment::create(
+            &subject,
+            &answer_template,
+            group,
+            AssignmentState::Preparation,
+            self.peer_reviews,
+            self.anonymous_reviews,
+            review_deadline,
+            opens,
+            due,
+            closes,
+        )
+        .save(&db)
+        .await?;
+
+        for criterion in &self.criteria.0 {
+            assignment
+                .add_criterion(
+                    &criterion.label,
+                    &criterion.description,
+                    criterion.max_points,
+                    criterion.weight,
+                    db,
+                )
+                .await?;
+        }
+
+        Ok(assignment)
+    }
+
+    /// JSON representation of the template.
+    pub async fn to_json(&self, db: &Db) -> Result<Value> {
+        Ok(json!({
+            "id": self.id,
+            "name": self.name,
+            "subject": HARSH.encode(self.subject(&db).await?.id),
+            "answer_template": HARSH.encode(self.answer_template(&db).await?.id),
+            "criteria": self.criteria.0,
+            "peer_reviews": self.peer_reviews,
+            "anonymous_reviews": self.anonymous_reviews,
+            "due_after": self.due_after,
+            "closes_after": self.closes_after,
+            "review_duration": self.review_duration,
         }))
     }
 }
@@ -166,25 +758,68 @@ pub struct Answer {
     #[many_to_one(subject_answers)]
     pub capsule: Capsule,
 
-    /// Whether the student has finished is answer or not.
+    /// The student that writes the answer.
+    ///
+    /// The capsule of the answer is owned by a teacher, so the student cannot be found from its
+    /// users.
+    #[many_to_one(answers)]
+    pub student: User,
+
+    /// Whether the student has submitted their answer.
     pub finished: bool,
+
+    /// Whether the answer was submitted after its deadline.
+    pub late: bool,
+
+    /// The moment the answer was submitted.
+    pub submitted: Option<NaiveDateTime>,
+
+    /// The deadline granted to the student by a teacher, that replaces the due date.
+    pub extension: Option<NaiveDateTime>,
+
+    /// The number of reminders sent to the student before the deadline.
+    pub reminders_sent: i32,
 }
 
 impl Answer {
-    /// Returns the owner of the answer.
-    pub async fn owner(&self, db: &Db) -> Result<User> {
-        for (user, role) in self.capsule(&db).await?.users(&db).await? {
-            if role == Role::Owner {
-                return Ok(user);
+    /// Returns the average points of each criterion of the rubric given to the answer by the peer
+    /// reviews and by the teachers, if any.
+    pub async fn partial_scores(
+        &self,
+        rubric: &[Criterion],
+        db: &Db,
+    ) -> Result<(Option<Vec<f64>>, Option<Vec<f64>>)> {
+        let mut teacher = vec![];
+        let mut peer = vec![];
+
+        for evaluation in self.evaluations(&db).await? {
+            if let Some(points) = evaluation.points(rubric, db).await? {
+                if evaluation.peer {
+                    peer.push(points);
+                } else {
+                    teacher.push(points);
+                }
             }
         }
 
-        Err(Error(Status::NotFound))
+        Ok((
+            average_scores(&peer, rubric.len()),
+            average_scores(&teacher, rubric.len()),
+        ))
+    }
+
+    /// Returns the points of each criterion of the rubric given to the answer, if it was
+    /// evaluated.
+    ///
+    /// The evaluation of a teacher overrides the average of the peer reviews.
+    pub async fn final_scores(&self, rubric: &[Criterion], db: &Db) -> Result<Option<Vec<f64>>> {
+        let (peer, teacher) = self.partial_scores(rubric, db).await?;
+        Ok(teacher.or(peer))
     }
 
     /// Creates a non finished answer.
-    pub fn new(assignment: &Assignment, capsule: &Capsule) -> AnswerWithoutId {
-        Answer::create(assignment, capsule, false)
+    pub fn new(assignment: &Assignment, capsule: &Capsule, student: &User) -> AnswerWithoutId {
+        Answer::create(assignment, capsule, student, false, false, None, None, 0)
     }
 
     /// JSON representation of the answer.
@@ -193,11 +828,14 @@ impl Answer {
             "id": self.id,
             "capsule": HARSH.encode(self.capsule(db).await?.id),
             "finished": self.finished,
+            "late": self.late,
+            "submitted": self.submitted.map(|x| x.timestamp()),
+            "extension": self.extension.map(|x| x.timestamp()),
         }))
     }
 }
 
-/// An eavluation of an answer.
+/// An evaluation of an answer.
 #[ergol]
 pub struct Evaluation {
     /// The id of the evaluation.
@@ -212,14 +850,166 @@ pub struct Evaluation {
     #[many_to_one(evaluations)]
     pub reviewer: User,
 
-    /// The score for each criterion.
+    /// Whether the reviewer submitted their scores.
+    pub done: bool,
+
+    /// The id of an optional capsule giving feedback to the assignee.
+    pub feedback_capsule: Option<i32>,
+
+    /// Whether the evaluation is a peer review by a student rather than by a teacher.
+    pub peer: bool,
+}
+
+impl Evaluation {
+    /// Returns the points given for each criterion of the rubric, if the evaluation is done and
+    /// scores every criterion.
+    pub async fn points(&self, rubric: &[Criterion], db: &Db) -> Result<Option<Vec<f64>>> {
+        if !self.done {
+            return Ok(None);
+        }
+
+        let mut scores = HashMap::new();
+        for score in self.scores(&db).await? {
+            scores.insert(score.criterion(&db).await?.id, score.points);
+        }
+
+        Ok(rubric.iter().map(|x| scores.get(&x.id).copied()).collect())
+    }
+
+    /// Replaces the scores of the evaluation and marks it as done.
     ///
-    /// It's a string of integers seperated by new lines.
-    pub scores: String,
+    /// The scores are given as pairs of criterion id and points, see [`validate_scores`].
+    pub async fn set_scores(
+        &mut self,
+        rubric: &[Criterion],
+        scores: &[(i32, f64)],
+        db: &Db,
+    ) -> Result<()> {
+        let points = validate_scores(rubric, scores)?;
 
-    /// An optional capsule giving feedback to the assignee.
-    #[many_to_one(evaluations)]
-    pub capsule: Capsule,
+        for score in self.scores(&db).await? {
+            score.delete(&db).await?;
+        }
+
+        for (criterion, points) in rubric.iter().zip(points) {
+            Score::create(&*self, criterion, points).save(&db).await?;
+        }
+
+        self.done = true;
+        self.save(&db).await?;
+
+        Ok(())
+    }
+
+    /// JSON representation of the evaluation.
+    ///
+    /// The reviewer is hidden when `show_reviewer` is false, for anonymous peer reviews.
+    pub async fn to_json(&self, show_reviewer: bool, db: &Db) -> Result<Value> {
+        let reviewer = if show_reviewer {
+            Some(self.reviewer(&db).await?.username)
+        } else {
+            None
+        };
+
+        let mut scores = vec![];
+        for score in self.scores(&db).await? {
+            scores.push(json!({
+                "criterion": score.criterion(&db).await?.id,
+                "points": score.points,
+            }));
+        }
+
+        Ok(json!({
+            "id": self.id,
+            "answer": self.answer(&db).await?.id,
+            "reviewer": reviewer,
+            "peer": self.peer,
+            "done": self.done,
+            "scores": scores,
+            "feedback_capsule": self.feedback_capsule.map(|x| HARSH.encode(x)),
+        }))
+    }
+}
+
+/// Checks that scores, given as pairs of criterion id and points, score every criterion of the
+/// rubric exactly once within its maximum number of points.
+///
+/// Returns the points in the order of the rubric.
+pub fn validate_scores(rubric: &[Criterion], scores: &[(i32, f64)]) -> Result<Vec<f64>> {
+    if scores.len() != rubric.len() {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let mut points = vec![];
+    for criterion in rubric {
+        let mut matching = scores.iter().filter(|(id, _)| *id == criterion.id);
+
+        let value = match (matching.next(), matching.next()) {
+            (Some((_, value)), None) => *value,
+            _ => return Err(Error(Status::BadRequest)),
+        };
+
+        if !(value >= 0.0 && value <= criterion.max_points) {
+            return Err(Error(Status::BadRequest));
+        }
+
+        points.push(value);
+    }
+
+    Ok(points)
+}
+
+/// The points given for a criterion in an evaluation.
+#[ergol]
+pub struct Score {
+    /// The id of the score.
+    #[id]
+    pub id: i32,
+
+    /// The evaluation the score belongs to.
+    #[many_to_one(scores)]
+    pub evaluation: Evaluation,
+
+    /// The criterion that is scored.
+    #[many_to_one(scores)]
+    pub criterion: Criterion,
+
+    /// The number of points given.
+    pub points: f64,
+}
+
+/// Returns the indices of the reviewers of each answer of a peer review round.
+///
+/// Each answer is given to the authors of the answers that follow it, so that every author reviews
+/// the same number of answers and never reviews their own.
+fn peer_reviewers(count: usize, reviews: usize) -> Vec<Vec<usize>> {
+    let reviews = reviews.min(count.saturating_sub(1));
+
+    (0..count)
+        .map(|index| {
+            (1..=reviews)
+                .map(|offset| (index + offset) % count)
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the average points of each criterion among several evaluations of the same rubric.
+pub fn average_scores(points: &[Vec<f64>], criteria: usize) -> Option<Vec<f64>> {
+    let points = points
+        .iter()
+        .filter(|x| x.len() == criteria)
+        .collect::<Vec<_>>();
+
+    if points.is_empty() {
+        return None;
+    }
+
+    Some(
+        (0..criteria)
+            .map(|i| points.iter().map(|x| x[i]).sum::<f64>() / points.len() as f64)
+            .collect(),
+    )
 }
 
 /// Creates some users in the db.
@@ -298,6 +1088,7 @@ pub async fn populate_db(db: &Db, config: &Config) -> Result<()> {
             events: vec![],
             webcam_settings: None,
             fade: Fade::none(),
+            quizzes: vec![],
         })
         .collect::<Vec<_>>();
 
@@ -327,6 +1118,7 @@ pub async fn populate_db(db: &Db, config: &Config) -> Result<()> {
             events: vec![],
             webcam_settings: None,
             fade: Fade::none(),
+            quizzes: vec![],
         })
         .collect::<Vec<_>>();
 
@@ -337,3 +1129,73 @@ pub async fn populate_db(db: &Db, config: &Config) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{peer_reviewers, AssignmentState};
+
+    #[test]
+    fn peer_reviews_are_balanced() {
+        for count in 0..8_usize {
+            for reviews in 0..4 {
+                let reviewers = peer_reviewers(count, reviews);
+                let expected = reviews.min(count.saturating_sub(1));
+                let mut reviewed = vec![0; count];
+
+                assert_eq!(reviewers.len(), count);
+
+                for (answer, reviewers) in reviewers.iter().enumerate() {
+                    assert_eq!(reviewers.len(), expected);
+                    assert!(!reviewers.contains(&answer));
+
+                    for reviewer in reviewers {
+                        reviewed[*reviewer] += 1;
+                    }
+
+                    let mut distinct = reviewers.clone();
+                    distinct.sort_unstable();
+                    distinct.dedup();
+                    assert_eq!(distinct.len(), expected);
+                }
+
+                assert!(reviewed.iter().all(|x| *x == expected));
+            }
+        }
+    }
+
+    #[test]
+    fn reviews_are_capped_by_the_number_of_answers() {
+        assert_eq!(
+            peer_reviewers(3, 5),
+            vec![vec![1, 2], vec![2, 0], vec![0, 1]]
+        );
+        assert_eq!(peer_reviewers(1, 2), vec![Vec::<usize>::new()]);
+        assert!(peer_reviewers(0, 2).is_empty());
+    }
+
+    #[test]
+    fn assignments_only_move_forward() {
+        use AssignmentState::*;
+
+        let states = [Preparation, Prepared, Working, Evaluation, Finished];
+        let allowed = [
+            (Preparation, Prepared),
+            (Preparation, Working),
+            (Prepared, Working),
+            (Working, Evaluation),
+            (Evaluation, Finished),
+        ];
+
+        for from in states.iter().copied() {
+            for to in states.iter().copied() {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from, to)),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! This module contains the lockouts triggered by too many failed login attempts.
+
+use chrono::NaiveDateTime;
+
+use ergol::prelude::*;
+
+use crate::{Db, Result};
+
+/// A lockout of an account or an ip address.
+///
+/// A row is inserted each time a key gets locked, which makes this table a history of lockouts.
+#[ergol]
+pub struct LoginLockout {
+    /// The id of the lockout.
+    #[id]
+    pub id: i32,
+
+    /// The key that was locked, for example `account-ip:127.0.0.1/username` or `ip:127.0.0.1`.
+    pub key: String,
+
+    /// The number of failed attempts that triggered the lockout.
+    pub failures: i32,
+
+    /// The moment the lockout started.
+    pub date: NaiveDateTime,
+
+    /// The moment the lockout ends.
+    pub locked_until: NaiveDateTime,
+}
+
+impl LoginLockout {
+    /// Returns the moment until which a key is locked according to the database, if it is locked.
+    pub async fn locked_until(
+        key: &str,
+        now: NaiveDateTime,
+        db: &Db,
+    ) -> Result<Option<NaiveDateTime>> {
+        let lockouts = LoginLockout::select()
+            .filter(login_lockout::key::eq(key.to_string()))
+            .order_by(login_lockout::locked_until::descend())
+            .limit(1)
+            .execute(&db)
+            .await?;
+
+        Ok(lockouts
+            .into_iter()
+            .map(|x| x.locked_until)
+            .find(|x| *x > now))
+    }
+}
@@ -1,9 +1,17 @@
 //! This module contains everything that helps us deal with the library.
 
+pub mod audit;
 pub mod capsule;
+pub mod comment;
+pub mod export;
 pub mod group;
+pub mod lockout;
 pub mod notification;
+pub mod project;
+pub mod quiz;
 pub mod session;
+pub mod share;
 pub mod stats;
 pub mod task_status;
 pub mod user;
+pub mod view;
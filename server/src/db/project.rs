@@ -0,0 +1,146 @@
+//! This module contains the projects, that gather capsules.
+//!
+//! Projects can be shared: the role of a collaborator on a project cascades to all its capsules,
+//! unless they have a higher role on a capsule. Nobody becomes the owner of a capsule through a
+//! project.
+
+use std::cmp::Reverse;
+
+use chrono::{NaiveDateTime, Utc};
+
+use ergol::prelude::*;
+use ergol::tokio_postgres::types::Json;
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+
+use crate::db::capsule::{Capsule, Role};
+use crate::db::user::User;
+use crate::{Db, Error, Result, HARSH};
+
+/// Returns the role on the capsules of a project given by a role on the project.
+pub fn cascaded_role(role: Role) -> Role {
+    role.min(Role::Write)
+}
+
+/// A project, that gathers capsules.
+#[ergol]
+pub struct Project {
+    /// The id of the project.
+    #[id]
+    pub id: i32,
+
+    /// The name of the project.
+    pub name: String,
+
+    /// The description of the project.
+    pub description: String,
+
+    /// The ids of the capsules of the project, in the order chosen by its collaborators.
+    ///
+    /// The capsules missing from the order come after the others, the most recently modified
+    /// first.
+    pub capsule_order: Json<Vec<i32>>,
+
+    /// The last time the project was modified.
+    pub last_modified: NaiveDateTime,
+
+    /// The users that have rights on the project.
+    #[many_to_many(projects, Role)]
+    pub users: User,
+}
+
+impl Project {
+    /// Creates a new project.
+    pub async fn new<P: Into<String>, Q: Into<String>>(
+        name: P,
+        description: Q,
+        owner: &User,
+        db: &Db,
+    ) -> Result<Project> {
+        let project = Project::create(
+            name.into(),
+            description.into(),
+            Json(vec![]),
+            Utc::now().naive_utc(),
+        )
+        .save(&db)
+        .await?;
+
+        project.add_user(owner, Role::Owner, db).await?;
+
+        Ok(project)
+    }
+
+    /// Returns the project of a user with a given name, creating it if needed.
+    ///
+    /// The projects shared with the user are never chosen, so that naming a project does not move
+    /// capsules into someone else's project.
+    pub async fn find_or_create(name: &str, user: &User, db: &Db) -> Result<Project> {
+        let project = user
+            .projects(&db)
+            .await?
+            .into_iter()
+            .find(|(x, role)| x.name == name && *role == Role::Owner);
+
+        match project {
+            Some((project, _)) => Ok(project),
+            None => Project::new(name, "", user, db).await,
+        }
+    }
+
+    /// Retrieves the owner of a project.
+    pub async fn owner(&self, db: &Db) -> Result<User> {
+        for (user, role) in self.users(db).await? {
+            if role == Role::Owner {
+                return Ok(user);
+            }
+        }
+
+        Err(Error(Status::NotFound))
+    }
+
+    /// Returns the capsules of the project, in order.
+    pub async fn ordered_capsules(&self, db: &Db) -> Result<Vec<Capsule>> {
+        let mut capsules = self.capsules(&db).await?;
+
+        capsules.sort_by_key(|x| {
+            let position = self.capsule_order.0.iter().position(|y| *y == x.id);
+            (position.unwrap_or(usize::MAX), Reverse(x.last_modified))
+        });
+
+        Ok(capsules)
+    }
+
+    /// Returns a json representation of the project.
+    pub async fn to_json(&self, role: Role, db: &Db) -> Result<Value> {
+        let users = self
+            .users(&db)
+            .await?
+            .into_iter()
+            .map(|(x, role)| {
+                json!({
+                    "username": x.username,
+                    "role": role,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let capsules = self
+            .ordered_capsules(db)
+            .await?
+            .iter()
+            .map(|x| HARSH.encode(x.id))
+            .collect::<Vec<_>>();
+
+        Ok(json!({
+            "id": HARSH.encode(self.id),
+            "name": self.name,
+            "description": self.description,
+            "role": role,
+            "users": users,
+            "capsules": capsules,
+            "last_modified": self.last_modified.timestamp(),
+        }))
+    }
+}
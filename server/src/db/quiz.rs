@@ -0,0 +1,175 @@
+//! This module contains the answers of the viewers to the quizzes of the capsules.
+//!
+//! Like the views, anonymous answers are only ever exposed aggregated, identified answers are
+//! listed so that teachers can follow their students.
+
+use std::collections::HashMap;
+
+use chrono::{NaiveDateTime, Utc};
+
+use ergol::prelude::*;
+
+use rocket::serde::json::{json, Value};
+
+use crate::db::capsule::{Capsule, Quiz};
+use crate::db::user::User;
+use crate::{Db, Result};
+
+/// The answer of a viewer to a quiz.
+#[ergol]
+pub struct QuizAnswer {
+    /// The id of the answer.
+    #[id]
+    pub id: i32,
+
+    /// The capsule of the quiz.
+    #[many_to_one(quiz_answers)]
+    pub capsule: Capsule,
+
+    /// The id of the quiz.
+    pub quiz: String,
+
+    /// The identifier of the play during which the quiz was answered.
+    pub session: String,
+
+    /// The id of the user that answered, if they were logged in.
+    pub user_id: Option<i32>,
+
+    /// The index of the chosen option.
+    pub option: i32,
+
+    /// Whether the chosen option is the correct one.
+    pub correct: bool,
+
+    /// The moment of the answer.
+    pub answered: NaiveDateTime,
+}
+
+impl QuizAnswer {
+    /// Records the answer of a viewer to a quiz.
+    ///
+    /// Only the first answer counts, so that the viewer cannot try every option: if the quiz was
+    /// already answered by the logged in user, or during the play for anonymous viewers, the first
+    /// answer is returned.
+    pub async fn record(
+        capsule: &Capsule,
+        quiz: &Quiz,
+        session: String,
+        user: Option<&User>,
+        option: usize,
+        db: &Db,
+    ) -> Result<QuizAnswer> {
+        let quiz_id = quiz.id.to_string();
+
+        let answers = match user {
+            Some(user) => {
+                let mut answers = QuizAnswer::select()
+                    .filter(quiz_answer::user_id::eq(Some(user.id)))
+                    .execute(&db)
+                    .await?;
+                answers.sort_by_key(|x| x.answered);
+                answers
+            }
+            None => {
+                QuizAnswer::select()
+                    .filter(quiz_answer::session::eq(session.clone()))
+                    .execute(&db)
+                    .await?
+            }
+        };
+
+        for answer in answers {
+            if answer.quiz == quiz_id && answer.capsule(&db).await?.id == capsule.id {
+                return Ok(answer);
+            }
+        }
+
+        Ok(QuizAnswer::create(
+            capsule,
+            quiz_id,
+            session,
+            user.map(|x| x.id),
+            option as i32,
+            option == quiz.answer,
+            Utc::now().naive_utc(),
+        )
+        .save(&db)
+        .await?)
+    }
+
+    /// Returns the first answer of each user to each quiz of a capsule, indexed by user id and
+    /// quiz id.
+    pub async fn by_user(capsule: &Capsule, db: &Db) -> Result<HashMap<(i32, String), QuizAnswer>> {
+        let mut answers = capsule.quiz_answers(&db).await?;
+        answers.sort_by_key(|x| x.answered);
+
+        let mut by_user = HashMap::new();
+        for answer in answers {
+            if let Some(user_id) = answer.user_id {
+                by_user
+                    .entry((user_id, answer.quiz.clone()))
+                    .or_insert(answer);
+            }
+        }
+
+        Ok(by_user)
+    }
+
+    /// Returns the results of the quizzes of a capsule.
+    pub async fn results(capsule: &Capsule, db: &Db) -> Result<Value> {
+        let answers = capsule.quiz_answers(&db).await?;
+        let by_user = QuizAnswer::by_user(capsule, db).await?;
+
+        let mut usernames = HashMap::new();
+        for (user_id, _) in by_user.keys() {
+            if !usernames.contains_key(user_id) {
+                if let Some(user) = User::get_by_id(*user_id, &db).await? {
+                    usernames.insert(*user_id, user.username);
+                }
+            }
+        }
+
+        let mut quizzes = vec![];
+        for (gos, quiz) in capsule.quizzes() {
+            let quiz_id = quiz.id.to_string();
+            let answers = answers
+                .iter()
+                .filter(|x| x.quiz == quiz_id)
+                .collect::<Vec<_>>();
+
+            let counts = (0..quiz.options.len())
+                .map(|i| answers.iter().filter(|x| x.option == i as i32).count())
+                .collect::<Vec<_>>();
+
+            let correct = answers.iter().filter(|x| x.correct).count();
+
+            let viewers = by_user
+                .iter()
+                .filter(|((_, quiz), _)| *quiz == quiz_id)
+                .filter_map(|((user_id, _), answer)| {
+                    usernames.get(user_id).map(|username| {
+                        json!({
+                            "username": username,
+                            "option": answer.option,
+                            "correct": answer.correct,
+                        })
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            quizzes.push(json!({
+                "id": quiz.id,
+                "gos": gos,
+                "question": quiz.question,
+                "options": quiz.options,
+                "answer": quiz.answer,
+                "answers": answers.len(),
+                "counts": counts,
+                "correct_rate": if answers.is_empty() { 0.0 } else { correct as f64 / answers.len() as f64 },
+                "viewers": viewers,
+            }));
+        }
+
+        Ok(json!({ "quizzes": quizzes }))
+    }
+}
@@ -0,0 +1,162 @@
+//! This module contains the share tokens that give access to private capsules without an account.
+
+use chrono::{NaiveDateTime, Utc};
+
+use ergol::prelude::*;
+
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+
+use crate::db::capsule::Capsule;
+use crate::{Db, Error, Result};
+
+/// A token that allows anyone who knows it to watch a published capsule.
+#[ergol]
+pub struct ShareToken {
+    /// The id of the share token.
+    #[id]
+    pub id: i32,
+
+    /// The secret that is put in the url.
+    #[unique]
+    pub token: String,
+
+    /// The capsule that can be watched with the token.
+    #[many_to_one(share_tokens)]
+    pub capsule: Capsule,
+
+    /// The moment the token was created.
+    pub created: NaiveDateTime,
+
+    /// The moment after which the token can no longer be used, if any.
+    pub expires: Option<NaiveDateTime>,
+
+    /// The number of times the video can be watched with this token, if limited.
+    pub max_views: Option<i32>,
+
+    /// The number of times the video has been watched with this token.
+    pub views: i32,
+
+    /// The hash of the password required to use the token, if any.
+    pub hashed_password: Option<String>,
+}
+
+impl ShareToken {
+    /// Creates and saves a new share token for a capsule.
+    pub async fn new(
+        capsule: &Capsule,
+        expires: Option<NaiveDateTime>,
+        max_views: Option<i32>,
+        password: Option<&str>,
+        db: &Db,
+    ) -> Result<ShareToken> {
+        let rng = OsRng {};
+        let token = rng
+            .sample_iter(&Alphanumeric)
+            .map(char::from)
+            .take(40)
+            .collect::<String>();
+
+        let hashed_password = match password {
+            Some(password) if !password.is_empty() => {
+                Some(bcrypt::hash(password, bcrypt::DEFAULT_COST)?)
+            }
+            _ => None,
+        };
+
+        Ok(ShareToken::create(
+            token,
+            capsule,
+            Utc::now().naive_utc(),
+            expires,
+            max_views,
+            0,
+            hashed_password,
+        )
+        .save(&db)
+        .await?)
+    }
+
+    /// Returns true if the token has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .map(|x| x <= Utc::now().naive_utc())
+            .unwrap_or(false)
+    }
+
+    /// Returns true if the token has been used as many times as it is allowed.
+    pub fn is_exhausted(&self) -> bool {
+        self.max_views.map(|x| self.views >= x).unwrap_or(false)
+    }
+
+    /// Counts a view with the token, and returns false if the token was already exhausted.
+    ///
+    /// The check and the increment are a single statement, so that concurrent views cannot exceed
+    /// the maximum number of views.
+    pub async fn count_view(&mut self, db: &Db) -> Result<bool> {
+        let row = db
+            .query_opt(
+                "UPDATE share_tokens SET views = views + 1 \
+                 WHERE id = $1 AND (max_views IS NULL OR views < max_views) RETURNING views",
+                &[&self.id],
+            )
+            .await?;
+
+        match row {
+            Some(row) => {
+                self.views = row.get(0);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Tests the password of the token, if it requires one.
+    pub fn test_password(&self, password: Option<&str>) -> Result<()> {
+        match (&self.hashed_password, password) {
+            (None, _) => Ok(()),
+            (Some(hash), Some(password)) if bcrypt::verify(password, hash)? => Ok(()),
+            _ => Err(Error(Status::Unauthorized)),
+        }
+    }
+
+    /// Returns a json representation of the token for the audit log.
+    ///
+    /// The token is a secret that gives access to the capsule, so only its last characters are
+    /// logged.
+    pub fn to_audit_json(&self) -> Value {
+        let suffix = self
+            .token
+            .char_indices()
+            .rev()
+            .nth(3)
+            .map(|(i, _)| &self.token[i..])
+            .unwrap_or(&self.token);
+
+        json!({
+            "id": self.id,
+            "token_suffix": suffix,
+            "created": self.created.timestamp(),
+            "expires": self.expires.map(|x| x.timestamp()),
+            "max_views": self.max_views,
+            "views": self.views,
+            "password": self.hashed_password.is_some(),
+        })
+    }
+
+    /// Returns a json representation of the token.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "token": self.token,
+            "created": self.created.timestamp(),
+            "expires": self.expires.map(|x| x.timestamp()),
+            "max_views": self.max_views,
+            "views": self.views,
+            "password": self.hashed_password.is_some(),
+        })
+    }
+}
@@ -19,6 +19,9 @@ pub enum TaskStatType {
 
     /// A publication of a capsule.
     Publication,
+
+    /// An export of a capsule.
+    Export,
 }
 
 /// This table records all production and publication, as well as their start date and duration.
@@ -19,10 +19,13 @@ use rocket::request::{FromRequest, Outcome, Request};
 use rocket::serde::json::{json, Value};
 
 use crate::config::Config;
+use crate::db::audit::{AuditFilter, AuditLog};
 use crate::db::capsule::{capsule, Capsule, Role};
 use crate::db::notification::Notification;
+use crate::db::project::{cascaded_role, project, Project};
 use crate::db::session::Session;
 use crate::mailer::Mailer;
+use crate::rate_limit::dummy_verify;
 use crate::templates::{
     reset_password_email_html, reset_password_email_plain_text, validation_email_html,
     validation_email_plain_text, validation_invitation_html, validation_invitation_plain_text,
@@ -243,16 +246,17 @@ impl User {
         Ok(())
     }
 
-    /// Authenticates a user from its username and password.
+    /// Authenticates a user from its username (or email) and password.
+    ///
+    /// Unknown users and wrong passwords both give an unauthorized error, so that this function
+    /// cannot be used to find out which usernames exist.
     pub async fn authenticate(username: &str, password: &str, db: &Db) -> Result<User> {
-        let user = User::get_by_username(username, db).await?;
-
-        let user = if let Some(user) = user {
-            user
-        } else {
-            User::get_by_email(username, db)
-                .await?
-                .ok_or(Error(Status::NotFound))?
+        let user = match User::get_by_username_or_email(username, db).await? {
+            Some(user) => user,
+            None => {
+                dummy_verify(password);
+                return Err(Error(Status::Unauthorized));
+            }
         };
 
         user.test_password(password)?;
@@ -311,9 +315,28 @@ impl User {
         Ok(Some(session.owner(&db).await?))
     }
 
+    /// Returns the capsules the user has access to, directly or through the projects shared with
+    /// them, with the highest of their roles.
+    pub async fn all_capsules(&self, db: &Db) -> Result<Vec<(Capsule, Role)>> {
+        let mut capsules = self.capsules(&db).await?;
+
+        for (project, role) in self.projects(&db).await? {
+            let role = cascaded_role(role);
+
+            for capsule in project.capsules(&db).await? {
+                match capsules.iter_mut().find(|(x, _)| x.id == capsule.id) {
+                    Some((_, r)) => *r = (*r).max(role),
+                    None => capsules.push((capsule, role)),
+                }
+            }
+        }
+
+        Ok(capsules)
+    }
+
     /// Returns a json representation of the user.
     pub async fn to_json(&self, db: &Db) -> Result<Value> {
-        let capsules = self.capsules(&db).await?;
+        let capsules = self.all_capsules(&db).await?;
         let capsules = capsules
             .iter()
             .map(|(capsule, role)| capsule.to_json(*role, db))
@@ -321,6 +344,14 @@ impl User {
 
         let capsules = try_join_all(capsules).await?;
 
+        let projects = self.projects(&db).await?;
+        let projects = projects
+            .iter()
+            .map(|(project, role)| project.to_json(*role, db))
+            .collect::<Vec<_>>();
+
+        let projects = try_join_all(projects).await?;
+
         let notifications = self
             .notifications(&db)
             .await?
@@ -331,9 +362,7 @@ impl User {
         let groups = self.groups(&db).await?;
         let groups = groups
             .iter()
-            .map(|(group, _)| {
-                group.to_json(&db)
-            })
+            .map(|(group, _)| group.to_json(self, &db))
             .collect::<Vec<_>>();
 
         let groups = try_join_all(groups).await?;
@@ -343,6 +372,7 @@ impl User {
             "email": self.email,
             "cookie": self.sessions(&db).await?.get(0).map(|x| x.secret.clone()),
             "capsules": capsules,
+            "projects": projects,
             "notifications": notifications,
             "plan": self.plan,
             "disk_quota": self.disk_quota,
@@ -411,19 +441,43 @@ impl User {
                 Err(Error(Status::NotFound))
             }
         } else {
-            Ok(self
-                .capsules(&db)
+            // Only the roles on this capsule are loaded, directly or through its project.
+            let role = db
+                .query(
+                    "SELECT role, FALSE FROM capsules_users_join \
+                     WHERE capsules_id = $1 AND users_id = $2 \
+                     UNION ALL \
+                     SELECT projects_users_join.role, TRUE FROM capsules \
+                     JOIN projects_users_join ON projects_users_join.projects_id = capsules.project \
+                     WHERE capsules.id = $1 AND projects_users_join.users_id = $2",
+                    &[&id, &self.id],
+                )
                 .await?
                 .into_iter()
-                .filter(|(x, r)| x.id == id && *r >= permission)
-                .nth(0)
-                .ok_or(Error(Status::NotFound))?)
+                .map(|row| {
+                    let role: Role = row.get(0);
+                    let cascaded: bool = row.get(1);
+                    if cascaded {
+                        cascaded_role(role)
+                    } else {
+                        role
+                    }
+                })
+                .max()
+                .filter(|x| *x >= permission)
+                .ok_or(Error(Status::NotFound))?;
+
+            let capsule = Capsule::get_by_id(id, &db)
+                .await?
+                .ok_or(Error(Status::NotFound))?;
+
+            Ok((capsule, role))
         }
     }
 
     /// Invite a user to join polymny
     pub async fn request_invitation(
-        self,
+        &self,
         username: String,
         email: String,
         mailer: &Option<Mailer>,
@@ -727,12 +781,19 @@ impl Admin {
 
     /// Search by project.
     pub async fn search_by_project(&self, db: &Db, search: &str) -> Result<Value> {
+        let mut capsules = vec![];
+        for project in Project::select()
+            .filter(project::name::like(format!("%{}%", search.to_string())))
+            .execute(&db)
+            .await?
+        {
+            capsules.extend(project.capsules(&db).await?);
+        }
+
+        capsules.sort_by(|a, b| b.last_modified.cmp(&a.last_modified));
+
         let capsules = futures::future::join_all(
-            Capsule::select()
-                .filter(capsule::project::like(format!("%{}%", search.to_string())))
-                .order_by(capsule::last_modified::descend())
-                .execute(&db)
-                .await?
+            capsules
                 .iter()
                 .map(|capsule| capsule.to_json(Role::Read, db)),
         )
@@ -742,4 +803,15 @@ impl Admin {
 
         Ok(json!(capsules))
     }
+
+    /// Returns a page of the audit log.
+    pub async fn get_audit_log(&self, db: &Db, filter: &AuditFilter, page: i32) -> Result<Value> {
+        let entries = AuditLog::search(filter, page, db)
+            .await?
+            .iter()
+            .map(|x| x.to_json())
+            .collect::<Vec<_>>();
+
+        Ok(json!(entries))
+    }
 }
@@ -0,0 +1,453 @@
+//! This module contains the views of published capsules, used to compute analytics.
+//!
+//! Views are reported by the player through a beacon. Each beacon can only add as much watched
+//! time as has passed since the previous one, so that a viewer cannot report more than they
+//! watched. Views are only ever exposed aggregated,
+//! except to the teachers of a group who can see the views of their students: anonymous views are
+//! not linked to any ip address nor user, and the analytics never list them individually.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{NaiveDateTime, Utc};
+
+use ergol::prelude::*;
+use ergol::tokio_postgres::types::Json;
+
+use rocket::serde::json::{json, Value};
+
+use crate::db::capsule::Capsule;
+use crate::db::group::ParticipantRole;
+use crate::db::user::User;
+use crate::{Db, Result};
+
+/// The ratio of the video that must be watched for a view to be considered complete.
+const COMPLETION_RATIO: f64 = 0.9;
+
+/// The interval between two beacons of the player, in ms.
+const BEACON_INTERVAL_MS: i64 = 15_000;
+
+/// The fastest playback rate of the player.
+const MAX_PLAYBACK_RATE: i64 = 2;
+
+/// The delay tolerated between two beacons, on top of the time between them, in ms.
+const BEACON_SLACK_MS: i64 = 5_000;
+
+/// A play of a published capsule.
+#[ergol]
+pub struct View {
+    /// The id of the view.
+    #[id]
+    pub id: i32,
+
+    /// The random identifier generated by the player for this play.
+    #[unique]
+    pub session: String,
+
+    /// The capsule that was watched.
+    #[many_to_one(views)]
+    pub capsule: Capsule,
+
+    /// The id of the user that watched the capsule, if they were logged in.
+    pub user_id: Option<i32>,
+
+    /// The moment the play started.
+    pub started: NaiveDateTime,
+
+    /// The moment the last beacon was received.
+    pub last_seen: NaiveDateTime,
+
+    /// The sorted and disjoint ranges of the video that were watched, in ms.
+    pub watched: Json<Vec<(i32, i32)>>,
+}
+
+/// Merges ranges into a sorted list of disjoint ranges.
+pub fn merge_ranges(mut ranges: Vec<(i32, i32)>) -> Vec<(i32, i32)> {
+    ranges.retain(|(start, end)| start < end);
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(i32, i32)> = Vec::with_capacity(ranges.len());
+
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+}
+
+/// Returns the parts of the ranges that are not watched yet, at most `budget` ms of them.
+///
+/// The watched ranges must be sorted and disjoint, as returned by `merge_ranges`.
+pub fn unwatched_parts(
+    watched: &[(i32, i32)],
+    ranges: Vec<(i32, i32)>,
+    budget: i64,
+) -> Vec<(i32, i32)> {
+    let mut budget = budget;
+    let mut parts = vec![];
+
+    let mut take = |start: i32, end: i32, parts: &mut Vec<(i32, i32)>| {
+        let len = (end as i64 - start as i64).min(budget);
+        if len > 0 {
+            parts.push((start, start + len as i32));
+            budget -= len;
+        }
+    };
+
+    for (start, end) in merge_ranges(ranges) {
+        let mut cursor = start;
+
+        for (x, y) in watched.iter().filter(|(x, y)| *y > start && *x < end) {
+            if *x > cursor {
+                take(cursor, *x, &mut parts);
+            }
+            cursor = cursor.max(*y);
+        }
+
+        if cursor < end {
+            take(cursor, end, &mut parts);
+        }
+    }
+
+    parts
+}
+
+/// Returns the number of ms of the range [start, end) covered by the ranges.
+fn covered(ranges: &[(i32, i32)], start: i32, end: i32) -> i32 {
+    ranges
+        .iter()
+        .map(|(x, y)| ((*y).min(end) - (*x).max(start)).max(0))
+        .sum()
+}
+
+impl View {
+    /// Records the ranges watched during a play, creating the view if needed.
+    ///
+    /// The first beacon of a play is sent after at most one interval, the next ones can add the
+    /// time elapsed since the previous one.
+    pub async fn record(
+        session: String,
+        capsule: &Capsule,
+        user: Option<&User>,
+        ranges: Vec<(i32, i32)>,
+        db: &Db,
+    ) -> Result<()> {
+        let now = Utc::now().naive_utc();
+
+        // The ranges are clamped to the video, so that the stored ranges stay bounded.
+        let duration = capsule.duration_ms.max(0);
+        let ranges = ranges
+            .into_iter()
+            .map(|(x, y)| (x.clamp(0, duration), y.clamp(0, duration)))
+            .collect::<Vec<_>>();
+
+        let budget = |elapsed: i64| elapsed.max(0) * MAX_PLAYBACK_RATE + BEACON_SLACK_MS;
+
+        let parts = match View::get_by_session(session.clone(), &db).await? {
+            Some(mut view) => {
+                // A session cannot be reused for another capsule.
+                if view.capsule(&db).await?.id != capsule.id {
+                    return Ok(());
+                }
+
+                let elapsed = (now - view.last_seen).num_milliseconds();
+                let parts = unwatched_parts(&view.watched.0, ranges, budget(elapsed));
+
+                let mut watched = view.watched.0.clone();
+                watched.extend_from_slice(&parts);
+                view.watched = Json(merge_ranges(watched));
+                view.last_seen = now;
+                view.save(&db).await?;
+                parts
+            }
+            None => {
+                let parts = unwatched_parts(&[], ranges, budget(BEACON_INTERVAL_MS));
+
+                View::create(
+                    session,
+                    capsule,
+                    user.map(|x| x.id),
+                    now,
+                    now,
+                    Json(merge_ranges(parts.clone())),
+                )
+                .save(&db)
+                .await?;
+                parts
+            }
+        };
+
+        if let Some(user) = user {
+            WatchProgress::record(user, capsule, &parts, db).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of ms of the video that were watched during the view.
+    pub fn watched_ms(&self) -> i32 {
+        self.watched.0.iter().map(|(x, y)| y - x).sum()
+    }
+
+    /// Returns the analytics of a capsule, as seen by a user.
+    ///
+    /// The analytics are aggregated over all the plays, only the teachers of a group see the
+    /// progress of each of their students.
+    pub async fn analytics(capsule: &Capsule, user: &User, db: &Db) -> Result<Value> {
+        let views = capsule.views(&db).await?;
+        let duration = capsule.duration_ms.max(0);
+
+        let ratio = |view: &View| {
+            if duration == 0 {
+                0.0
+            } else {
+                (view.watched_ms() as f64 / duration as f64).min(1.0)
+            }
+        };
+
+        let plays = views.len();
+        let completed = views
+            .iter()
+            .filter(|x| ratio(x) >= COMPLETION_RATIO)
+            .count();
+
+        let average = |x: f64| if plays == 0 { 0.0 } else { x / plays as f64 };
+
+        // Per slide drop-off: the proportion of plays that watched at least part of each slide.
+        let slides = capsule
+            .slide_timeline()
+            .into_iter()
+            .map(|span| {
+                let reached = views
+                    .iter()
+                    .filter(|x| covered(&x.watched.0, span.start, span.end) > 0)
+                    .count();
+
+                json!({
+                    "gos": span.gos,
+                    "slide": span.slide,
+                    "start": span.start,
+                    "end": span.end,
+                    "plays": reached,
+                    "retention": average(reached as f64),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Only the students of the groups taught by the user are listed, all the other plays are
+        // only counted.
+        let mut students = HashSet::new();
+        for (group, role) in user.groups(&db).await? {
+            if role != ParticipantRole::Teacher {
+                continue;
+            }
+
+            for (participant, role) in group.participants(&db).await? {
+                if role == ParticipantRole::Student {
+                    students.insert(participant.id);
+                }
+            }
+        }
+
+        let mut viewers: HashMap<i32, Vec<&View>> = HashMap::new();
+        let mut anonymous_plays = 0;
+
+        for view in &views {
+            match view.user_id {
+                Some(id) if students.contains(&id) => {
+                    viewers.entry(id).or_insert_with(Vec::new).push(view)
+                }
+                _ => anonymous_plays += 1,
+            }
+        }
+
+        let mut users = vec![];
+        for (id, views) in viewers {
+            let user = match User::get_by_id(id, &db).await? {
+                Some(user) => user,
+                // The views of a user are deleted with their account.
+                None => continue,
+            };
+
+            let best = views.iter().map(|x| ratio(x)).fold(0.0, f64::max);
+            let last_seen = views.iter().map(|x| x.last_seen).max();
+
+            users.push(json!({
+                "username": user.username,
+                "plays": views.len(),
+                "watched": best,
+                "completed": best >= COMPLETION_RATIO,
+                "last_seen": last_seen.map(|x| x.timestamp()),
+            }));
+        }
+
+        Ok(json!({
+            "duration_ms": duration,
+            "plays": plays,
+            "anonymous_plays": anonymous_plays,
+            "completion_rate": average(completed as f64),
+            "average_watched": average(views.iter().map(|x| ratio(x)).sum()),
+            "slides": slides,
+            "viewers": users,
+        }))
+    }
+}
+
+/// The progress of a logged in user watching a capsule.
+///
+/// There is at most one progress per user and capsule, enforced by the database, which accumulates
+/// all their views.
+#[ergol]
+pub struct WatchProgress {
+    /// The id of the progress.
+    #[id]
+    pub id: i32,
+
+    /// The user watching the capsule.
+    #[many_to_one(watch_progress)]
+    pub user: User,
+
+    /// The capsule being watched.
+    #[many_to_one(watch_progress)]
+    pub capsule: Capsule,
+
+    /// The sorted and disjoint ranges of the video that were watched, in ms.
+    pub watched: Json<Vec<(i32, i32)>>,
+
+    /// The furthest position reached in the video, in ms.
+    pub position: i32,
+
+    /// The moment the user last watched the capsule.
+    pub updated: NaiveDateTime,
+}
+
+impl WatchProgress {
+    /// Returns the progress of a user on a capsule, if they watched it.
+    pub async fn get(user: &User, capsule: &Capsule, db: &Db) -> Result<Option<WatchProgress>> {
+        let row = db
+            .query_opt(
+                "SELECT id FROM watch_progresss WHERE \"user\" = $1 AND capsule = $2",
+                &[&user.id, &capsule.id],
+            )
+            .await?;
+
+        match row {
+            Some(row) => Ok(WatchProgress::get_by_id(row.get(0), &db).await?),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the progress of the users that watched a capsule, by user id.
+    pub async fn get_all(capsule: &Capsule, db: &Db) -> Result<HashMap<i32, WatchProgress>> {
+        let users = db
+            .query(
+                "SELECT id, \"user\" FROM watch_progresss WHERE capsule = $1",
+                &[&capsule.id],
+            )
+            .await?
+            .into_iter()
+            .map(|row| (row.get::<_, i32>(0), row.get::<_, i32>(1)))
+            .collect::<HashMap<_, _>>();
+
+        Ok(capsule
+            .watch_progress(&db)
+            .await?
+            .into_iter()
+            .filter_map(|x| users.get(&x.id).map(|user| (*user, x)))
+            .collect())
+    }
+
+    /// Adds watched ranges to the progress of a user on a capsule.
+    ///
+    /// The progress is upserted, so that concurrent beacons never create two progresses for the
+    /// same user and capsule.
+    pub async fn record(
+        user: &User,
+        capsule: &Capsule,
+        ranges: &[(i32, i32)],
+        db: &Db,
+    ) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let position = ranges.iter().map(|x| x.1).max().unwrap_or(0);
+
+        let mut watched = match WatchProgress::get(user, capsule, db).await? {
+            Some(progress) => progress.watched.0,
+            None => vec![],
+        };
+        watched.extend_from_slice(ranges);
+
+        db.query(
+            "INSERT INTO watch_progresss (\"user\", capsule, watched, position, updated) \
+             VALUES ($1, $2, $3, $4, $5) \
+             ON CONFLICT (\"user\", capsule) DO UPDATE SET watched = EXCLUDED.watched, \
+             position = GREATEST(watch_progresss.position, EXCLUDED.position), \
+             updated = EXCLUDED.updated",
+            &[
+                &user.id,
+                &capsule.id,
+                &Json(merge_ranges(watched)),
+                &position,
+                &now,
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the ratio of the video that was watched, between 0 and 1.
+    pub fn ratio(&self, duration_ms: i32) -> f64 {
+        if duration_ms <= 0 {
+            return 0.0;
+        }
+
+        let watched: i32 = self.watched.0.iter().map(|(x, y)| y - x).sum();
+        (watched as f64 / duration_ms as f64).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{covered, merge_ranges, unwatched_parts};
+
+    #[test]
+    fn merge_sorts_and_joins_ranges() {
+        assert_eq!(
+            merge_ranges(vec![(50, 60), (0, 10), (5, 20), (20, 30), (40, 40)]),
+            vec![(0, 30), (50, 60)]
+        );
+        assert_eq!(merge_ranges(vec![(10, 0)]), vec![]);
+    }
+
+    #[test]
+    fn covered_counts_the_overlap() {
+        let ranges = [(0, 10), (20, 30)];
+        assert_eq!(covered(&ranges, 5, 25), 10);
+        assert_eq!(covered(&ranges, 10, 20), 0);
+    }
+
+    #[test]
+    fn unwatched_parts_skip_the_watched_ranges() {
+        let watched = [(10, 20), (30, 40)];
+        assert_eq!(
+            unwatched_parts(&watched, vec![(0, 50)], 1000),
+            vec![(0, 10), (20, 30), (40, 50)]
+        );
+        assert_eq!(unwatched_parts(&watched, vec![(12, 18)], 1000), vec![]);
+    }
+
+    #[test]
+    fn unwatched_parts_are_capped_by_the_budget() {
+        assert_eq!(
+            unwatched_parts(&[(10, 20)], vec![(0, 50)], 15),
+            vec![(0, 10), (20, 25)]
+        );
+        assert_eq!(unwatched_parts(&[], vec![(0, 3_600_000)], 0), vec![]);
+        assert_eq!(
+            unwatched_parts(&[], vec![(0, 10), (5, 30)], i64::MAX),
+            vec![(0, 30)]
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! This module contains the generation of the HLS playlists of the publications.
+//!
+//! The publication script encodes each rendition of a publication profile in its own directory,
+//! with its own media playlist, and the master playlist that lists them is written here so that it
+//! always matches the profile used.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use tokio::fs::write;
+use tokio::process::Command;
+
+use rocket::http::Status;
+
+use crate::config::{PublicationProfile, Rendition};
+use crate::{Error, Result};
+
+/// The RFC 6381 name of the audio codec of every rendition.
+const AUDIO_CODEC: &str = "mp4a.40.2";
+
+/// The name of the group of the subtitles in the master playlist.
+const SUBTITLES_GROUP: &str = "subs";
+
+/// Reads the width and the height of the video of a rendition from its media playlist.
+///
+/// The renditions keep the aspect ratio of the capsule, so their width depends on the video and
+/// not only on their height.
+async fn video_size(playlist: &Path) -> Result<(u32, u32)> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-select_streams", "v:0"])
+        .args(["-show_entries", "stream=width,height", "-of", "csv=p=0"])
+        .arg(playlist)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        error!("Failed to read the size of {}", playlist.display());
+        return Err(Error(Status::InternalServerError));
+    }
+
+    let output = String::from_utf8_lossy(&output.stdout);
+    output
+        .trim()
+        .split_once(',')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .ok_or(Error(Status::InternalServerError))
+}
+
+/// Returns the bandwidth of a rendition in bit/s, with some margin for the container.
+fn bandwidth(rendition: &Rendition) -> u32 {
+    let kbps = match rendition.height {
+        Some(_) => rendition.video_bitrate + rendition.audio_bitrate,
+        None => rendition.audio_bitrate,
+    };
+
+    kbps * 1100
+}
+
+/// Generates the master playlist of a publication.
+///
+/// The video renditions come first in the order of the profile, since players start with the first
+/// variant, and the audio only renditions come last. The sizes give the width and the height of the
+/// video renditions by name.
+pub fn master_playlist(
+    profile: &PublicationProfile,
+    sizes: &HashMap<String, (u32, u32)>,
+    subtitles: bool,
+) -> String {
+    let mut playlist = String::from("#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-INDEPENDENT-SEGMENTS\n");
+
+    if subtitles {
+        writeln!(
+            playlist,
+            "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"{}\",NAME=\"Subtitles\",DEFAULT=NO,AUTOSELECT=YES,URI=\"subtitles.m3u8\"",
+            SUBTITLES_GROUP
+        )
+        .ok();
+    }
+
+    let video = profile.renditions.iter().filter(|x| x.height.is_some());
+    let audio = profile.renditions.iter().filter(|x| x.height.is_none());
+
+    for rendition in video.chain(audio) {
+        write!(
+            playlist,
+            "#EXT-X-STREAM-INF:BANDWIDTH={}",
+            bandwidth(rendition)
+        )
+        .ok();
+
+        if rendition.height.is_some() {
+            if let Some((width, height)) = sizes.get(&rendition.name) {
+                write!(playlist, ",RESOLUTION={}x{}", width, height).ok();
+            }
+
+            write!(
+                playlist,
+                ",CODECS=\"{},{}\"",
+                profile.codec.rfc6381(),
+                AUDIO_CODEC
+            )
+            .ok();
+        } else {
+            write!(playlist, ",CODECS=\"{}\"", AUDIO_CODEC).ok();
+        }
+
+        if subtitles {
+            write!(playlist, ",SUBTITLES=\"{}\"", SUBTITLES_GROUP).ok();
+        }
+
+        writeln!(playlist, "\n{}/index.m3u8", rendition.name).ok();
+    }
+
+    playlist
+}
+
+/// Generates the media playlist of the subtitles, made of a single WebVTT file covering the whole
+/// video.
+pub fn subtitles_playlist(duration_ms: i32) -> String {
+    let duration = duration_ms.max(0) as f64 / 1000.0;
+
+    format!(
+        "#EXTM3U\n\
+         #EXT-X-VERSION:3\n\
+         #EXT-X-PLAYLIST-TYPE:VOD\n\
+         #EXT-X-TARGETDURATION:{}\n\
+         #EXT-X-MEDIA-SEQUENCE:0\n\
+         #EXTINF:{:.3},\n\
+         subtitles.webvtt\n\
+         #EXT-X-ENDLIST\n",
+        duration.ceil() as u64,
+        duration
+    )
+}
+
+/// Writes the master playlist of a publication, and the playlist of its subtitles if any, in its
+/// output directory.
+pub async fn write_playlists(
+    output: &Path,
+    profile: &PublicationProfile,
+    subtitles: bool,
+    duration_ms: i32,
+) -> Result<()> {
+    let mut sizes = HashMap::new();
+
+    for rendition in profile.renditions.iter().filter(|x| x.height.is_some()) {
+        let path = output.join(&rendition.name).join("index.m3u8");
+        sizes.insert(rendition.name.clone(), video_size(&path).await?);
+    }
+
+    write(
+        output.join("manifest.m3u8"),
+        master_playlist(profile, &sizes, subtitles),
+    )
+    .await?;
+
+    if subtitles {
+        write(
+            output.join("subtitles.m3u8"),
+            subtitles_playlist(duration_ms),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{master_playlist, subtitles_playlist};
+    use crate::config::{PublicationProfile, Rendition, VideoCodec};
+
+    /// Creates a rendition, audio only if it has no height.
+    fn rendition(
+        name: &str,
+        height: Option<u32>,
+        video_bitrate: u32,
+        audio_bitrate: u32,
+    ) -> Rendition {
+        Rendition {
+            name: String::from(name),
+            height,
+            video_bitrate,
+            audio_bitrate,
+        }
+    }
+
+    /// Creates a profile whose audio only rendition comes first.
+    fn profile() -> PublicationProfile {
+        PublicationProfile {
+            codec: VideoCodec::H264,
+            segment_duration: 6,
+            renditions: vec![
+                rendition("audio", None, 0, 64),
+                rendition("360p", Some(360), 800, 96),
+                rendition("720p", Some(720), 2800, 128),
+            ],
+        }
+    }
+
+    #[test]
+    fn videos_come_before_audio() {
+        let mut sizes = HashMap::new();
+        sizes.insert(String::from("360p"), (640, 360));
+
+        assert_eq!(
+            master_playlist(&profile(), &sizes, false),
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=985600,RESOLUTION=640x360,CODECS=\"avc1.640028,mp4a.40.2\"\n\
+             360p/index.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=3220800,CODECS=\"avc1.640028,mp4a.40.2\"\n\
+             720p/index.m3u8\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=70400,CODECS=\"mp4a.40.2\"\n\
+             audio/index.m3u8\n"
+        );
+    }
+
+    #[test]
+    fn codec_of_the_profile_is_announced() {
+        let mut profile = profile();
+        profile.codec = VideoCodec::Av1;
+        let playlist = master_playlist(&profile, &HashMap::new(), false);
+
+        assert!(playlist.contains("CODECS=\"av01.0.08M.08,mp4a.40.2\"\n360p/index.m3u8"));
+        assert!(playlist.contains("CODECS=\"mp4a.40.2\"\naudio/index.m3u8"));
+    }
+
+    #[test]
+    fn every_variant_has_the_subtitles() {
+        let playlist = master_playlist(&profile(), &HashMap::new(), true);
+
+        assert!(playlist.contains(
+            "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",NAME=\"Subtitles\",DEFAULT=NO,AUTOSELECT=YES,URI=\"subtitles.m3u8\"\n"
+        ));
+
+        let variants = playlist
+            .lines()
+            .filter(|x| x.starts_with("#EXT-X-STREAM-INF"))
+            .collect::<Vec<_>>();
+
+        assert_eq!(variants.len(), 3);
+        assert!(variants.iter().all(|x| x.ends_with(",SUBTITLES=\"subs\"")));
+    }
+
+    #[test]
+    fn subtitles_cover_the_whole_video() {
+        let playlist = subtitles_playlist(61_500);
+        assert!(playlist.contains("#EXT-X-TARGETDURATION:62\n"));
+        assert!(playlist.contains("#EXTINF:61.500,\nsubtitles.webvtt\n"));
+
+        let playlist = subtitles_playlist(-1);
+        assert!(playlist.contains("#EXT-X-TARGETDURATION:0\n"));
+        assert!(playlist.contains("#EXTINF:0.000,\n"));
+    }
+}
@@ -5,13 +5,22 @@
 #[macro_use]
 extern crate rocket;
 
+pub mod blobs;
 pub mod command;
 pub mod config;
+pub mod csv;
 pub mod db;
+pub mod hls;
 pub mod log_fairing;
 pub mod mailer;
+pub mod ods;
+pub mod rate_limit;
 pub mod routes;
+pub mod scheduler;
+pub mod signing;
+pub mod storage;
 pub mod templates;
+pub mod timeline;
 pub mod websockets;
 
 use std::error::Error as StdError;
@@ -43,7 +52,10 @@ use rocket::{Ignite, Rocket, State};
 
 use crate::command::run_command;
 use crate::config::Config;
+use crate::db::export::Export;
 use crate::db::group::populate_db;
+use crate::rate_limit::RateLimiter;
+use crate::signing::SegmentSigner;
 use crate::websockets::{websocket, WebSockets};
 
 lazy_static! {
@@ -98,6 +110,9 @@ impl_from_error!(bcrypt::BcryptError);
 impl_from_error!(tungstenite::Error);
 impl_from_error!(std::str::Utf8Error);
 impl_from_error!(std::num::ParseIntError);
+impl_from_error!(zip::result::ZipError);
+impl_from_error!(s3::error::S3Error);
+impl_from_error!(s3::creds::error::CredentialsError);
 
 /// A wrapper for a database connection extrated from a pool.
 pub struct Db(Object<ergol::pool::Manager>);
@@ -304,19 +319,13 @@ pub async fn user_disk_usage() {
 
         let path = &config.data_path.join(format!("{}", capsule.id));
 
-        let output = run_command(&vec!["../scripts/psh", "du", path.to_str().unwrap()]);
-        match &output {
-            Ok(o) => {
-                for line in std::str::from_utf8(&o.stdout)
-                    .map_err(|_| Error(Status::InternalServerError))
-                    .unwrap()
-                    .lines()
-                {
-                    let du = line.parse::<i32>().unwrap();
-                    if du != capsule.disk_usage {
-                        capsule.disk_usage = du;
-                        capsule.save(&db).await.unwrap();
-                    }
+        // Files shared with other capsules are split between them, the usage is in MiB like du.
+        match blobs::disk_usage(path).await {
+            Ok(bytes) => {
+                let du = ((bytes + (1 << 20) - 1) >> 20) as i32;
+                if du != capsule.disk_usage {
+                    capsule.disk_usage = du;
+                    capsule.save(&db).await.unwrap();
                 }
             }
             Err(_) => println!("error"),
@@ -417,6 +426,23 @@ pub async fn rocket() -> StdResult<Rocket<Ignite>, rocket::Error> {
         .attach(AdHoc::on_ignite("WebSockets", |rocket| async move {
             rocket.manage(WebSockets::new())
         }))
+        .attach(AdHoc::on_ignite("RateLimiter", |rocket| async move {
+            rocket.manage(RateLimiter::new())
+        }))
+        .attach(AdHoc::on_ignite("SegmentSigner", |rocket| async move {
+            let config = Config::from_rocket(&rocket);
+            rocket.manage(SegmentSigner::from_config(&config))
+        }))
+        .attach(AdHoc::try_on_ignite("Storage", |rocket| async move {
+            let config = Config::from_rocket(&rocket);
+            match storage::open(&config) {
+                Ok(_) => Ok(rocket),
+                Err(e) => {
+                    error!("Failed to open the storage: {}", e);
+                    Err(rocket)
+                }
+            }
+        }))
         .attach(AdHoc::on_ignite("Semaphore", |rocket| async move {
             let config = config::Config::from_rocket(&rocket);
             rocket.manage(Arc::new(Semaphore::new(config.concurrent_tasks)))
@@ -448,7 +474,21 @@ pub async fn rocket() -> StdResult<Rocket<Ignite>, rocket::Error> {
                 routes::user::validate_email,
                 routes::user::validate_invitation,
                 routes::watch::watch,
+                routes::watch::watch_with_password,
                 routes::watch::watch_asset,
+                routes::watch::oembed,
+                routes::watch::view_beacon,
+                routes::watch::answer_quiz,
+                routes::comment::comments,
+                routes::comment::new_comment,
+                routes::comment::moderate_comment,
+                routes::comment::delete_comment,
+                routes::watch::exports,
+                routes::watch::request_export,
+                routes::watch::export_status,
+                routes::watch::download_export,
+                routes::feed::feed,
+                routes::feed::feed_file,
                 routes::watch::polymny_video,
             ],
         )
@@ -501,8 +541,15 @@ pub async fn rocket() -> StdResult<Rocket<Ignite>, rocket::Error> {
                 routes::capsule::empty_capsule,
                 routes::capsule::new_capsule,
                 routes::capsule::edit_capsule,
+                routes::capsule::publication_profiles,
                 routes::capsule::delete_capsule,
                 routes::capsule::delete_project,
+                routes::project::get_project,
+                routes::project::new_project,
+                routes::project::edit_project,
+                routes::project::invite_project,
+                routes::project::change_project_role,
+                routes::project::deinvite_project,
                 routes::capsule::upload_record,
                 routes::capsule::delete_record,
                 routes::capsule::upload_pointer,
@@ -522,16 +569,40 @@ pub async fn rocket() -> StdResult<Rocket<Ignite>, rocket::Error> {
                 routes::capsule::change_role,
                 routes::capsule::leave,
                 routes::capsule::sound_track,
+                routes::capsule::new_share_token,
+                routes::capsule::delete_share_token,
+                routes::capsule::analytics,
+                routes::capsule::quiz_results,
                 routes::notification::mark_as_read,
                 routes::notification::delete,
                 routes::group::new_group,
                 routes::group::delete_group,
                 routes::group::add_participant,
                 routes::group::remove_participant,
+                routes::group::new_group_invite,
+                routes::group::delete_group_invite,
+                routes::group::join_group,
+                routes::group::import_roster,
                 routes::group::new_assignment,
                 routes::group::delete_assignment,
                 routes::group::validate_assignment,
                 routes::group::validate_answer,
+                routes::group::assignment_dates,
+                routes::group::extend_deadline,
+                routes::group::open_evaluation,
+                routes::group::evaluate_answer,
+                routes::group::close_assignment,
+                routes::group::configure_peer_review,
+                routes::group::peer_reviews,
+                routes::group::grades,
+                routes::group::set_rubric,
+                routes::group::new_assignment_template,
+                routes::group::assignment_templates,
+                routes::group::delete_assignment_template,
+                routes::group::instantiate_template,
+                routes::group::group_progress,
+                routes::group::group_quizzes,
+                routes::group::grade_book,
                 routes::admin::get_dashboard,
                 routes::admin::get_users,
                 routes::admin::get_search_users,
@@ -540,6 +611,7 @@ pub async fn rocket() -> StdResult<Rocket<Ignite>, rocket::Error> {
                 routes::admin::get_search_capsules,
                 routes::admin::request_invite_user,
                 routes::admin::delete_user,
+                routes::admin::get_audit_log,
                 routes::admin::clear_websockets,
             ],
         )
@@ -551,5 +623,14 @@ pub async fn rocket() -> StdResult<Rocket<Ignite>, rocket::Error> {
     let pool = rocket.state::<Pool>().unwrap();
     tokio::spawn(websocket(socks.clone(), pool.clone()));
 
+    // The exports that were being generated when the server stopped will never end.
+    let db = Db::from_pool(pool.clone()).await.unwrap();
+    if let Err(e) = Export::fail_interrupted(&db).await {
+        error!("Failed to reset the interrupted exports: {}", e);
+    }
+
+    let config = rocket.state::<Config>().unwrap();
+    tokio::spawn(scheduler::run(socks.clone(), pool.clone(), config.clone()));
+
     rocket.launch().await
 }
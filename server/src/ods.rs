@@ -0,0 +1,185 @@
+//! This module contains helpers to write OpenDocument spreadsheets.
+
+use std::fmt;
+use std::io::{Cursor, Write};
+
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::Result;
+
+/// The mime type of OpenDocument spreadsheets.
+pub const MIME_TYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+
+/// The manifest of the archive, that lists the files of the spreadsheet.
+const MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">
+ <manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>
+ <manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>
+</manifest:manifest>
+"#;
+
+/// A cell of a spreadsheet.
+#[derive(Debug, Clone)]
+pub enum Cell {
+    /// An empty cell.
+    Empty,
+
+    /// A cell containing text.
+    Text(String),
+
+    /// A cell containing a number.
+    Float(f64),
+}
+
+impl fmt::Display for Cell {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cell::Empty => Ok(()),
+            Cell::Text(text) => write!(f, "{}", text),
+            Cell::Float(x) => write!(f, "{}", (x * 100.0).round() / 100.0),
+        }
+    }
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Cell {
+        Cell::Text(text.to_string())
+    }
+}
+
+impl From<String> for Cell {
+    fn from(text: String) -> Cell {
+        Cell::Text(text)
+    }
+}
+
+impl From<f64> for Cell {
+    fn from(x: f64) -> Cell {
+        Cell::Float(x)
+    }
+}
+
+/// Escapes text so it can be put in an XML document.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Writes the content of a spreadsheet with a single sheet.
+fn content(sheet: &str, rows: &[Vec<Cell>]) -> String {
+    let mut content = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" office:version="1.2">
+<office:body><office:spreadsheet>"#,
+    );
+
+    // Some characters are not allowed in the names of sheets.
+    let sheet = sheet.replace(|c| "[]*?:/\\".contains(c), " ");
+    content.push_str(&format!(r#"<table:table table:name="{}">"#, escape(&sheet)));
+
+    for row in rows {
+        content.push_str("<table:table-row>");
+
+        for cell in row {
+            match cell {
+                Cell::Empty => content.push_str("<table:table-cell/>"),
+                Cell::Text(text) => content.push_str(&format!(
+                    r#"<table:table-cell office:value-type="string"><text:p>{}</text:p></table:table-cell>"#,
+                    escape(text)
+                )),
+                Cell::Float(x) => content.push_str(&format!(
+                    r#"<table:table-cell office:value-type="float" office:value="{}"><text:p>{}</text:p></table:table-cell>"#,
+                    x, cell
+                )),
+            }
+        }
+
+        content.push_str("</table:table-row>");
+    }
+
+    content
+        .push_str("</table:table></office:spreadsheet></office:body></office:document-content>\n");
+    content
+}
+
+/// Writes a spreadsheet with a single sheet.
+pub fn write(sheet: &str, rows: &[Vec<Cell>]) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+    // The mime type must be the first file of the archive, and must not be compressed.
+    zip.start_file(
+        "mimetype",
+        FileOptions::default().compression_method(CompressionMethod::Stored),
+    )?;
+    zip.write_all(MIME_TYPE.as_bytes())?;
+
+    zip.start_file("META-INF/manifest.xml", FileOptions::default())?;
+    zip.write_all(MANIFEST.as_bytes())?;
+
+    zip.start_file("content.xml", FileOptions::default())?;
+    zip.write_all(content(sheet, rows).as_bytes())?;
+
+    Ok(zip.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use zip::{CompressionMethod, ZipArchive};
+
+    use super::{content, write, Cell, MIME_TYPE};
+
+    #[test]
+    fn numbers_are_shown_with_two_decimals() {
+        assert_eq!(Cell::Float(2.0 / 3.0).to_string(), "0.67");
+        assert_eq!(Cell::Float(10.0).to_string(), "10");
+        assert_eq!(Cell::Empty.to_string(), "");
+    }
+
+    #[test]
+    fn content_is_escaped() {
+        let rows = vec![vec![
+            Cell::from("<b>Tom & Jerry</b>"),
+            Cell::from(2.0 / 3.0),
+            Cell::Empty,
+        ]];
+
+        let content = content("Grades 1/2", &rows);
+
+        assert!(content.contains(r#"<table:table table:name="Grades 1 2">"#));
+        assert!(content.contains("<text:p>&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;</text:p>"));
+        assert!(content.contains(&format!(
+            r#"office:value-type="float" office:value="{}"><text:p>0.67</text:p>"#,
+            2.0 / 3.0
+        )));
+        assert!(content.contains("<table:table-cell/></table:table-row>"));
+    }
+
+    #[test]
+    fn mime_type_comes_first_uncompressed() {
+        let bytes = write("Grades", &[vec![Cell::from("Alice"), Cell::from(12.0)]]).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+
+        let mut mime_type = String::new();
+        let mut file = archive.by_index(0).unwrap();
+        assert_eq!(file.name(), "mimetype");
+        assert_eq!(file.compression(), CompressionMethod::Stored);
+        file.read_to_string(&mut mime_type).unwrap();
+        assert_eq!(mime_type, MIME_TYPE);
+        drop(file);
+
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains("<text:p>Alice</text:p>"));
+        assert!(archive.by_name("META-INF/manifest.xml").is_ok());
+    }
+}
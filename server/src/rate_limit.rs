@@ -0,0 +1,532 @@
+//! This module contains the rate limiter that protects the authentication routes.
+//!
+//! Failed logins are tracked per account and ip address pair, and per ip address. After
+//! `login_free_attempts` failures, each new attempt must wait for a delay that doubles at every
+//! failure, and after `login_max_attempts` failures, the key is locked for
+//! `login_lockout_duration` seconds.
+//!
+//! Failed logins are also tracked per account, whatever the ip address, so that an attacker cannot
+//! guess a password from many ip addresses. This key is never delayed, and is only locked after
+//! `login_account_max_attempts` failures, so that nobody can easily lock someone else out of their
+//! account.
+//!
+//! Wrong passwords of share links are failures as well, tracked per share token and ip address
+//! pair, and per ip address, and so are wrong codes to join a group, tracked per user and per ip
+//! address.
+//!
+//! Password reset requests are not failures: they are counted separately, and at most
+//! `reset_max_requests` of them are accepted per email and per ip address during
+//! `login_lockout_duration` seconds. Accounts created by importing rosters are counted the same
+//! way, at most `roster_max_invitations` per user, and so are the answers to the quizzes, at most
+//! `quiz_max_answers` per ip address, and the beacons of the player, at most `view_max_beacons` per
+//! ip address. The comments themselves are not limited, but at most
+//! `comment_max_notifications` of them notify the owner of a capsule per author.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use lazy_static::lazy_static;
+
+use chrono::{Duration, NaiveDateTime, Utc};
+
+use tokio::sync::Mutex;
+
+use ergol::prelude::*;
+
+use rocket::http::Status;
+use rocket::serde::json::json;
+
+use crate::config::Config;
+use crate::db::audit::{AuditAction, AuditLog, Target};
+use crate::db::lockout::LoginLockout;
+use crate::db::user::User;
+use crate::{Db, Error, Result};
+
+lazy_static! {
+    /// A hash against which passwords are verified when the user does not exist.
+    ///
+    /// This makes the authentication of unknown users take as long as the one of existing users.
+    static ref DUMMY_HASH: String = bcrypt::hash("polymny", bcrypt::DEFAULT_COST).unwrap();
+}
+
+/// Verifies a password against a dummy hash, to mitigate timing attacks.
+pub fn dummy_verify(password: &str) {
+    bcrypt::verify(password, &DUMMY_HASH).ok();
+}
+
+/// The limits applied to the failures of a key.
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    /// The number of failures allowed before delays are applied.
+    free_attempts: u32,
+
+    /// The number of failures after which the key is locked.
+    max_attempts: u32,
+
+    /// The duration of a lockout.
+    lockout_duration: Duration,
+}
+
+impl Limits {
+    /// Returns the limits of a key returned by `keys`, `share_keys` or `join_keys`.
+    fn of(key: &str, config: &Config) -> Limits {
+        let lockout_duration = Duration::seconds(config.login_lockout_duration as i64);
+
+        // The key of an account is shared by all ip addresses, so it is never delayed.
+        if key.starts_with("account:") {
+            Limits {
+                free_attempts: config.login_account_max_attempts,
+                max_attempts: config.login_account_max_attempts,
+                lockout_duration,
+            }
+        } else {
+            Limits {
+                free_attempts: config.login_free_attempts,
+                max_attempts: config.login_max_attempts,
+                lockout_duration,
+            }
+        }
+    }
+
+    /// Returns the delay a key must wait after its last failure.
+    fn delay(&self, failures: u32) -> Duration {
+        if failures < self.free_attempts {
+            return Duration::zero();
+        }
+
+        let exponent = (failures - self.free_attempts).min(16);
+        let seconds = (1_i64 << exponent).min(self.lockout_duration.num_seconds());
+        Duration::seconds(seconds)
+    }
+}
+
+/// The failed attempts recorded for a key.
+#[derive(Debug, Clone)]
+struct Attempts {
+    /// The number of consecutive failures.
+    failures: u32,
+
+    /// The moment of the last failure.
+    last_failure: NaiveDateTime,
+
+    /// The moment until which the key is locked, if any.
+    locked_until: Option<NaiveDateTime>,
+}
+
+impl Attempts {
+    /// Creates attempts without failures.
+    fn new(now: NaiveDateTime) -> Attempts {
+        Attempts {
+            failures: 0,
+            last_failure: now,
+            locked_until: None,
+        }
+    }
+
+    /// Returns true if the key is locked or delayed.
+    fn blocked(&self, limits: Limits, now: NaiveDateTime) -> bool {
+        self.locked_until.map(|x| x > now).unwrap_or(false)
+            || self.last_failure + limits.delay(self.failures) > now
+    }
+
+    /// Returns true if the attempts can be forgotten.
+    fn expired(&self, limits: Limits, now: NaiveDateTime) -> bool {
+        self.last_failure + limits.lockout_duration <= now
+            && self.locked_until.map(|x| x <= now).unwrap_or(true)
+    }
+
+    /// Records a failure, and returns the end of the lockout if it locks the key.
+    fn fail(&mut self, limits: Limits, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        // A lockout that expired gives a fresh start.
+        if self.locked_until.map(|x| x <= now).unwrap_or(false) {
+            self.failures = 0;
+            self.locked_until = None;
+        }
+
+        self.failures += 1;
+        self.last_failure = now;
+
+        if self.failures >= limits.max_attempts {
+            let locked_until = now + limits.lockout_duration;
+            self.locked_until = Some(locked_until);
+            Some(locked_until)
+        } else {
+            None
+        }
+    }
+}
+
+/// The requests recorded for a key.
+#[derive(Debug, Clone)]
+struct Requests {
+    /// The number of requests since the start of the window.
+    count: u32,
+
+    /// The moment the window started.
+    start: NaiveDateTime,
+}
+
+/// The struct that keeps track of failed attempts and requests in memory.
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// The failed attempts of each key.
+    failures: Arc<Mutex<HashMap<String, Attempts>>>,
+
+    /// The requests of each key, which are not failures.
+    requests: Arc<Mutex<HashMap<String, Requests>>>,
+}
+
+impl RateLimiter {
+    /// Creates a new empty rate limiter.
+    pub fn new() -> RateLimiter {
+        RateLimiter {
+            failures: Arc::new(Mutex::new(HashMap::new())),
+            requests: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Returns the keys that identify a login attempt.
+    ///
+    /// Accounts are quickly locked for the ip address that failed to log in, and only after many
+    /// more failures for every ip address.
+    pub fn keys(ip: Option<IpAddr>, account: &str) -> Vec<String> {
+        let account = account.trim().to_lowercase();
+        match ip {
+            Some(ip) => vec![
+                format!("account-ip:{}/{}", ip, account),
+                format!("ip:{}", ip),
+                format!("account:{}", account),
+            ],
+            None => vec![format!("account:{}", account)],
+        }
+    }
+
+    /// Returns the account of a key returned by `keys`, if any.
+    fn account(key: &str) -> Option<&str> {
+        match key.strip_prefix("account-ip:") {
+            Some(key) => key.split_once('/').map(|(_, x)| x),
+            None => key.strip_prefix("account:"),
+        }
+    }
+
+    /// Returns the keys that identify an attempt to unlock a share token with a password.
+    pub fn share_keys(ip: Option<IpAddr>, token: &str) -> Vec<String> {
+        match ip {
+            Some(ip) => vec![format!("share:{}/{}", ip, token), format!("ip:{}", ip)],
+            None => vec![format!("share:{}", token)],
+        }
+    }
+
+    /// Returns the keys that identify an attempt to join a group with a code.
+    pub fn join_keys(ip: Option<IpAddr>, user_id: i32) -> Vec<String> {
+        let mut keys = vec![format!("join:{}", user_id)];
+        if let Some(ip) = ip {
+            keys.push(format!("ip:{}", ip));
+        }
+        keys
+    }
+
+    /// Returns the keys that identify a password reset request.
+    pub fn reset_keys(ip: Option<IpAddr>, email: &str) -> Vec<String> {
+        let mut keys = vec![format!("reset:{}", email.trim().to_lowercase())];
+        if let Some(ip) = ip {
+            keys.push(format!("reset-ip:{}", ip));
+        }
+        keys
+    }
+
+    /// Checks that none of the keys is locked or delayed.
+    pub async fn check(&self, keys: &[String], config: &Config, db: &Db) -> Result<()> {
+        let now = Utc::now().naive_utc();
+
+        {
+            let map = self.failures.lock().await;
+            for key in keys {
+                if let Some(attempts) = map.get(key) {
+                    if attempts.blocked(Limits::of(key, config), now) {
+                        return Err(Error(Status::TooManyRequests));
+                    }
+                }
+            }
+        }
+
+        if config.login_persist_lockouts {
+            for key in keys {
+                if LoginLockout::locked_until(key, now, db).await?.is_some() {
+                    return Err(Error(Status::TooManyRequests));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records a failed attempt for each key, locking the keys that reached the maximum number of
+    /// failures.
+    pub async fn failure(&self, keys: &[String], config: &Config, db: &Db) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let mut locked = vec![];
+
+        {
+            let mut map = self.failures.lock().await;
+
+            // Forget about keys that have been quiet for long enough.
+            map.retain(|key, x| !x.expired(Limits::of(key, config), now));
+
+            for key in keys {
+                let attempts = map.entry(key.clone()).or_insert_with(|| Attempts::new(now));
+
+                if let Some(locked_until) = attempts.fail(Limits::of(key, config), now) {
+                    locked.push((key.clone(), attempts.failures, locked_until));
+                }
+            }
+        }
+
+        for (key, failures, locked_until) in locked {
+            warn!(
+                "Locking {} until {} after {} failures",
+                key, locked_until, failures
+            );
+            let target = match RateLimiter::account(&key) {
+                Some(account) => match User::get_by_username_or_email(account, db).await? {
+                    Some(user) => Target::user(user.id),
+                    None => Target::default(),
+                },
+                None => Target::default(),
+            };
+
+            AuditLog::record(
+                None,
+                AuditAction::LoginLockout,
+                target,
+                None,
+                Some(json!({
+                    "key": key,
+                    "failures": failures,
+                    "locked_until": locked_until.timestamp(),
+                })),
+                db,
+            )
+            .await?;
+
+            LoginLockout::create(key, failures as i32, now, locked_until)
+                .save(&db)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the failures of the keys after a successful attempt.
+    pub async fn success(&self, keys: &[String]) {
+        let mut map = self.failures.lock().await;
+        for key in keys {
+            map.remove(key);
+        }
+    }
+
+    /// Records a request for each key, and fails if one of them made more than `max` requests
+    /// during the lockout duration.
+    ///
+    /// Unlike failures, requests never lock a key: they are accepted again once the window ends.
+    pub async fn request(&self, keys: &[String], max: u32, config: &Config) -> Result<()> {
+        let now = Utc::now().naive_utc();
+        let window = Duration::seconds(config.login_lockout_duration as i64);
+
+        let mut map = self.requests.lock().await;
+        map.retain(|_, x| x.start + window > now);
+
+        if keys
+            .iter()
+            .any(|key| map.get(key).map(|x| x.count >= max).unwrap_or(false))
+        {
+            return Err(Error(Status::TooManyRequests));
+        }
+
+        for key in keys {
+            map.entry(key.clone())
+                .or_insert(Requests {
+                    count: 0,
+                    start: now,
+                })
+                .count += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Authenticates a user, recording the failure if the credentials are wrong.
+    ///
+    /// All failures return an unauthorized error, so that the response does not tell whether the
+    /// account exists or not.
+    pub async fn authenticate(
+        &self,
+        username: &str,
+        password: &str,
+        ip: Option<IpAddr>,
+        config: &Config,
+        db: &Db,
+    ) -> Result<User> {
+        let keys = RateLimiter::keys(ip, username);
+        self.check(&keys, config, db).await?;
+
+        match User::authenticate(username, password, db).await {
+            Ok(user) => {
+                self.success(&keys).await;
+                Ok(user)
+            }
+            Err(Error(Status::Unauthorized)) => {
+                self.failure(&keys, config, db).await?;
+                Err(Error(Status::Unauthorized))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+    use super::{Attempts, Limits, RateLimiter};
+
+    /// Returns the default limits of the keys of an ip address.
+    fn ip_limits() -> Limits {
+        Limits {
+            free_attempts: 3,
+            max_attempts: 10,
+            lockout_duration: Duration::seconds(900),
+        }
+    }
+
+    /// Returns the default limits of the keys of an account.
+    fn account_limits() -> Limits {
+        Limits {
+            free_attempts: 100,
+            max_attempts: 100,
+            lockout_duration: Duration::seconds(900),
+        }
+    }
+
+    /// Returns an arbitrary moment.
+    fn start() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap()
+    }
+
+    /// Records failures one second apart, and returns the moment of the last one.
+    fn fail(
+        attempts: &mut Attempts,
+        limits: Limits,
+        count: u32,
+        now: NaiveDateTime,
+    ) -> NaiveDateTime {
+        let mut now = now;
+        for _ in 0..count {
+            now += Duration::seconds(1);
+            attempts.fail(limits, now);
+        }
+        now
+    }
+
+    #[test]
+    fn keys_track_the_account_with_and_without_ip() {
+        let ip = "10.0.0.1".parse().ok();
+        assert_eq!(
+            RateLimiter::keys(ip, " Alice@Example.com "),
+            vec![
+                "account-ip:10.0.0.1/alice@example.com",
+                "ip:10.0.0.1",
+                "account:alice@example.com",
+            ]
+        );
+        assert_eq!(RateLimiter::keys(None, "alice"), vec!["account:alice"]);
+    }
+
+    #[test]
+    fn account_of_keys() {
+        for key in RateLimiter::keys("::1".parse().ok(), "alice") {
+            match key.as_str() {
+                "ip:::1" => assert_eq!(RateLimiter::account(&key), None),
+                _ => assert_eq!(RateLimiter::account(&key), Some("alice")),
+            }
+        }
+        assert_eq!(RateLimiter::account("account:a/b"), Some("a/b"));
+        assert_eq!(RateLimiter::account("share:10.0.0.1/token"), None);
+    }
+
+    #[test]
+    fn delays_double_after_free_attempts() {
+        assert_eq!(ip_limits().delay(2), Duration::zero());
+        assert_eq!(ip_limits().delay(3), Duration::seconds(1));
+        assert_eq!(ip_limits().delay(5), Duration::seconds(4));
+        assert_eq!(ip_limits().delay(40), Duration::seconds(900));
+        assert_eq!(account_limits().delay(99), Duration::zero());
+    }
+
+    #[test]
+    fn lockout_after_max_attempts() {
+        let mut attempts = Attempts::new(start());
+        let now = fail(&mut attempts, ip_limits(), 9, start());
+        assert!(attempts.locked_until.is_none());
+
+        let now = now + Duration::seconds(1000);
+        assert!(!attempts.blocked(ip_limits(), now));
+        assert_eq!(
+            attempts.fail(ip_limits(), now),
+            Some(now + Duration::seconds(900))
+        );
+        assert!(attempts.blocked(ip_limits(), now + Duration::seconds(899)));
+    }
+
+    #[test]
+    fn accounts_are_locked_after_more_attempts() {
+        let mut attempts = Attempts::new(start());
+        let now = fail(&mut attempts, account_limits(), 99, start());
+        assert!(!attempts.blocked(account_limits(), now));
+
+        assert!(attempts.fail(account_limits(), now).is_some());
+        assert!(attempts.blocked(account_limits(), now));
+    }
+
+    #[test]
+    fn unlock_after_lockout() {
+        let mut attempts = Attempts::new(start());
+        let now = fail(&mut attempts, ip_limits(), 10, start());
+        assert!(attempts.blocked(ip_limits(), now));
+        assert!(!attempts.expired(ip_limits(), now));
+
+        let now = now + Duration::seconds(900);
+        assert!(!attempts.blocked(ip_limits(), now));
+        assert!(attempts.expired(ip_limits(), now));
+
+        // The first failure after a lockout starts over.
+        assert_eq!(attempts.fail(ip_limits(), now), None);
+        assert_eq!(attempts.failures, 1);
+    }
+
+    #[rocket::async_test]
+    async fn success_forgets_failures() {
+        let limiter = RateLimiter::new();
+        let keys = RateLimiter::keys("10.0.0.1".parse().ok(), "alice");
+
+        {
+            let mut map = limiter.failures.lock().await;
+            for key in &keys {
+                let attempts = map
+                    .entry(key.clone())
+                    .or_insert_with(|| Attempts::new(start()));
+                fail(attempts, ip_limits(), 10, start());
+            }
+        }
+
+        limiter.success(&keys[..1]).await;
+
+        let map = limiter.failures.lock().await;
+        assert!(!map.contains_key(&keys[0]));
+        assert!(map.contains_key(&keys[1]));
+    }
+}
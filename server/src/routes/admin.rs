@@ -1,6 +1,6 @@
 //! This module contains the routes for admin management.
 
-use tokio::fs::remove_dir_all;
+use chrono::NaiveDateTime;
 
 use futures::{poll, task::Poll, StreamExt};
 
@@ -13,10 +13,12 @@ use rocket::State as S;
 use serde::{Deserialize, Serialize};
 
 use crate::config::Config;
+use crate::db::audit::{user_summary, AuditAction, AuditFilter, AuditLog, Target};
 use crate::db::capsule::Role;
 use crate::db::user::{Admin, User};
+use crate::storage;
 use crate::websockets::WebSockets;
-use crate::{Db, Error, Result};
+use crate::{Db, Error, Result, HARSH};
 
 /// Admin get dashboard
 #[get("/admin/dashboard")]
@@ -97,15 +99,27 @@ pub async fn request_invite_user(
     config: &S<Config>,
     form: Json<InviteUserForm>,
 ) -> Result<()> {
-    Ok(admin
+    let after = json!({ "username": form.username, "email": form.email });
+
+    admin
         .0
         .request_invitation(form.0.username, form.0.email, &config.mailer, &db, &config)
-        .await?)
+        .await?;
+
+    AuditLog::record(
+        Some(&admin.0),
+        AuditAction::UserInvited,
+        Target::default(),
+        None,
+        Some(after),
+        &db,
+    )
+    .await
 }
 
 /// The route that deletes a user
 #[delete("/admin/user/<id>")]
-pub async fn delete_user(_admin: Admin, db: Db, id: i32, config: &S<Config>) -> Result<()> {
+pub async fn delete_user(admin: Admin, db: Db, id: i32, config: &S<Config>) -> Result<()> {
     let user = User::get_by_id(id, &db)
         .await?
         .ok_or(Error(Status::NotFound))?;
@@ -113,13 +127,55 @@ pub async fn delete_user(_admin: Admin, db: Db, id: i32, config: &S<Config>) ->
     let capsules = user.capsules(&db).await?;
     for (capsule, role) in capsules {
         if role == Role::Owner {
-            let dir = config.data_path.join(format!("{}", capsule.id));
-            remove_dir_all(dir).await?;
+            storage::delete_capsule(capsule.id, &config).await?;
             capsule.delete(&db).await?;
         }
     }
 
-    Ok(user.delete(&db).await?)
+    let summary = user_summary(&user);
+    user.delete(&db).await?;
+
+    AuditLog::record(
+        Some(&admin.0),
+        AuditAction::UserDeletedByAdmin,
+        Target::user(id),
+        Some(summary),
+        None,
+        &db,
+    )
+    .await
+}
+
+/// Admin get the audit log, filtered by actor, target and date.
+///
+/// The dates are unix timestamps in seconds, and the capsule is given by its hash id.
+#[get("/admin/audit/<page>?<actor>&<capsule>&<user>&<group>&<from>&<to>")]
+pub async fn get_audit_log(
+    admin: Admin,
+    db: Db,
+    page: i32,
+    actor: Option<i32>,
+    capsule: Option<String>,
+    user: Option<i32>,
+    group: Option<i32>,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> Result<Value> {
+    let capsule = match capsule {
+        Some(capsule) => Some(HARSH.decode(capsule)?),
+        None => None,
+    };
+
+    let filter = AuditFilter {
+        actor,
+        capsule,
+        user,
+        group,
+        from: from.and_then(|x| NaiveDateTime::from_timestamp_opt(x, 0)),
+        to: to.and_then(|x| NaiveDateTime::from_timestamp_opt(x, 0)),
+    };
+
+    admin.get_audit_log(&db, &filter, page).await
 }
 
 /// A routes that clears unused websockets.
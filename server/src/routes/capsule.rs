@@ -1,35 +1,53 @@
 //! This module contains the routes to manage the capsules.
 
+use std::collections::HashSet;
+use std::io::ErrorKind;
 use std::path::Path;
 use std::process::Stdio;
 use std::sync::Arc;
 
+use chrono::NaiveDateTime;
+
 use uuid::Uuid;
 
 use serde::{Deserialize, Serialize};
 
-use tokio::fs::{copy, create_dir_all, read_dir, remove_dir_all, remove_file};
+use tokio::fs::{create_dir_all, read_dir, remove_dir_all, remove_file};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::process::Command;
 use tokio::sync::Semaphore;
+use tokio::task::spawn_blocking;
 
 use ergol::tokio_postgres::types::Json as EJson;
+use ergol::Pool;
 
 use rocket::data::ToByteUnit;
 use rocket::http::{ContentType, Status};
 use rocket::serde::json::{json, Json, Value};
 use rocket::{Data, State as S};
 
+use crate::blobs::BlobStore;
 use crate::command::{export_slides, run_command};
 use crate::config::Config;
+use crate::db::audit::{capsule_summary, AuditAction, AuditLog, Target};
 use crate::db::capsule::{
     Capsule, Fade, Gos, Privacy, Record, Role, Slide, SoundTrack, WebcamSettings,
 };
+use crate::db::comment::CommentVisibility;
+use crate::db::export::Export;
+use crate::db::project::Project;
+use crate::db::quiz::QuizAnswer;
+use crate::db::share::ShareToken;
 use crate::db::stats::{TaskStat, TaskStatType};
 use crate::db::task_status::TaskStatus;
 use crate::db::user::{Plan, User};
+use crate::db::view::View;
+use crate::hls;
+use crate::routes::feed::start_feed_exports;
+use crate::storage::{self, capsule_key};
+use crate::timeline;
 use crate::websockets::WebSockets;
-use crate::{Db, Error, HashId, Result};
+use crate::{Db, Error, HashId, Result, HARSH};
 
 /// The route that gives the capsule information.
 #[get("/capsule/<capsule_id>")]
@@ -97,9 +115,12 @@ pub async fn new_capsule(
             events: vec![],
             webcam_settings: None,
             fade: Fade::none(),
+            quizzes: vec![],
         })
         .collect::<Vec<_>>();
 
+    push_dir(capsule.id, "assets", &config).await?;
+
     capsule.structure = EJson(gos);
     capsule.set_changed();
     capsule.save(&db).await?;
@@ -133,6 +154,40 @@ pub struct CapsuleEdit {
 
     /// The new soundtrack.
     pub sound_track: Option<SoundTrack>,
+
+    /// The new origins allowed to embed the video, unchanged if absent.
+    #[serde(default)]
+    pub embed_origins: Option<Vec<String>>,
+
+    /// The new publication profile, unchanged if absent.
+    #[serde(default)]
+    pub publication_profile: Option<String>,
+
+    /// Whether the viewers can download the video, unchanged if absent.
+    #[serde(default)]
+    pub allow_download: Option<bool>,
+
+    /// Who can read the comments of the video, unchanged if absent.
+    #[serde(default)]
+    pub comment_visibility: Option<CommentVisibility>,
+}
+
+/// Checks that an origin is of the form `scheme://host[:port]`, so that it can safely be put in a
+/// Content-Security-Policy header.
+fn is_valid_origin(origin: &str) -> bool {
+    let rest = match origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+    {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    // Wildcards are refused, they would let any website embed the video.
+    !rest.is_empty()
+        && rest
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == ':')
 }
 
 /// The route that updates a capsule structure.
@@ -141,7 +196,10 @@ pub async fn edit_capsule(
     user: User,
     db: Db,
     data: Json<CapsuleEdit>,
+    config: &S<Config>,
     socks: &S<WebSockets>,
+    sem: &S<Arc<Semaphore>>,
+    pool: &S<Pool>,
 ) -> Result<()> {
     let CapsuleEdit {
         id,
@@ -152,13 +210,69 @@ pub async fn edit_capsule(
         sound_track,
         privacy,
         prompt_subtitles,
+        embed_origins,
+        publication_profile,
+        allow_download,
+        comment_visibility,
     } = data.0;
 
-    let (mut capsule, _) = user
+    let (mut capsule, role) = user
         .get_capsule_with_permission(*id, Role::Write, &db)
         .await?;
 
-    capsule.project = project;
+    // Only the owner of a capsule can move it to another project.
+    if capsule.project(&db).await?.name != project {
+        if role != Role::Owner {
+            return Err(Error(Status::Forbidden));
+        }
+
+        capsule.project = Project::find_or_create(&project, &user, &db).await?.id;
+    }
+
+    if let Some(embed_origins) = embed_origins {
+        if !embed_origins.iter().all(|x| is_valid_origin(x)) {
+            return Err(Error(Status::BadRequest));
+        }
+
+        capsule.embed_origins = EJson(embed_origins);
+    }
+
+    if let Some(publication_profile) = publication_profile {
+        let owner = capsule.owner(&db).await?;
+
+        if !config
+            .allowed_publication_profiles(owner.plan)
+            .contains(&publication_profile)
+        {
+            return Err(Error(Status::Forbidden));
+        }
+
+        capsule.publication_profile = Some(publication_profile);
+    }
+
+    let download_allowed = allow_download == Some(true) && !capsule.allow_download;
+
+    if let Some(allow_download) = allow_download {
+        capsule.allow_download = allow_download;
+    }
+
+    if let Some(comment_visibility) = comment_visibility {
+        capsule.comment_visibility = Some(comment_visibility);
+    }
+
+    // The answers of the quizzes are identified by the id of their quiz, which must be unique.
+    let mut quiz_ids = HashSet::new();
+    if !structure
+        .iter()
+        .flat_map(|gos| gos.quizzes.iter())
+        .all(|quiz| quiz.is_valid() && quiz_ids.insert(quiz.id))
+    {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let before = capsule_summary(&capsule, &db).await?;
+    let privacy_changed = capsule.privacy != privacy;
+
     capsule.name = name;
     capsule.privacy = privacy;
     capsule.prompt_subtitles = prompt_subtitles;
@@ -168,11 +282,47 @@ pub async fn edit_capsule(
     capsule.set_changed();
     capsule.save(&db).await?;
 
+    if privacy_changed {
+        AuditLog::record(
+            Some(&user),
+            AuditAction::PrivacyChanged,
+            Target::capsule(capsule.id),
+            Some(before),
+            Some(capsule_summary(&capsule, &db).await?),
+            &db,
+        )
+        .await?;
+    }
+
+    if download_allowed {
+        start_feed_exports(&capsule, &config, &sem, &pool).await?;
+    }
+
     capsule.notify_change(&db, &socks).await?;
 
     Ok(())
 }
 
+/// The route that lists the publication profiles the user can choose for their capsules.
+#[get("/publication-profiles")]
+pub fn publication_profiles(user: User, config: &S<Config>) -> Value {
+    let profiles = config
+        .allowed_publication_profiles(user.plan)
+        .iter()
+        .filter_map(|name| {
+            config.publication_profiles.get(name).map(|profile| {
+                json!({
+                    "name": name,
+                    "codec": profile.codec,
+                    "renditions": profile.renditions.iter().map(|x| &x.name).collect::<Vec<_>>(),
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!(profiles)
+}
+
 /// The route that deletes a capsule by id.
 #[delete("/capsule/<id>")]
 pub async fn delete_capsule(user: User, db: Db, id: HashId, config: &S<Config>) -> Result<()> {
@@ -180,30 +330,89 @@ pub async fn delete_capsule(user: User, db: Db, id: HashId, config: &S<Config>)
         .get_capsule_with_permission(*id, Role::Owner, &db)
         .await?;
 
+    let summary = capsule_summary(&capsule, &db).await?;
     capsule.delete(&db).await?;
-    let dir = config.data_path.join(format!("{}", *id));
-    remove_dir_all(dir).await?;
+    storage::delete_capsule(*id, &config).await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::CapsuleDeleted,
+        Target::capsule(*id),
+        Some(summary),
+        None,
+        &db,
+    )
+    .await?;
+
     Ok(())
 }
 
 /// The route that deletes a whole project.
+///
+/// The owner of the project deletes it with all the capsules they own, the capsules of the other
+/// collaborators are moved to projects of their own. The other collaborators leave the project, as
+/// well as the capsules shared with them that belong to a project with the same name.
 #[delete("/project/<name>")]
 pub async fn delete_project(user: User, db: Db, config: &S<Config>, name: String) -> Result<()> {
-    let capsules = user.capsules(&db).await?;
+    let project = user
+        .projects(&db)
+        .await?
+        .into_iter()
+        .filter(|(x, _)| x.name == name)
+        .max_by_key(|(_, role)| *role);
+
+    match project {
+        Some((project, Role::Owner)) => {
+            for mut capsule in project.capsules(&db).await? {
+                // The capsules of the other collaborators go back to a project of their own.
+                let owner = capsule.owner(&db).await?;
+                if owner.id != user.id {
+                    capsule.project = Project::find_or_create(&project.name, &owner, &db)
+                        .await?
+                        .id;
+                    capsule.save(&db).await?;
+                    continue;
+                }
 
-    for (capsule, role) in capsules {
-        if role != Role::Owner {
-            leave_aux(&user, HashId(capsule.id), &db).await?;
+                let summary = capsule_summary(&capsule, &db).await?;
+                storage::delete_capsule(capsule.id, &config).await?;
+                capsule.delete(&db).await?;
+
+                AuditLog::record(
+                    Some(&user),
+                    AuditAction::CapsuleDeleted,
+                    Target::capsule(capsule.id),
+                    Some(summary),
+                    None,
+                    &db,
+                )
+                .await?;
+            }
+
+            project.delete(&db).await?;
         }
 
-        if capsule.project != name {
-            continue;
+        Some((project, role)) => {
+            project.remove_user(&user, &db).await?;
+
+            AuditLog::record(
+                Some(&user),
+                AuditAction::CollaboratorRemoved,
+                Target::user(user.id),
+                Some(json!({ "project": HARSH.encode(project.id), "role": role })),
+                None,
+                &db,
+            )
+            .await?;
         }
 
-        // Delete the capsule
-        let dir = config.data_path.join(format!("{}", capsule.id));
-        remove_dir_all(dir).await?;
-        capsule.delete(&db).await?;
+        None => (),
+    }
+
+    for (capsule, role) in user.capsules(&db).await? {
+        if role != Role::Owner && capsule.project(&db).await?.name == name {
+            leave_aux(&user, HashId(capsule.id), &db).await?;
+        }
     }
 
     Ok(())
@@ -258,6 +467,8 @@ pub async fn upload_record(
         None
     };
 
+    push_dir(capsule.id, "assets", &config).await?;
+
     gos.record = Some(Record {
         uuid,
         size,
@@ -328,6 +539,8 @@ pub async fn upload_pointer(
 
     data.open(1_i32.gibibytes()).into_file(output).await?;
 
+    push_dir(capsule.id, "assets", &config).await?;
+
     gos.record.as_mut().unwrap().pointer_uuid = Some(pointer_uuid);
 
     capsule.set_changed();
@@ -406,6 +619,8 @@ pub async fn replace_slide(
             &config.pdf_target_size,
         ])?;
 
+        push_dir(capsule.id, "assets", &config).await?;
+
         capsule.set_changed();
         capsule.save(&db).await?;
         capsule.to_json(role, &db).await?
@@ -420,6 +635,8 @@ pub async fn replace_slide(
             &config.pdf_target_size,
         ])?;
 
+        push_dir(capsule.id, "assets", &config).await?;
+
         capsule.set_changed();
         capsule.save(&db).await?;
         capsule.to_json(role, &db).await?
@@ -430,6 +647,7 @@ pub async fn replace_slide(
 
         let socks = socks.inner().clone();
         let sem = sem.inner().clone();
+        let config = config.inner().clone();
 
         tokio::spawn(async move {
             let child = Command::new("../scripts/psh")
@@ -517,6 +735,15 @@ pub async fn replace_slide(
                 slide_found.extra = Some(output_uuid);
             }
 
+            if succeed {
+                if let Err(e) = push_dir(capsule.id, "assets", &config).await {
+                    error!(
+                        "Failed to store the assets of capsule {}: {}",
+                        capsule.id, e
+                    );
+                }
+            }
+
             capsule.set_changed();
             capsule.save(&db).await.ok();
             capsule.notify_change(&db, &socks).await.ok();
@@ -649,6 +876,8 @@ pub async fn add_slide(
         return Err(Error(Status::UnsupportedMediaType));
     };
 
+    push_dir(capsule.id, "assets", &config).await?;
+
     gos.slides.push(Slide {
         uuid: output_uuid,
         extra: None,
@@ -750,6 +979,8 @@ pub async fn add_gos(
         return Err(Error(Status::UnsupportedMediaType));
     };
 
+    push_dir(capsule.id, "assets", &config).await?;
+
     gos.slides.push(Slide {
         uuid: output_uuid,
         extra: None,
@@ -762,6 +993,121 @@ pub async fn add_gos(
     Ok(capsule.to_json(role, &db).await?)
 }
 
+/// Stores the files of a directory of the working copy of a capsule that are missing from the
+/// storage.
+async fn push_dir(capsule_id: i32, dir: &str, config: &Config) -> Result<()> {
+    let path = config.data_path.join(format!("{}", capsule_id)).join(dir);
+    storage::open(config)?
+        .push_dir(&capsule_key(capsule_id, dir)?, &path)
+        .await
+}
+
+/// Downloads the files of a directory of a capsule that are missing from its working copy.
+async fn pull_dir(capsule_id: i32, dir: &str, config: &Config) -> Result<()> {
+    let path = config.data_path.join(format!("{}", capsule_id)).join(dir);
+    storage::open(config)?
+        .pull_dir(&capsule_key(capsule_id, dir)?, &path)
+        .await
+}
+
+/// Generates the thumbnails of the produced gos of a capsule, and the poster and the preview of
+/// its video if the whole capsule has been produced, which is the case when gos is -1.
+///
+/// Returns the indices of the gos that have a thumbnail in the working copy of the capsule.
+async fn generate_thumbnails(capsule_id: i32, gos: i32, config: &Config) -> Result<Vec<i32>> {
+    spawn_blocking(move || {
+        run_command(&vec![
+            "../scripts/psh",
+            "thumbnails",
+            &format!("{}", capsule_id),
+            &format!("{}", gos),
+        ])
+    })
+    .await
+    .map_err(|_| Error(Status::InternalServerError))??;
+
+    let tmp = config.data_path.join(format!("{}", capsule_id)).join("tmp");
+    let mut iter = match read_dir(&tmp).await {
+        Ok(iter) => iter,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut thumbnails = vec![];
+    while let Some(entry) = iter.next_entry().await? {
+        let index = entry
+            .file_name()
+            .to_str()
+            .and_then(|x| x.strip_prefix("gos_"))
+            .and_then(|x| x.strip_suffix(".jpg"))
+            .and_then(|x| x.parse::<i32>().ok());
+
+        if let Some(index) = index {
+            thumbnails.push(index);
+        }
+    }
+
+    thumbnails.sort_unstable();
+    Ok(thumbnails)
+}
+
+/// Downloads a file of a capsule if it is missing from its working copy.
+pub async fn pull_file(capsule_id: i32, path: &str, config: &Config) -> Result<()> {
+    let local = config.data_path.join(format!("{}", capsule_id)).join(path);
+
+    if local.is_file() {
+        return Ok(());
+    }
+
+    storage::open(config)?
+        .get_file(&capsule_key(capsule_id, path)?, &local)
+        .await
+}
+
+/// Deletes a file of a capsule, from the storage and from its working copy.
+async fn delete_file(capsule_id: i32, path: &str, config: &Config) -> Result<()> {
+    storage::open(config)?
+        .delete(&capsule_key(capsule_id, path)?)
+        .await?;
+
+    let local = config.data_path.join(format!("{}", capsule_id)).join(path);
+    match remove_file(local).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Stores the files written by the production of a capsule.
+async fn push_production(capsule_id: i32, config: &Config) -> Result<()> {
+    push_dir(capsule_id, "tmp", config).await?;
+
+    let output = config
+        .data_path
+        .join(format!("{}", capsule_id))
+        .join("output.mp4");
+
+    if output.is_file() {
+        storage::open(config)?
+            .put_file(&capsule_key(capsule_id, "output.mp4")?, &output)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Gives the capsule its own copy of the files that the production rewrites, since they may be
+/// shared with duplicates of the capsule.
+async fn detach_production_files(capsule_id: i32, config: &Config) -> Result<()> {
+    let store = BlobStore::new(config);
+    let path = config.data_path.join(format!("{}", capsule_id));
+
+    store.detach_dir(&path.join("tmp")).await?;
+    store.detach_dir(&path.join("output")).await?;
+    store.detach(&path.join("output.mp4")).await?;
+
+    Ok(())
+}
+
 /// The route that triggers the production of a capsule.
 #[post("/produce/<id>")]
 pub async fn produce(
@@ -780,10 +1126,16 @@ pub async fn produce(
         return Err(Error(Status::Conflict));
     }
 
+    // The exports are made from the video, which is about to change.
+    Export::clear(&capsule, config, &db).await?;
+
     let mut stat = TaskStat::new(TaskStatType::Production, &db).await?;
 
+    pull_dir(capsule.id, "assets", config).await?;
+
     let socks = socks.inner().clone();
     let sem = sem.inner().clone();
+    let config = config.inner().clone();
 
     let output_path = config.data_path.join(format!("{}", *id)).join("output.mp4");
 
@@ -824,23 +1176,37 @@ pub async fn produce(
                     .unwrap();
 
                 if let Ok(_) = sem.acquire().await {
-                    child.stdin.unwrap();
-                    let stdout = child.stdout.take().unwrap();
-                    let reader = BufReader::new(stdout);
-
-                    let mut lines = reader.lines();
-                    while let Some(line) = lines.next_line().await.unwrap() {
-                        capsule
-                            .notify_production_progress(
-                                &id.hash(),
-                                &format!("{}", line),
-                                &db,
-                                &socks,
-                            )
-                            .await
-                            .ok();
+                    // The script only starts writing once its stdin is closed, the files are
+                    // detached right before so that no duplicate can share them in the meantime.
+                    match detach_production_files(capsule.id, &config).await {
+                        Err(e) => {
+                            error!(
+                                "Failed to detach the files of capsule {}: {}",
+                                capsule.id, e
+                            );
+                            child.kill().await.ok();
+                            false
+                        }
+                        Ok(()) => {
+                            child.stdin.unwrap();
+                            let stdout = child.stdout.take().unwrap();
+                            let reader = BufReader::new(stdout);
+
+                            let mut lines = reader.lines();
+                            while let Some(line) = lines.next_line().await.unwrap() {
+                                capsule
+                                    .notify_production_progress(
+                                        &id.hash(),
+                                        &format!("{}", line),
+                                        &db,
+                                        &socks,
+                                    )
+                                    .await
+                                    .ok();
+                            }
+                            true
+                        }
                     }
-                    true
                 } else {
                     false
                 }
@@ -885,6 +1251,25 @@ pub async fn produce(
         capsule.save(&db).await.ok();
 
         if succeed {
+            match generate_thumbnails(capsule.id, -1, &config).await {
+                Ok(gos) => {
+                    capsule.thumbnails_generated = true;
+                    capsule.gos_thumbnails = EJson(gos);
+                    capsule.save(&db).await.ok();
+                }
+                Err(e) => error!(
+                    "Failed to generate the thumbnails of capsule {}: {}",
+                    capsule.id, e
+                ),
+            }
+
+            if let Err(e) = push_production(capsule.id, &config).await {
+                error!(
+                    "Failed to store the production of capsule {}: {}",
+                    capsule.id, e
+                );
+            }
+
             capsule
                 .notify_production(&id.hash(), &db, &socks)
                 .await
@@ -924,6 +1309,7 @@ pub async fn produce_gos(
     gos: i32,
     socks: &S<WebSockets>,
     sem: &S<Arc<Semaphore>>,
+    config: &S<Config>,
     db: Db,
 ) -> Result<()> {
     let (mut capsule, _) = user
@@ -934,8 +1320,11 @@ pub async fn produce_gos(
         return Err(Error(Status::Conflict));
     }
 
+    pull_dir(capsule.id, "assets", config).await?;
+
     let socks = socks.inner().clone();
     let sem = sem.inner().clone();
+    let config = config.inner().clone();
 
     tokio::spawn(async move {
         let child = Command::new("../scripts/psh")
@@ -959,23 +1348,37 @@ pub async fn produce_gos(
                     .unwrap();
 
                 if let Ok(_) = sem.acquire().await {
-                    child.stdin.unwrap();
-                    let stdout = child.stdout.take().unwrap();
-                    let reader = BufReader::new(stdout);
-
-                    let mut lines = reader.lines();
-                    while let Some(line) = lines.next_line().await.unwrap() {
-                        capsule
-                            .notify_production_progress(
-                                &id.hash(),
-                                &format!("{}", line),
-                                &db,
-                                &socks,
-                            )
-                            .await
-                            .ok();
+                    // The script only starts writing once its stdin is closed, the files are
+                    // detached right before so that no duplicate can share them in the meantime.
+                    match detach_production_files(capsule.id, &config).await {
+                        Err(e) => {
+                            error!(
+                                "Failed to detach the files of capsule {}: {}",
+                                capsule.id, e
+                            );
+                            child.kill().await.ok();
+                            false
+                        }
+                        Ok(()) => {
+                            child.stdin.unwrap();
+                            let stdout = child.stdout.take().unwrap();
+                            let reader = BufReader::new(stdout);
+
+                            let mut lines = reader.lines();
+                            while let Some(line) = lines.next_line().await.unwrap() {
+                                capsule
+                                    .notify_production_progress(
+                                        &id.hash(),
+                                        &format!("{}", line),
+                                        &db,
+                                        &socks,
+                                    )
+                                    .await
+                                    .ok();
+                            }
+                            true
+                        }
                     }
-                    true
                 } else {
                     false
                 }
@@ -996,6 +1399,28 @@ pub async fn produce_gos(
         capsule.save(&db).await.ok();
 
         if succeed {
+            match generate_thumbnails(capsule.id, gos, &config).await {
+                Ok(thumbnails) if thumbnails.contains(&gos) => {
+                    if !capsule.gos_thumbnails.0.contains(&gos) {
+                        capsule.gos_thumbnails.0.push(gos);
+                        capsule.gos_thumbnails.0.sort_unstable();
+                        capsule.save(&db).await.ok();
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => error!(
+                    "Failed to generate the thumbnails of capsule {}: {}",
+                    capsule.id, e
+                ),
+            }
+
+            if let Err(e) = push_production(capsule.id, &config).await {
+                error!(
+                    "Failed to store the production of capsule {}: {}",
+                    capsule.id, e
+                );
+            }
+
             capsule
                 .notify_production(&id.hash(), &db, &socks)
                 .await
@@ -1061,6 +1486,7 @@ pub async fn publish(
     db: Db,
     socks: &S<WebSockets>,
     sem: &S<Arc<Semaphore>>,
+    pool: &S<Pool>,
 ) -> Result<()> {
     let (mut capsule, _) = user
         .get_capsule_with_permission(*id, Role::Write, &db)
@@ -1070,26 +1496,58 @@ pub async fn publish(
         return Err(Error(Status::Conflict));
     }
 
+    // The exports are made from the video, which is about to change.
+    Export::clear(&capsule, &config, &db).await?;
+
     let mut stat = TaskStat::new(TaskStatType::Publication, &db).await?;
 
     let input = config.data_path.join(format!("{}", *id)).join("output.mp4");
     let output = config.data_path.join(format!("{}", *id)).join("output");
 
+    let owner = capsule.owner(&db).await?;
+    let profile = config
+        .publication_profile(owner.plan, capsule.publication_profile.as_deref())
+        .map(|(_, profile)| profile.clone())
+        .ok_or(Error(Status::InternalServerError))?;
+
+    let storage = storage::open(&config)?;
+    let output_key = capsule_key(capsule.id, "output")?;
+
+    pull_file(capsule.id, "output.mp4", &config).await?;
+
+    // The poster and the preview are published with the video, if they have been generated.
+    for file in ["poster.jpg", "preview.jpg", "thumbnails.vtt"] {
+        match pull_file(capsule.id, &format!("tmp/{}", file), &config).await {
+            Err(e) if e.0 != Status::NotFound => return Err(e),
+            _ => (),
+        }
+    }
+
+    // The slides give the thumbnails of the slide index, and the extra videos of the gos that were
+    // not recorded give the timing of the subtitles and of the chapters.
+    pull_dir(capsule.id, "assets", &config).await?;
+
+    let config = config.inner().clone();
     let socks = socks.inner().clone();
     let sem = sem.inner().clone();
+    let pool = pool.inner().clone();
 
     tokio::spawn(async move {
+        storage.delete_dir(&output_key).await.ok();
         remove_dir_all(&output).await.ok();
 
         let child = Command::new("../scripts/psh")
             .arg("on-publish")
             .arg(input)
-            .arg(output)
+            .arg(&output)
             .arg(format!("{}", capsule.prompt_subtitles))
+            .arg(profile.codec.name())
+            .arg(format!("{}", profile.segment_duration))
+            .args(profile.renditions.iter().map(|x| x.script_arg()))
             .stdin(Stdio::piped())
             .spawn();
 
-        let succeed = if let Ok(mut child) = child {
+        let mut succeed = if let Ok(mut child) = child {
             if let Some(stdin) = child.stdin.as_mut() {
                 stdin
                     .write_all(json!(capsule.structure.0).to_string().as_bytes())
@@ -1116,6 +1574,33 @@ pub async fn publish(
 
         stat.end(&db).await.unwrap();
 
+        if succeed {
+            let playlists = hls::write_playlists(
+                &output,
+                &profile,
+                capsule.prompt_subtitles,
+                capsule.duration_ms,
+            )
+            .await;
+
+            if let Err(e) = playlists {
+                error!(
+                    "Failed to write the playlists of capsule {}: {}",
+                    capsule.id, e
+                );
+                succeed = false;
+            }
+        }
+
+        if succeed {
+            if let Err(e) = timeline::write_publication_files(&capsule, &config, &output).await {
+                error!(
+                    "Failed to write the chapters of capsule {}: {}",
+                    capsule.id, e
+                );
+            }
+        }
+
         capsule.published = if succeed {
             TaskStatus::Done
         } else {
@@ -1126,6 +1611,37 @@ pub async fn publish(
         capsule.save(&db).await.ok();
 
         if succeed {
+            if let Err(e) = storage.push_dir(&output_key, &output).await {
+                error!(
+                    "Failed to store the publication of capsule {}: {}",
+                    capsule.id, e
+                );
+            }
+
+            // The publication is only recorded once it has succeeded.
+            if let Ok(summary) = capsule_summary(&capsule, &db).await {
+                AuditLog::record(
+                    Some(&user),
+                    AuditAction::CapsulePublished,
+                    Target::capsule(capsule.id),
+                    None,
+                    Some(summary),
+                    &db,
+                )
+                .await
+                .ok();
+            }
+
+            // The download may have been allowed during the publication.
+            if let Ok(Some(capsule)) = Capsule::get_by_id(capsule.id, &db).await {
+                if let Err(e) = start_feed_exports(&capsule, &config, &sem, &pool).await {
+                    error!(
+                        "Failed to start the exports of capsule {}: {}",
+                        capsule.id, e
+                    );
+                }
+            }
+
             capsule
                 .notify_publication(&id.hash(), &db, &socks)
                 .await
@@ -1197,11 +1713,26 @@ pub async fn unpublish(user: User, id: HashId, db: Db, config: &S<Config>) -> Re
         return Err(Error(Status::BadRequest));
     }
 
+    let before = capsule_summary(&capsule, &db).await?;
     capsule.published = TaskStatus::Idle;
     capsule.save(&db).await?;
 
+    storage::open(config)?
+        .delete_dir(&capsule_key(capsule.id, "output")?)
+        .await?;
+
     let output = config.data_path.join(format!("{}", *id)).join("output");
-    remove_dir_all(output).await?;
+    remove_dir_all(output).await.ok();
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::CapsuleUnpublished,
+        Target::capsule(capsule.id),
+        Some(before),
+        Some(capsule_summary(&capsule, &db).await?),
+        &db,
+    )
+    .await?;
 
     Ok(())
 }
@@ -1239,63 +1770,9 @@ pub async fn duplicate(user: User, id: HashId, config: &S<Config>, db: Db) -> Re
         .get_capsule_with_permission(*id, Role::Read, &db)
         .await?;
 
-    let mut new = Capsule::new(
-        capsule.project,
-        format!("{} (copie)", capsule.name),
-        &user,
-        &db,
-    )
-    .await?;
-    new.privacy = capsule.privacy;
-    new.produced = capsule.produced;
-    new.structure = capsule.structure;
-    new.webcam_settings = capsule.webcam_settings;
-    new.sound_track = capsule.sound_track;
-    new.duration_ms = capsule.duration_ms;
-
-    for dir in ["assets", "tmp", "output"] {
-        let orig = config.data_path.join(&format!("{}/{}", capsule.id, dir));
-        let dest = config.data_path.join(&format!("{}/{}", new.id, dir));
-
-        if orig.is_dir() {
-            create_dir_all(&dest).await?;
-
-            let mut iter = read_dir(&orig)
-                .await
-                .map_err(|_| Error(Status::InternalServerError))?;
-
-            loop {
-                let next = iter
-                    .next_entry()
-                    .await
-                    .map_err(|_| Error(Status::InternalServerError))?;
-
-                let next = match next {
-                    Some(x) => x,
-                    None => break,
-                };
-
-                let path = next.path();
-                let file_name = path.file_name().ok_or(Error(Status::InternalServerError))?;
-
-                copy(orig.join(&file_name), dest.join(&file_name))
-                    .await
-                    .map_err(|_| Error(Status::InternalServerError))?;
-            }
-        }
-    }
-
-    let orig = config.data_path.join(&format!("{}/output.mp4", capsule.id));
-    let dest = config.data_path.join(&format!("{}/output.mp4", new.id));
-
-    if orig.is_file() {
-        copy(orig, dest)
-            .await
-            .map_err(|_| Error(Status::InternalServerError))?;
-    }
-
-    new.set_changed();
-    new.save(&db).await?;
+    let new = capsule
+        .duplicate(format!("{} (copie)", capsule.name), &user, &config, &db)
+        .await?;
 
     Ok(new.to_json(Role::Owner, &db).await?)
 }
@@ -1334,6 +1811,16 @@ pub async fn invite(user: User, id: HashId, db: Db, data: Json<Invite>) -> Resul
 
     capsule.add_user(&invited, role, &db).await?;
 
+    AuditLog::record(
+        Some(&user),
+        AuditAction::CollaboratorAdded,
+        Target::capsule(capsule.id).with_user(invited.id),
+        None,
+        Some(json!({ "role": role })),
+        &db,
+    )
+    .await?;
+
     Ok(())
 }
 
@@ -1350,8 +1837,26 @@ pub async fn change_role(user: User, id: HashId, db: Db, data: Json<Invite>) ->
         .await?
         .ok_or(Error(Status::BadRequest))?;
 
+    let before = capsule
+        .users(&db)
+        .await?
+        .into_iter()
+        .find(|(x, _)| x.id == invited.id)
+        .map(|(_, r)| r)
+        .ok_or(Error(Status::BadRequest))?;
+
     capsule.update_role(&invited, role, &db).await?;
 
+    AuditLog::record(
+        Some(&user),
+        AuditAction::RoleChanged,
+        Target::capsule(capsule.id).with_user(invited.id),
+        Some(json!({ "role": before })),
+        Some(json!({ "role": role })),
+        &db,
+    )
+    .await?;
+
     Ok(())
 }
 
@@ -1385,6 +1890,16 @@ pub async fn deinvite(user: User, id: HashId, db: Db, data: Json<Deinvite>) -> R
 
     capsule.remove_user(&deinvited, &db).await?;
 
+    AuditLog::record(
+        Some(&user),
+        AuditAction::CollaboratorRemoved,
+        Target::capsule(capsule.id).with_user(deinvited.id),
+        Some(json!({ "role": role })),
+        None,
+        &db,
+    )
+    .await?;
+
     Ok(())
 }
 
@@ -1400,6 +1915,16 @@ pub async fn leave_aux(user: &User, id: HashId, db: &Db) -> Result<()> {
 
     capsule.remove_user(&user, &db).await?;
 
+    AuditLog::record(
+        Some(&user),
+        AuditAction::CollaboratorRemoved,
+        Target::capsule(capsule.id).with_user(user.id),
+        Some(json!({ "role": role })),
+        None,
+        &db,
+    )
+    .await?;
+
     Ok(())
 }
 
@@ -1409,6 +1934,113 @@ pub async fn leave(user: User, id: HashId, db: Db) -> Result<()> {
     leave_aux(&user, id, &db).await
 }
 
+/// The data to create a share token.
+#[derive(Serialize, Deserialize)]
+pub struct NewShareToken {
+    /// The moment after which the token expires, as a unix timestamp in seconds.
+    expires: Option<i64>,
+
+    /// The number of times the video can be watched with the token.
+    max_views: Option<i32>,
+
+    /// The password required to use the token.
+    password: Option<String>,
+}
+
+/// The route that creates a share token for a capsule.
+#[post("/share-token/<id>", data = "<data>")]
+pub async fn new_share_token(
+    user: User,
+    id: HashId,
+    db: Db,
+    data: Json<NewShareToken>,
+) -> Result<Value> {
+    let (capsule, _) = user
+        .get_capsule_with_permission(*id, Role::Write, &db)
+        .await?;
+
+    let expires = match data.expires {
+        Some(x) => Some(NaiveDateTime::from_timestamp_opt(x, 0).ok_or(Error(Status::BadRequest))?),
+        None => None,
+    };
+
+    if data.max_views.map(|x| x <= 0).unwrap_or(false) {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let token = ShareToken::new(
+        &capsule,
+        expires,
+        data.max_views,
+        data.password.as_deref(),
+        &db,
+    )
+    .await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::ShareTokenCreated,
+        Target::capsule(capsule.id),
+        None,
+        Some(token.to_audit_json()),
+        &db,
+    )
+    .await?;
+
+    Ok(token.to_json())
+}
+
+/// The route that revokes a share token.
+#[delete("/share-token/<id>/<token>")]
+pub async fn delete_share_token(user: User, id: HashId, token: String, db: Db) -> Result<()> {
+    let (capsule, _) = user
+        .get_capsule_with_permission(*id, Role::Write, &db)
+        .await?;
+
+    let token = ShareToken::get_by_token(token, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if token.capsule(&db).await?.id != capsule.id {
+        return Err(Error(Status::NotFound));
+    }
+
+    let before = token.to_audit_json();
+    token.delete(&db).await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::ShareTokenRevoked,
+        Target::capsule(capsule.id),
+        Some(before),
+        None,
+        &db,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The route that gives the view analytics of a published capsule.
+#[get("/analytics/<id>")]
+pub async fn analytics(user: User, db: Db, id: HashId) -> Result<Value> {
+    let (capsule, _) = user
+        .get_capsule_with_permission(*id, Role::Write, &db)
+        .await?;
+
+    View::analytics(&capsule, &user, &db).await
+}
+
+/// The route that gives the results of the quizzes of a capsule.
+#[get("/quiz-results/<id>")]
+pub async fn quiz_results(user: User, db: Db, id: HashId) -> Result<Value> {
+    let (capsule, _) = user
+        .get_capsule_with_permission(*id, Role::Write, &db)
+        .await?;
+
+    QuizAnswer::results(&capsule, &db).await
+}
+
 /// Update the capsule's track.
 #[post("/sound-track/<id>/<name>", data = "<data>")]
 pub async fn sound_track(
@@ -1434,8 +2066,9 @@ pub async fn sound_track(
     if let Some(old_track) = old_track {
         let old_uuid = old_track.0.uuid;
         volume = old_track.0.volume;
-        let old_path = path.join(format!("{}", old_uuid)).with_extension("m4a");
-        remove_file(&old_path).await.ok();
+        delete_file(capsule.id, &format!("assets/{}.m4a", old_uuid), config)
+            .await
+            .ok();
     }
 
     // Create paths.
@@ -1465,6 +2098,8 @@ pub async fn sound_track(
     // Remove the temporary file.
     remove_file(&tmp_path).await.ok();
 
+    push_dir(capsule.id, "assets", &config).await?;
+
     // Save the track in the database.
     let sound_track = SoundTrack {
         uuid,
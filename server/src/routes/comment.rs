@@ -0,0 +1,306 @@
+//! This module contains the routes of the timed comments of the published capsules.
+//!
+//! Viewers need to be logged in to comment, and to be able to watch the video. The collaborators
+//! that can edit a capsule read every comment and moderate them.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use rocket::http::{CookieJar, Status};
+use rocket::serde::json::{json, Json, Value};
+use rocket::State as S;
+
+use crate::config::Config;
+use crate::db::capsule::{Capsule, Privacy, Role};
+use crate::db::comment::{Comment, CommentVisibility, COMMENT_MAX_LENGTH};
+use crate::db::task_status::TaskStatus;
+use crate::db::user::User;
+use crate::rate_limit::RateLimiter;
+use crate::routes::watch::check_asset_access;
+use crate::websockets::WebSockets;
+use crate::{Db, Error, HashId, Result};
+
+/// What a user can do with the comments of a capsule.
+struct CommentAccess {
+    /// The commented capsule.
+    capsule: Capsule,
+
+    /// The visibility of the comments of the capsule.
+    visibility: CommentVisibility,
+
+    /// Whether the user can edit the capsule, and thus read every comment and moderate them.
+    moderator: bool,
+}
+
+impl CommentAccess {
+    /// Returns true if the user can read a thread, given its first comment and its author.
+    ///
+    /// Viewers always read their own threads, even hidden ones, so that they see the answers to
+    /// their questions.
+    fn can_read(&self, root: &Comment, author: &User, user: Option<&User>) -> bool {
+        if self.moderator {
+            return true;
+        }
+
+        match self.visibility {
+            CommentVisibility::Disabled => false,
+            CommentVisibility::Collaborators => user.map(|x| x.id) == Some(author.id),
+            CommentVisibility::Viewers => !root.hidden || user.map(|x| x.id) == Some(author.id),
+        }
+    }
+}
+
+/// Checks that a user, or the share token stored in cookies, allows to watch a published capsule,
+/// and returns what they can do with its comments.
+async fn comment_access(
+    capsule_id: HashId,
+    user: Option<&User>,
+    config: &Config,
+    cookies: &CookieJar<'_>,
+    db: &Db,
+) -> Result<CommentAccess> {
+    let capsule = Capsule::get_by_id(*capsule_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if capsule.published != TaskStatus::Done {
+        return Err(Error(Status::NotFound));
+    }
+
+    let role = match user {
+        Some(user) => user
+            .get_capsule_with_permission(capsule.id, Role::Read, &db)
+            .await
+            .ok()
+            .map(|(_, role)| role),
+        None => None,
+    };
+
+    if role.is_none() && capsule.privacy == Privacy::Private {
+        check_asset_access(capsule_id, cookies, &db).await?;
+    }
+
+    let visibility = config.comment_visibility(capsule.privacy, capsule.comment_visibility);
+
+    Ok(CommentAccess {
+        capsule,
+        visibility,
+        moderator: role.map(|x| x >= Role::Write).unwrap_or(false),
+    })
+}
+
+/// Returns a comment of a capsule.
+async fn capsule_comment(capsule: &Capsule, comment_id: i32, db: &Db) -> Result<Comment> {
+    let comment = Comment::get_by_id(comment_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if comment.capsule(&db).await?.id != capsule.id {
+        return Err(Error(Status::NotFound));
+    }
+
+    Ok(comment)
+}
+
+/// Formats a moment in ms as minutes and seconds.
+fn format_time(ms: i32) -> String {
+    let seconds = ms.max(0) / 1000;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// The route that lists the comments of a capsule that the user can read, by thread.
+#[get("/v/<capsule_id>/comments", rank = 1)]
+pub async fn comments(
+    user: Option<User>,
+    capsule_id: HashId,
+    config: &S<Config>,
+    cookies: &CookieJar<'_>,
+    db: Db,
+) -> Result<Value> {
+    let access = comment_access(capsule_id, user.as_ref(), &config, cookies, &db).await?;
+
+    let mut comments = access.capsule.comments(&db).await?;
+    comments.sort_by_key(|x| (x.time, x.created));
+
+    let mut authors = HashMap::new();
+    for comment in &comments {
+        authors.insert(comment.id, comment.author(&db).await?);
+    }
+
+    let user_id = user.as_ref().map(|x| x.id);
+
+    let mut threads = vec![];
+    for root in comments.iter().filter(|x| x.parent.is_none()) {
+        let author = &authors[&root.id];
+
+        if !access.can_read(root, author, user.as_ref()) {
+            continue;
+        }
+
+        let replies = comments
+            .iter()
+            .filter(|x| x.parent == Some(root.id))
+            .filter(|x| access.moderator || !x.hidden || Some(authors[&x.id].id) == user_id)
+            .map(|x| x.to_json(&authors[&x.id]))
+            .collect::<Vec<_>>();
+
+        let mut thread = root.to_json(author);
+        thread["replies"] = json!(replies);
+        threads.push(thread);
+    }
+
+    Ok(json!({
+        "visibility": access.visibility,
+        "moderator": access.moderator,
+        "comments": threads,
+    }))
+}
+
+/// The form of a new comment.
+#[derive(Deserialize)]
+pub struct NewComment {
+    /// The moment of the video that is commented, in seconds, ignored for replies.
+    time: f64,
+
+    /// The content of the comment.
+    content: String,
+
+    /// The id of the comment to reply to, if any.
+    #[serde(default)]
+    parent: Option<i32>,
+}
+
+/// The route that posts a comment on a capsule, and notifies its owner.
+#[post("/v/<capsule_id>/comments", data = "<data>")]
+pub async fn new_comment(
+    user: User,
+    capsule_id: HashId,
+    config: &S<Config>,
+    cookies: &CookieJar<'_>,
+    limiter: &S<RateLimiter>,
+    socks: &S<WebSockets>,
+    db: Db,
+    data: Json<NewComment>,
+) -> Result<Value> {
+    let NewComment {
+        time,
+        content,
+        parent,
+    } = data.into_inner();
+
+    let content = content.trim().to_string();
+
+    if content.is_empty() || content.chars().count() > COMMENT_MAX_LENGTH || !time.is_finite() {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let access = comment_access(capsule_id, Some(&user), &config, cookies, &db).await?;
+
+    if access.visibility == CommentVisibility::Disabled {
+        return Err(Error(Status::Forbidden));
+    }
+
+    let capsule = &access.capsule;
+
+    // Only the threads the user can read can be replied to.
+    let parent = match parent {
+        Some(parent) => {
+            let parent = capsule_comment(capsule, parent, &db).await?;
+
+            let root = match parent.parent {
+                Some(root) => capsule_comment(capsule, root, &db).await?,
+                None => parent,
+            };
+
+            if !access.can_read(&root, &root.author(&db).await?, Some(&user)) {
+                return Err(Error(Status::NotFound));
+            }
+
+            Some(root)
+        }
+        None => None,
+    };
+
+    let time = ((time * 1000.0) as i32).clamp(0, capsule.duration_ms.max(0));
+    let comment = Comment::new(capsule, &user, parent.as_ref(), time, content, &db).await?;
+
+    // The comment is kept even when its author sent too many notifications to the owner.
+    let owner = capsule.owner(&db).await?;
+    let key = format!("comment-notify:{}:{}", user.id, owner.id);
+    if owner.id != user.id
+        && limiter
+            .request(&[key], config.comment_max_notifications, config)
+            .await
+            .is_ok()
+    {
+        owner
+            .notify(
+                &socks,
+                "Nouveau commentaire",
+                &format!(
+                    "{} a commenté la capsule \"{}\" à {}.",
+                    user.username,
+                    capsule.name,
+                    format_time(comment.time)
+                ),
+                &db,
+            )
+            .await?;
+    }
+
+    Ok(comment.to_json(&user))
+}
+
+/// The form to moderate a comment.
+#[derive(Deserialize)]
+pub struct Moderation {
+    /// Whether the comment is hidden from the viewers.
+    hidden: bool,
+}
+
+/// The route that hides or shows a comment of a capsule.
+#[post("/v/<capsule_id>/comments/<comment_id>/moderate", data = "<data>")]
+pub async fn moderate_comment(
+    user: User,
+    capsule_id: HashId,
+    comment_id: i32,
+    db: Db,
+    data: Json<Moderation>,
+) -> Result<Value> {
+    let (capsule, _) = user
+        .get_capsule_with_permission(*capsule_id, Role::Write, &db)
+        .await?;
+
+    let mut comment = capsule_comment(&capsule, comment_id, &db).await?;
+    comment.hidden = data.hidden;
+    comment.save(&db).await?;
+
+    Ok(comment.to_json(&comment.author(&db).await?))
+}
+
+/// The route that deletes a comment of a capsule, with its replies.
+///
+/// Comments can be deleted by their author and by the moderators of the capsule.
+#[delete("/v/<capsule_id>/comments/<comment_id>")]
+pub async fn delete_comment(user: User, capsule_id: HashId, comment_id: i32, db: Db) -> Result<()> {
+    let capsule = Capsule::get_by_id(*capsule_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    let comment = capsule_comment(&capsule, comment_id, &db).await?;
+
+    let moderator = user
+        .get_capsule_with_permission(capsule.id, Role::Write, &db)
+        .await
+        .is_ok();
+
+    if !moderator && comment.author(&db).await?.id != user.id {
+        return Err(Error(Status::Forbidden));
+    }
+
+    // The replies are deleted with their parent.
+    comment.delete(&db).await?;
+
+    Ok(())
+}
@@ -0,0 +1,296 @@
+//! This module contains the podcast feeds of the projects.
+//!
+//! The published capsules of a project that are public and can be downloaded are listed in an RSS
+//! 2.0 feed, with the iTunes and Podcasting 2.0 tags, whose enclosures are exports of the videos.
+//! The exports are generated when the capsules are published or when their download is allowed,
+//! the feeds only list the capsules whose export is ready.
+
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{TimeZone, Utc};
+
+use tokio::sync::Semaphore;
+
+use rocket::http::{ContentType, RawStr, Status};
+use rocket::State as S;
+
+use ergol::Pool;
+
+use crate::config::Config;
+use crate::db::capsule::{Capsule, Privacy, Role};
+use crate::db::export::ExportFormat;
+use crate::db::task_status::TaskStatus;
+use crate::db::user::User;
+use crate::routes::watch::{export_height, find_or_start_export};
+use crate::routes::{PartialContent, PartialContentResponse};
+use crate::storage::{self, capsule_key};
+use crate::templates::escape_html;
+use crate::{Db, Error, HashId, Result};
+
+/// The files of a publication that are linked from the feeds.
+const FEED_FILES: [&str; 4] = [
+    "chapters.json",
+    "transcript.txt",
+    "subtitles.webvtt",
+    "poster.jpg",
+];
+
+/// The kind of media of a feed.
+#[derive(FromFormField, Clone, Copy, PartialEq, Eq)]
+pub enum FeedKind {
+    /// An audio podcast.
+    Audio,
+
+    /// A video podcast.
+    Video,
+}
+
+impl FeedKind {
+    /// Returns the export format of the enclosures of the feed.
+    pub fn format(self) -> ExportFormat {
+        match self {
+            FeedKind::Audio => ExportFormat::M4a,
+            FeedKind::Video => ExportFormat::Mp4,
+        }
+    }
+
+    /// Returns the mime type of the enclosures of the feed.
+    pub fn mime(self) -> &'static str {
+        match self {
+            FeedKind::Audio => "audio/mp4",
+            FeedKind::Video => "video/mp4",
+        }
+    }
+}
+
+/// Returns true if a capsule can be listed in the feed of its project.
+fn is_in_feed(capsule: &Capsule) -> bool {
+    capsule.published == TaskStatus::Done
+        && capsule.privacy == Privacy::Public
+        && capsule.allow_download
+}
+
+/// Formats a duration in ms as the hh:mm:ss of the iTunes duration tag.
+fn itunes_duration(duration_ms: i32) -> String {
+    let seconds = duration_ms.max(0) / 1000;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+/// Starts the generation of the exports listed in the feeds, if the capsule is published and can
+/// be downloaded.
+pub async fn start_feed_exports(
+    capsule: &Capsule,
+    config: &Config,
+    sem: &Arc<Semaphore>,
+    pool: &Pool,
+) -> Result<()> {
+    if capsule.published != TaskStatus::Done || !capsule.allow_download {
+        return Ok(());
+    }
+
+    for kind in [FeedKind::Audio, FeedKind::Video] {
+        let db = Db::from_pool(pool.clone()).await?;
+        let format = kind.format();
+        let height = export_height(capsule, format, None, config, &db).await?;
+        find_or_start_export(capsule.clone(), format, height, config, sem, db).await?;
+    }
+
+    Ok(())
+}
+
+/// The route that gives the podcast feed of a project of a user.
+#[get("/feed/<username>/<project_name>?<kind>")]
+pub async fn feed(
+    username: String,
+    project_name: String,
+    kind: Option<FeedKind>,
+    config: &S<Config>,
+    db: Db,
+) -> Result<(ContentType, String)> {
+    let kind = kind.unwrap_or(FeedKind::Audio);
+
+    let user = User::get_by_username(&username, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    let (project, _) = user
+        .projects(&db)
+        .await?
+        .into_iter()
+        .find(|(x, role)| *role == Role::Owner && x.name == project_name)
+        .ok_or(Error(Status::NotFound))?;
+
+    let mut capsules = project
+        .capsules(&db)
+        .await?
+        .into_iter()
+        .filter(is_in_feed)
+        .collect::<Vec<_>>();
+
+    capsules.sort_by(|a, b| b.last_modified.cmp(&a.last_modified));
+
+    let storage = storage::open(&config)?;
+    let root = config.root.trim_end_matches('/');
+    let feed_url = format!(
+        "{}/feed/{}/{}",
+        root,
+        RawStr::new(&username).percent_encode().as_str(),
+        RawStr::new(&project.name).percent_encode().as_str()
+    );
+
+    let mut items = String::new();
+    let mut image = None;
+
+    for capsule in capsules {
+        let format = kind.format();
+        let height = export_height(&capsule, format, None, &config, &db).await?;
+
+        let export = capsule
+            .exports(&db)
+            .await?
+            .into_iter()
+            .find(|x| x.format == format && x.height == height && x.status == TaskStatus::Done);
+
+        let export = match export {
+            Some(export) => export,
+            None => continue,
+        };
+
+        let length = storage
+            .stat(&capsule_key(capsule.id, export.path())?)
+            .await?
+            .map(|x| x.len)
+            .unwrap_or(0);
+
+        let hash = HashId(capsule.id).hash();
+        let base = format!("{}/v/{}", root, hash);
+        let published = Utc.from_utc_datetime(&capsule.last_modified).to_rfc2822();
+
+        // Videos published before the posters were generated have none.
+        let poster = storage
+            .stat(&capsule_key(capsule.id, "output/poster.jpg")?)
+            .await?
+            .map(|_| format!("{}/feed/poster.jpg", base));
+
+        if image.is_none() {
+            image = poster.clone();
+        }
+
+        write!(
+            items,
+            r#"
+        <item>
+            <title>{title}</title>
+            <link>{base}</link>
+            <guid isPermaLink="false">polymny-{hash}-{export}</guid>
+            <pubDate>{published}</pubDate>
+            <enclosure url="{base}/exports/{export}/download" length="{length}" type="{mime}"/>
+            <itunes:duration>{duration}</itunes:duration>
+            <podcast:chapters url="{base}/feed/chapters.json" type="application/json+chapters"/>
+            <podcast:transcript url="{base}/feed/transcript.txt" type="text/plain"/>"#,
+            title = escape_html(&capsule.name),
+            base = base,
+            hash = hash,
+            export = export.id,
+            published = published,
+            length = length,
+            mime = kind.mime(),
+            duration = itunes_duration(capsule.duration_ms),
+        )
+        .ok();
+
+        if let Some(poster) = poster {
+            write!(
+                items,
+                r#"
+            <itunes:image href="{}"/>"#,
+                poster
+            )
+            .ok();
+        }
+
+        if capsule.prompt_subtitles {
+            write!(
+                items,
+                r#"
+            <podcast:transcript url="{}/feed/subtitles.webvtt" type="text/vtt"/>"#,
+                base
+            )
+            .ok();
+        }
+
+        items.push_str("\n        </item>");
+    }
+
+    let image = image
+        .map(|x| format!("\n        <itunes:image href=\"{}\"/>", x))
+        .unwrap_or_default();
+
+    let rss = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0" xmlns:itunes="http://www.itunes.com/dtds/podcast-1.0.dtd" xmlns:podcast="https://podcastindex.org/namespace/1.0" xmlns:atom="http://www.w3.org/2005/Atom">
+    <channel>
+        <title>{title}</title>
+        <link>{root}</link>
+        <description>{description}</description>
+        <language>{language}</language>
+        <atom:link href="{feed_url}" rel="self" type="application/rss+xml"/>
+        <itunes:author>{author}</itunes:author>
+        <itunes:explicit>false</itunes:explicit>
+        <itunes:category text="Education"/>{image}{items}
+    </channel>
+</rss>
+"#,
+        title = escape_html(&project.name),
+        description = escape_html(if project.description.is_empty() {
+            &project.name
+        } else {
+            &project.description
+        }),
+        root = root,
+        language = escape_html(&config.content_language),
+        feed_url = escape_html(&feed_url),
+        author = escape_html(&username),
+        image = image,
+        items = items,
+    );
+
+    Ok((ContentType::new("application", "rss+xml"), rss))
+}
+
+/// The route that serves the files of a publication linked from the feeds.
+#[get("/v/<capsule_id>/feed/<file>", rank = 1)]
+pub async fn feed_file<'a>(
+    capsule_id: HashId,
+    file: String,
+    config: &S<Config>,
+    db: Db,
+    partial_content: PartialContent,
+) -> Result<PartialContentResponse<'a>> {
+    if !FEED_FILES.contains(&file.as_str()) {
+        return Err(Error(Status::NotFound));
+    }
+
+    let capsule = Capsule::get_by_id(*capsule_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if !is_in_feed(&capsule) {
+        return Err(Error(Status::NotFound));
+    }
+
+    let storage = storage::open(&config)?;
+    partial_content
+        .serve(
+            storage,
+            &capsule_key(capsule.id, PathBuf::from("output").join(file))?,
+        )
+        .await
+}
@@ -1,19 +1,33 @@
 //! This module contains all the routes for the group and assignment management.
 
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use chrono::{NaiveDateTime, Utc};
 
-use tokio::fs::{copy, create_dir_all, read_dir};
+use serde::{Deserialize, Serialize};
 
 use rocket::http::Status;
 use rocket::serde::json::{json, Json, Value};
 use rocket::State as S;
 
+use ergol::tokio_postgres::types::Json as EJson;
+
 use crate::config::Config;
-use crate::db::capsule::{Capsule, Role};
-use crate::db::group::{Answer, Assignment, AssignmentState, Group, ParticipantRole};
+use crate::csv;
+use crate::db::audit::{AuditAction, AuditLog, Target};
+use crate::db::capsule::Role;
+use crate::db::group::{
+    validate_scores, weighted_total, Answer, Assignment, AssignmentState, AssignmentTemplate,
+    Criterion, Evaluation, Group, GroupInvite, ParticipantRole, TemplateCriterion,
+};
+use crate::db::quiz::QuizAnswer;
 use crate::db::user::User;
+use crate::ods::{self, Cell};
+use crate::rate_limit::RateLimiter;
+use crate::routes::Attachment;
 use crate::websockets::WebSockets;
-use crate::{Db, Error, HashId, Result};
+use crate::{Db, Error, HashId, Result, HARSH};
 
 /// The data for the new group form.
 #[derive(Serialize, Deserialize)]
@@ -32,7 +46,17 @@ pub async fn new_group(user: User, db: Db, form: Json<NewGroupFrom>) -> Result<V
         .add_participant(&user, ParticipantRole::Teacher, &db)
         .await?;
 
-    Ok(group.to_json(&db).await?)
+    AuditLog::record(
+        Some(&user),
+        AuditAction::GroupCreated,
+        Target::group(group.id),
+        None,
+        Some(json!({ "name": group.name })),
+        &db,
+    )
+    .await?;
+
+    Ok(group.to_json(&user, &db).await?)
 }
 
 /// The data for the new group form.
@@ -62,8 +86,19 @@ pub async fn delete_group(user: User, db: Db, form: Json<DeleteGroupFrom>) -> Re
         return Err(Error(Status::NotFound));
     }
 
+    let (group_id, name) = (group.id, group.name.clone());
     group.delete(&db).await?;
 
+    AuditLog::record(
+        Some(&user),
+        AuditAction::GroupDeleted,
+        Target::group(group_id),
+        Some(json!({ "name": name })),
+        None,
+        &db,
+    )
+    .await?;
+
     Ok(json!({}))
 }
 
@@ -120,7 +155,17 @@ pub async fn add_participant(user: User, db: Db, form: Json<AddParticipantForm>)
         .add_participant(&participant, form.participant_role, &db)
         .await?;
 
-    Ok(group.to_json(&db).await?)
+    AuditLog::record(
+        Some(&user),
+        AuditAction::ParticipantAdded,
+        Target::group(group.id).with_user(participant.id),
+        None,
+        Some(json!({ "role": form.participant_role })),
+        &db,
+    )
+    .await?;
+
+    Ok(group.to_json(&user, &db).await?)
 }
 
 /// The data for the remove participant form.
@@ -181,14 +226,406 @@ pub async fn remove_participant(
     // Remove the participant
     group.remove_participant(&participant, &db).await?;
 
-    Ok(group.to_json(&db).await?)
+    AuditLog::record(
+        Some(&user),
+        AuditAction::ParticipantRemoved,
+        Target::group(group.id).with_user(participant.id),
+        Some(json!({ "role": participant_role })),
+        None,
+        &db,
+    )
+    .await?;
+
+    Ok(group.to_json(&user, &db).await?)
+}
+
+/// The data for the new group invite form.
+#[derive(Serialize, Deserialize)]
+pub struct NewGroupInviteForm {
+    /// The id of the group that can be joined.
+    group_id: i32,
+
+    /// The role given to the users joining the group, students by default.
+    #[serde(default)]
+    role: Option<ParticipantRole>,
+
+    /// The moment after which the invite expires, as a unix timestamp in seconds.
+    #[serde(default)]
+    expires: Option<i64>,
+}
+
+/// Fetches a group and checks that the user is one of its teachers.
+async fn group_as_teacher(group_id: i32, user: &User, db: &Db) -> Result<Group> {
+    let group = Group::get_by_id(group_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if !group.is_teacher(user, db).await? {
+        return Err(Error(Status::Forbidden));
+    }
+
+    Ok(group)
+}
+
+/// Route to create a code that lets users join a group by themselves.
+#[post("/group-invite", data = "<form>")]
+pub async fn new_group_invite(user: User, db: Db, form: Json<NewGroupInviteForm>) -> Result<Value> {
+    let form = form.into_inner();
+    let group = group_as_teacher(form.group_id, &user, &db).await?;
+
+    let expires = parse_timestamp(form.expires)?;
+    if expires
+        .map(|x| x <= Utc::now().naive_utc())
+        .unwrap_or(false)
+    {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let role = form.role.unwrap_or(ParticipantRole::Student);
+    let invite = GroupInvite::new(&group, role, expires, &db).await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::GroupInviteCreated,
+        Target::group(group.id),
+        None,
+        Some(json!({ "invite": invite.id, "role": role, "expires": form.expires })),
+        &db,
+    )
+    .await?;
+
+    Ok(invite.to_json())
+}
+
+/// Route to revoke a code to join a group.
+#[delete("/group-invite/<invite_id>")]
+pub async fn delete_group_invite(user: User, db: Db, invite_id: i32) -> Result<Value> {
+    let invite = GroupInvite::get_by_id(invite_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    let group = group_as_teacher(invite.group(&db).await?.id, &user, &db).await?;
+
+    let role = invite.role;
+    invite.delete(&db).await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::GroupInviteRevoked,
+        Target::group(group.id),
+        Some(json!({ "invite": invite_id, "role": role })),
+        None,
+        &db,
+    )
+    .await?;
+
+    Ok(group.to_json(&user, &db).await?)
+}
+
+/// Route to join a group with a code.
+///
+/// Wrong codes are rate limited like failed logins, so that codes cannot be guessed.
+#[post("/join-group/<code>")]
+pub async fn join_group(
+    user: User,
+    db: Db,
+    config: &S<Config>,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
+    code: String,
+) -> Result<Value> {
+    let keys = RateLimiter::join_keys(ip, user.id);
+    limiter.check(&keys, config, &db).await?;
+
+    let invite = match GroupInvite::get_by_code(code, &db).await? {
+        Some(invite) => invite,
+        None => {
+            limiter.failure(&keys, config, &db).await?;
+            return Err(Error(Status::NotFound));
+        }
+    };
+
+    if !invite.is_valid() {
+        return Err(Error(Status::Gone));
+    }
+
+    let group = invite.group(&db).await?;
+
+    // Joining a group twice does nothing, the role of the user is not changed.
+    let participants = group.participants(&db).await?;
+    if participants.iter().all(|(p, _)| p.id != user.id) {
+        group.add_participant(&user, invite.role, &db).await?;
+
+        AuditLog::record(
+            Some(&user),
+            AuditAction::ParticipantAdded,
+            Target::group(group.id).with_user(user.id),
+            None,
+            Some(json!({ "role": invite.role, "invite": invite.id })),
+            &db,
+        )
+        .await?;
+    }
+
+    Ok(group.to_json(&user, &db).await?)
+}
+
+/// Finds a username that is not taken yet, based on the beginning of an email address.
+async fn available_username(email: &str, db: &Db) -> Result<String> {
+    let base = email
+        .split('@')
+        .next()
+        .unwrap_or("")
+        .chars()
+        .filter(|c| c.is_alphanumeric() || *c == '.' || *c == '_' || *c == '-')
+        .collect::<String>();
+
+    let base = if base.is_empty() {
+        String::from("student")
+    } else {
+        base
+    };
+
+    let mut username = base.clone();
+    let mut suffix = 1;
+
+    while User::get_by_username(&username, db).await?.is_some() {
+        suffix += 1;
+        username = format!("{}{}", base, suffix);
+    }
+
+    Ok(username)
+}
+
+/// Route to import a roster of participants into a group.
+///
+/// The roster is a CSV file whose columns are the email, the role (`student` by default) and the
+/// username to give to the account if the participant has none yet. Known users are added to the
+/// group directly, the others are invited to create an account and added to the group.
+///
+/// The whole roster is validated before anything is changed: if a row is invalid, nothing is
+/// imported and the invalid rows are reported as errors. The rows are then imported one by one,
+/// since the invitations sent by email cannot be undone: the rows that cannot be imported, for
+/// example because the number of accounts a user can create this way is rate limited, are reported
+/// as errors while the others are kept.
+#[post("/import-roster/<group_id>", data = "<roster>")]
+pub async fn import_roster(
+    user: User,
+    db: Db,
+    config: &S<Config>,
+    limiter: &S<RateLimiter>,
+    group_id: i32,
+    roster: String,
+) -> Result<Value> {
+    let group = group_as_teacher(group_id, &user, &db).await?;
+    let mut participants = group
+        .participants(&db)
+        .await?
+        .into_iter()
+        .map(|(p, _)| p.id)
+        .collect::<Vec<_>>();
+
+    let mut rows = vec![];
+    let mut added = vec![];
+    let mut invited = vec![];
+    let mut skipped = vec![];
+    let mut errors = vec![];
+
+    for (index, row) in csv::parse(&roster).into_iter().enumerate() {
+        let line = index + 1;
+        let email = row.get(0).map(|x| x.trim()).unwrap_or("").to_string();
+
+        // The header, if any, is the only row without an email address.
+        if index == 0 && !email.contains('@') {
+            continue;
+        }
+
+        if !email.contains('@') {
+            errors.push(json!({ "line": line, "email": email, "error": "invalid email" }));
+            continue;
+        }
+
+        let role = match row.get(1).map(|x| x.trim().to_lowercase()).as_deref() {
+            None | Some("") | Some("student") => ParticipantRole::Student,
+            Some("teacher") => ParticipantRole::Teacher,
+            Some(_) => {
+                errors.push(json!({ "line": line, "email": email, "error": "invalid role" }));
+                continue;
+            }
+        };
+
+        let username = row
+            .get(2)
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty());
+
+        rows.push((line, email, role, username));
+    }
+
+    if !errors.is_empty() {
+        rows.clear();
+    }
+
+    let keys = [format!("roster:{}", user.id)];
+
+    for (line, email, role, username) in rows {
+        let participant = match User::get_by_email(&email, &db).await? {
+            Some(participant) => participant,
+            None => {
+                // Without a mailer, the invitation cannot be sent and no account is created.
+                if config.mailer.is_none() {
+                    errors.push(
+                        json!({ "line": line, "email": email, "error": "invitation unavailable" }),
+                    );
+                    continue;
+                }
+
+                if limiter
+                    .request(&keys, config.roster_max_invitations, config)
+                    .await
+                    .is_err()
+                {
+                    errors.push(
+                        json!({ "line": line, "email": email, "error": "too many invitations" }),
+                    );
+                    continue;
+                }
+
+                let username = match username {
+                    Some(username) => username,
+                    None => available_username(&email, &db).await?,
+                };
+
+                let invitation = user
+                    .request_invitation(username, email.clone(), &config.mailer, &db, config)
+                    .await;
+
+                let participant = match invitation {
+                    Ok(()) => User::get_by_email(&email, &db).await?,
+                    Err(_) => None,
+                };
+
+                match participant {
+                    Some(participant) => {
+                        invited.push(email.clone());
+                        participant
+                    }
+                    None => {
+                        errors.push(
+                            json!({ "line": line, "email": email, "error": "invitation failed" }),
+                        );
+                        continue;
+                    }
+                }
+            }
+        };
+
+        if participants.contains(&participant.id) {
+            skipped.push(email);
+            continue;
+        }
+
+        group.add_participant(&participant, role, &db).await?;
+        participants.push(participant.id);
+
+        AuditLog::record(
+            Some(&user),
+            AuditAction::ParticipantAdded,
+            Target::group(group.id).with_user(participant.id),
+            None,
+            Some(json!({ "role": role, "roster": true })),
+            &db,
+        )
+        .await?;
+
+        added.push(email);
+    }
+
+    Ok(json!({
+        "group": group.to_json(&user, &db).await?,
+        "added": added,
+        "invited": invited,
+        "skipped": skipped,
+        "errors": errors,
+    }))
+}
+
+fn default_max_points() -> f64 {
+    10.0
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+/// A criterion of the rubric of an assignment.
+#[derive(Serialize, Deserialize)]
+pub struct CriterionForm {
+    /// The short name of the criterion.
+    pub label: String,
+
+    /// A longer explanation of what is expected.
+    #[serde(default)]
+    pub description: String,
+
+    /// The maximum number of points that can be given for this criterion.
+    #[serde(default = "default_max_points")]
+    pub max_points: f64,
+
+    /// The weight of the criterion in the total.
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+/// A criterion, either given by its label only or with all its details.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CriterionInput {
+    /// A criterion with only a label, that uses the default maximum and weight.
+    Label(String),
+
+    /// A criterion with all its details.
+    Criterion(CriterionForm),
+}
+
+impl CriterionInput {
+    /// Returns the details of the criterion.
+    pub fn into_form(self) -> CriterionForm {
+        match self {
+            CriterionInput::Label(label) => CriterionForm {
+                label,
+                description: String::new(),
+                max_points: default_max_points(),
+                weight: default_weight(),
+            },
+            CriterionInput::Criterion(form) => form,
+        }
+    }
+}
+
+/// Converts criteria inputs into their details, ignoring the ones without label.
+fn parse_criteria(criteria: Vec<CriterionInput>) -> Result<Vec<CriterionForm>> {
+    let criteria = criteria
+        .into_iter()
+        .map(CriterionInput::into_form)
+        .filter(|x| !x.label.trim().is_empty())
+        .collect::<Vec<_>>();
+
+    if criteria
+        .iter()
+        .any(|x| !(x.max_points > 0.0) || !(x.weight >= 0.0))
+    {
+        return Err(Error(Status::BadRequest));
+    }
+
+    Ok(criteria)
 }
 
 /// The data for the remove participant form.
 #[derive(Serialize, Deserialize)]
 pub struct NewAssignmentForm {
     /// The criteria for evaluation of the assignment.
-    pub criteria: Vec<String>,
+    pub criteria: Vec<CriterionInput>,
 
     /// The subject for the assignment.
     pub subject: HashId,
@@ -198,6 +635,18 @@ pub struct NewAssignmentForm {
 
     /// The id of the group to which you want to assign the task.
     pub group_id: i32,
+
+    /// The number of students that review each answer, zero disables peer review.
+    #[serde(default)]
+    pub peer_reviews: i32,
+
+    /// Whether students reviewing each other are anonymous to each other.
+    #[serde(default)]
+    pub anonymous_reviews: bool,
+
+    /// The deadline of the reviews as a unix timestamp, if any.
+    #[serde(default)]
+    pub review_deadline: Option<i64>,
 }
 
 /// Create a new assignment for a group.
@@ -229,25 +678,288 @@ pub async fn new_assignment(user: User, db: Db, form: Json<NewAssignmentForm>) -
         return Err(Error(Status::Forbidden));
     }
 
-    let criteria = form
-        .criteria
-        .iter()
-        .map(|x| x.trim())
-        .filter(|x| !x.is_empty())
-        .collect::<Vec<_>>()
-        .join("\n");
+    let criteria = parse_criteria(form.criteria)?;
+
+    if form.peer_reviews < 0 {
+        return Err(Error(Status::BadRequest));
+    }
 
+    let review_deadline = parse_timestamp(form.review_deadline)?;
+
+    let group_id = group.id;
     let assignment = Assignment::create(
-        criteria,
         subject,
         answer_template,
         group,
         AssignmentState::Preparation,
+        form.peer_reviews,
+        form.anonymous_reviews,
+        review_deadline,
+        None,
+        None,
+        None,
+    )
+    .save(&db)
+    .await?;
+
+    for criterion in criteria {
+        assignment
+            .add_criterion(
+                &criterion.label,
+                &criterion.description,
+                criterion.max_points,
+                criterion.weight,
+                &db,
+            )
+            .await?;
+    }
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::AssignmentCreated,
+        Target::group(group_id),
+        None,
+        Some(json!({ "assignment": assignment.id })),
+        &db,
+    )
+    .await?;
+
+    Ok(assignment.to_json(&user, &db).await?)
+}
+
+/// Form for replacing the rubric of an assignment.
+#[derive(Serialize, Deserialize)]
+pub struct RubricForm {
+    /// The id of the assignment.
+    pub assignment_id: i32,
+
+    /// The new criteria of the assignment.
+    pub criteria: Vec<CriterionInput>,
+}
+
+/// Replaces the rubric of an assignment, before its evaluation is opened.
+#[post("/assignment-criteria", data = "<form>")]
+pub async fn set_rubric(user: User, db: Db, form: Json<RubricForm>) -> Result<Value> {
+    let form = form.into_inner();
+    let assignment = assignment_as_teacher(form.assignment_id, &user, &db).await?;
+
+    if assignment.state >= AssignmentState::Evaluation {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let criteria = parse_criteria(form.criteria)?;
+
+    for criterion in assignment.criteria(&db).await? {
+        criterion.delete(&db).await?;
+    }
+
+    for criterion in criteria {
+        assignment
+            .add_criterion(
+                &criterion.label,
+                &criterion.description,
+                criterion.max_points,
+                criterion.weight,
+                &db,
+            )
+            .await?;
+    }
+
+    Ok(assignment.to_json(&user, &db).await?)
+}
+
+/// The data for the new assignment template form.
+#[derive(Serialize, Deserialize)]
+pub struct NewAssignmentTemplateForm {
+    /// The name of the template.
+    pub name: String,
+
+    /// The subject for the assignments.
+    pub subject: HashId,
+
+    /// The template for answering the subject.
+    pub answer_template: HashId,
+
+    /// The criteria for evaluation of the assignments.
+    pub criteria: Vec<CriterionInput>,
+
+    /// The number of students that review each answer, zero disables peer review.
+    #[serde(default)]
+    pub peer_reviews: i32,
+
+    /// Whether students reviewing each other are anonymous to each other.
+    #[serde(default)]
+    pub anonymous_reviews: bool,
+
+    /// The number of hours between the opening of an assignment and its due date, if any.
+    #[serde(default)]
+    pub due_after: Option<i32>,
+
+    /// The number of hours between the opening of an assignment and its closing, if any.
+    #[serde(default)]
+    pub closes_after: Option<i32>,
+
+    /// The number of hours students have to review each other after the closing, if any.
+    #[serde(default)]
+    pub review_duration: Option<i32>,
+}
+
+/// Creates a template of assignment that can be given to several groups.
+#[post("/assignment-template", data = "<form>")]
+pub async fn new_assignment_template(
+    user: User,
+    db: Db,
+    form: Json<NewAssignmentTemplateForm>,
+) -> Result<Value> {
+    let form = form.into_inner();
+
+    let (subject, _) = user
+        .get_capsule_with_permission(*form.subject, Role::Write, &db)
+        .await?;
+
+    let (answer_template, _) = user
+        .get_capsule_with_permission(*form.answer_template, Role::Write, &db)
+        .await?;
+
+    if form.name.trim().is_empty() || form.peer_reviews < 0 {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let durations = [form.due_after, form.closes_after, form.review_duration];
+    if durations.iter().flatten().any(|x| *x < 0) {
+        return Err(Error(Status::BadRequest));
+    }
+
+    if let (Some(due), Some(closes)) = (form.due_after, form.closes_after) {
+        if due > closes {
+            return Err(Error(Status::BadRequest));
+        }
+    }
+
+    let criteria = parse_criteria(form.criteria)?
+        .into_iter()
+        .map(|x| TemplateCriterion {
+            label: x.label.trim().to_string(),
+            description: x.description.trim().to_string(),
+            max_points: x.max_points,
+            weight: x.weight,
+        })
+        .collect::<Vec<_>>();
+
+    let template = AssignmentTemplate::create(
+        form.name.trim().to_string(),
+        &user,
+        subject,
+        answer_template,
+        EJson(criteria),
+        form.peer_reviews,
+        form.anonymous_reviews,
+        form.due_after,
+        form.closes_after,
+        form.review_duration,
     )
     .save(&db)
     .await?;
 
-    Ok(assignment.to_json(&db).await?)
+    Ok(template.to_json(&db).await?)
+}
+
+/// Returns the assignment templates of the user.
+#[get("/assignment-templates")]
+pub async fn assignment_templates(user: User, db: Db) -> Result<Value> {
+    let mut templates = vec![];
+    for template in user.assignment_templates(&db).await? {
+        templates.push(template.to_json(&db).await?);
+    }
+
+    Ok(json!(templates))
+}
+
+/// Deletes an assignment template, the assignments created from it are kept.
+#[delete("/assignment-template/<template_id>")]
+pub async fn delete_assignment_template(user: User, db: Db, template_id: i32) -> Result<()> {
+    let template = AssignmentTemplate::get_by_id(template_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if template.owner(&db).await?.id != user.id {
+        return Err(Error(Status::NotFound));
+    }
+
+    template.delete(&db).await?;
+
+    Ok(())
+}
+
+/// Form for creating assignments from a template.
+#[derive(Serialize, Deserialize)]
+pub struct InstantiateTemplateForm {
+    /// The id of the template.
+    pub template_id: i32,
+
+    /// The ids of the groups that receive the assignment.
+    pub group_ids: Vec<i32>,
+
+    /// The moment the assignments open as a unix timestamp, from which the other dates are
+    /// computed.
+    #[serde(default)]
+    pub opens: Option<i64>,
+}
+
+/// Creates an assignment from a template in each of the given groups.
+#[post("/instantiate-template", data = "<form>")]
+pub async fn instantiate_template(
+    user: User,
+    db: Db,
+    config: &S<Config>,
+    form: Json<InstantiateTemplateForm>,
+) -> Result<Value> {
+    let form = form.into_inner();
+
+    let template = AssignmentTemplate::get_by_id(form.template_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if template.owner(&db).await?.id != user.id {
+        return Err(Error(Status::NotFound));
+    }
+
+    // The capsules are copied, so the user must still be allowed to edit them.
+    user.get_capsule_with_permission(template.subject(&db).await?.id, Role::Write, &db)
+        .await?;
+    user.get_capsule_with_permission(template.answer_template(&db).await?.id, Role::Write, &db)
+        .await?;
+
+    let opens = parse_timestamp(form.opens)?;
+
+    // Every group is checked before any assignment is created.
+    let mut groups: Vec<Group> = vec![];
+    for group_id in form.group_ids {
+        if groups.iter().all(|x| x.id != group_id) {
+            groups.push(group_as_teacher(group_id, &user, &db).await?);
+        }
+    }
+
+    let mut assignments = vec![];
+    for group in &groups {
+        let assignment = template
+            .instantiate(group, opens, &user, &config, &db)
+            .await?;
+
+        AuditLog::record(
+            Some(&user),
+            AuditAction::AssignmentCreated,
+            Target::group(group.id),
+            None,
+            Some(json!({ "assignment": assignment.id, "template": template.id })),
+            &db,
+        )
+        .await?;
+
+        assignments.push(assignment.to_json(&user, &db).await?);
+    }
+
+    Ok(json!(assignments))
 }
 
 /// Form for deleting an assignment.
@@ -270,7 +982,8 @@ pub async fn delete_assignment(
         .await?
         .ok_or(Error(Status::NotFound))?;
 
-    let participants = assignment.group(&db).await?.participants(&db).await?;
+    let group = assignment.group(&db).await?;
+    let participants = group.participants(&db).await?;
 
     // Check that user is a teacher from the group
     let mut allowed = false;
@@ -285,11 +998,42 @@ pub async fn delete_assignment(
         return Err(Error(Status::Forbidden));
     }
 
+    let assignment_id = assignment.id;
     assignment.delete(&db).await?;
 
+    AuditLog::record(
+        Some(&user),
+        AuditAction::AssignmentDeleted,
+        Target::group(group.id),
+        Some(json!({ "assignment": assignment_id })),
+        None,
+        &db,
+    )
+    .await?;
+
     Ok(())
 }
 
+/// Form that references an assignment.
+#[derive(Serialize, Deserialize)]
+pub struct AssignmentForm {
+    /// The id of the assignment.
+    pub assignment_id: i32,
+}
+
+/// Fetches an assignment and checks that the user is a teacher of its group.
+async fn assignment_as_teacher(assignment_id: i32, user: &User, db: &Db) -> Result<Assignment> {
+    let assignment = Assignment::get_by_id(assignment_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if !assignment.group(&db).await?.is_teacher(user, db).await? {
+        return Err(Error(Status::Forbidden));
+    }
+
+    Ok(assignment)
+}
+
 /// Form for validating an assignment.
 #[derive(Serialize, Deserialize)]
 pub struct ValidateAssignmentForm {
@@ -306,113 +1050,104 @@ pub async fn validate_assignment(
     socks: &S<WebSockets>,
     form: Json<ValidateAssignmentForm>,
 ) -> Result<()> {
-    let form = form.into_inner();
+    let mut assignment = assignment_as_teacher(form.assignment_id, &user, &db).await?;
+    assignment.start(Some(&user), &config, &socks, &db).await
+}
 
-    let assignment = Assignment::get_by_id(form.assignment_id, &db)
-        .await?
-        .ok_or(Error(Status::NotFound))?;
+/// Form for setting the dates of an assignment.
+#[derive(Serialize, Deserialize)]
+pub struct AssignmentDatesForm {
+    /// The id of the assignment.
+    pub assignment_id: i32,
 
-    let participants = assignment.group(&db).await?.participants(&db).await?;
+    /// The moment the assignment is automatically started, as a unix timestamp.
+    pub opens: Option<i64>,
 
-    // Check that user is a teacher from the group
-    let mut allowed = false;
+    /// The moment the answers are due, as a unix timestamp.
+    pub due: Option<i64>,
 
-    for (participant, role) in &participants {
-        if participant.id == user.id && *role == ParticipantRole::Teacher {
-            allowed = true;
-        }
-    }
+    /// The moment the assignment is automatically closed, as a unix timestamp.
+    pub closes: Option<i64>,
+}
 
-    if !allowed {
-        return Err(Error(Status::Forbidden));
+/// Converts an optional unix timestamp into a date.
+fn parse_timestamp(timestamp: Option<i64>) -> Result<Option<NaiveDateTime>> {
+    match timestamp {
+        Some(x) => Ok(Some(
+            NaiveDateTime::from_timestamp_opt(x, 0).ok_or(Error(Status::BadRequest))?,
+        )),
+        None => Ok(None),
     }
+}
 
-    let subject = assignment.subject(&db).await?;
-    let template = assignment.answer_template(&db).await?;
-
-    // Prepare answers and make subject read for students
-    for (student, role) in participants {
-        if role == ParticipantRole::Teacher {
-            continue;
-        }
-
-        subject.add_user(&student, Role::Read, &db).await?;
-
-        let mut new = Capsule::new(
-            &template.project,
-            format!("{} ({})", template.name, &student.username),
-            &user,
-            &db,
-        )
-        .await?;
-
-        new.privacy = template.privacy.clone();
-        new.produced = template.produced;
-        new.structure = template.structure.clone();
-        new.webcam_settings = template.webcam_settings.clone();
-        new.sound_track = template.sound_track.clone();
-        new.duration_ms = template.duration_ms;
-
-        for dir in ["assets", "tmp", "output"] {
-            let orig = config.data_path.join(&format!("{}/{}", template.id, dir));
-            let dest = config.data_path.join(&format!("{}/{}", new.id, dir));
+/// Sets the dates of an assignment.
+#[post("/assignment-dates", data = "<form>")]
+pub async fn assignment_dates(
+    user: User,
+    db: Db,
+    form: Json<AssignmentDatesForm>,
+) -> Result<Value> {
+    let form = form.into_inner();
+    let mut assignment = assignment_as_teacher(form.assignment_id, &user, &db).await?;
 
-            if orig.is_dir() {
-                create_dir_all(&dest).await?;
+    if assignment.state >= AssignmentState::Evaluation {
+        return Err(Error(Status::BadRequest));
+    }
 
-                let mut iter = read_dir(&orig)
-                    .await
-                    .map_err(|_| Error(Status::InternalServerError))?;
+    let opens = parse_timestamp(form.opens)?;
+    let due = parse_timestamp(form.due)?;
+    let closes = parse_timestamp(form.closes)?;
 
-                loop {
-                    let next = iter
-                        .next_entry()
-                        .await
-                        .map_err(|_| Error(Status::InternalServerError))?;
+    // The dates must be in chronological order.
+    let dates = [opens, due, closes]
+        .iter()
+        .flatten()
+        .copied()
+        .collect::<Vec<_>>();
+    if dates.windows(2).any(|x| x[0] > x[1]) {
+        return Err(Error(Status::BadRequest));
+    }
 
-                    let next = match next {
-                        Some(x) => x,
-                        None => break,
-                    };
+    assignment.opens = opens;
+    assignment.due = due;
+    assignment.closes = closes;
+    assignment.save(&db).await?;
 
-                    let path = next.path();
-                    let file_name = path.file_name().ok_or(Error(Status::InternalServerError))?;
+    Ok(assignment.to_json(&user, &db).await?)
+}
 
-                    copy(orig.join(&file_name), dest.join(&file_name))
-                        .await
-                        .map_err(|_| Error(Status::InternalServerError))?;
-                }
-            }
-        }
+/// Form for granting an extension to a student.
+#[derive(Serialize, Deserialize)]
+pub struct ExtensionForm {
+    /// The id of the answer of the student.
+    pub answer_id: i32,
 
-        let orig = config
-            .data_path
-            .join(&format!("{}/output.mp4", template.id));
-        let dest = config.data_path.join(&format!("{}/output.mp4", new.id));
+    /// The new deadline of the student as a unix timestamp, or None to remove the extension.
+    pub deadline: Option<i64>,
+}
 
-        if orig.is_file() {
-            copy(orig, dest)
-                .await
-                .map_err(|_| Error(Status::InternalServerError))?;
-        }
+/// Grants an extension of the deadline to a student.
+#[post("/extend-deadline", data = "<form>")]
+pub async fn extend_deadline(user: User, db: Db, form: Json<ExtensionForm>) -> Result<Value> {
+    let form = form.into_inner();
 
-        new.add_user(&student, Role::Write, &db).await?;
+    let mut answer = Answer::get_by_id(form.answer_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
 
-        new.set_changed();
-        new.save(&db).await?;
-        new.notify_change(&db, &socks).await?;
+    let assignment = assignment_as_teacher(answer.assignment(&db).await?.id, &user, &db).await?;
 
-        Answer::new(&assignment, &new).save(&db).await?;
+    if assignment.state >= AssignmentState::Evaluation {
+        return Err(Error(Status::BadRequest));
     }
 
-    let mut assignment = Assignment::get_by_id(form.assignment_id, &db)
-        .await?
-        .ok_or(Error(Status::NotFound))?;
+    answer.extension = parse_timestamp(form.deadline)?;
 
-    assignment.state = AssignmentState::Working;
-    assignment.save(&db).await?;
+    // The reminders start again for the new deadline.
+    answer.reminders_sent = 0;
+    answer.save(&db).await?;
 
-    Ok(())
+    Ok(answer.to_json(&db).await?)
 }
 
 /// Form for validating an answer.
@@ -445,11 +1180,547 @@ pub async fn validate_answer(user: User, db: Db, form: Json<ValidateAnswerForm>)
         return Err(Error(Status::Forbidden));
     }
 
+    // Answers can only be submitted while the assignment is open.
+    let assignment = answer.assignment(&db).await?;
+    if assignment.state != AssignmentState::Working {
+        return Err(Error(Status::Forbidden));
+    }
+
     capsule.remove_user(&user, &db).await?;
     capsule.add_user(&user, Role::Read, &db).await?;
 
+    let now = Utc::now().naive_utc();
     answer.finished = true;
+    answer.submitted = Some(now);
+    answer.late = assignment
+        .deadline_for(&answer)
+        .map(|x| now > x)
+        .unwrap_or(false);
     answer.save(&db).await?;
 
+    AuditLog::record(
+        Some(&user),
+        AuditAction::AnswerValidated,
+        Target::capsule(capsule.id).with_user(user.id),
+        None,
+        Some(json!({ "answer": answer.id, "late": answer.late })),
+        &db,
+    )
+    .await?;
+
     Ok(())
 }
+
+/// Opens the evaluation of an assignment.
+///
+/// The answers that were not submitted yet are submitted, and students can no longer edit them.
+#[post("/open-evaluation", data = "<form>")]
+pub async fn open_evaluation(user: User, db: Db, form: Json<AssignmentForm>) -> Result<Value> {
+    let mut assignment = assignment_as_teacher(form.assignment_id, &user, &db).await?;
+    assignment.open_evaluation(Some(&user), &db).await?;
+    Ok(assignment.to_json(&user, &db).await?)
+}
+
+/// The points given for a criterion.
+#[derive(Serialize, Deserialize)]
+pub struct ScoreForm {
+    /// The id of the criterion.
+    pub criterion: i32,
+
+    /// The number of points given.
+    pub points: f64,
+}
+
+/// Form for evaluating an answer.
+#[derive(Serialize, Deserialize)]
+pub struct EvaluateAnswerForm {
+    /// The id of the answer to evaluate.
+    pub answer_id: i32,
+
+    /// The points given for each criterion of the assignment.
+    pub scores: Vec<ScoreForm>,
+
+    /// A capsule giving feedback to the student, if any.
+    pub feedback_capsule: Option<HashId>,
+}
+
+/// Scores an answer against the criteria of its assignment.
+///
+/// Teachers can evaluate any answer, and evaluating an answer twice replaces their previous
+/// evaluation, which overrides the peer reviews. Students can only submit the peer reviews they
+/// were assigned, before the review deadline.
+#[post("/evaluate-answer", data = "<form>")]
+pub async fn evaluate_answer(user: User, db: Db, form: Json<EvaluateAnswerForm>) -> Result<Value> {
+    let form = form.into_inner();
+
+    let answer = Answer::get_by_id(form.answer_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    let assignment = answer.assignment(&db).await?;
+    let teacher = assignment.group(&db).await?.is_teacher(&user, &db).await?;
+
+    if assignment.state != AssignmentState::Evaluation {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let mut previous = None;
+    for evaluation in answer.evaluations(&db).await? {
+        if evaluation.reviewer(&db).await?.id == user.id && evaluation.peer != teacher {
+            previous = Some(evaluation);
+            break;
+        }
+    }
+
+    if !teacher {
+        // Students can only fill the peer reviews they were assigned.
+        if previous.is_none() || !assignment.accepts_peer_reviews() {
+            return Err(Error(Status::Forbidden));
+        }
+
+        if form.feedback_capsule.is_some() {
+            return Err(Error(Status::BadRequest));
+        }
+    }
+
+    // The scores are validated against the rubric before anything is saved.
+    let rubric = assignment.rubric(&db).await?;
+    let scores = form
+        .scores
+        .iter()
+        .map(|x| (x.criterion, x.points))
+        .collect::<Vec<_>>();
+    validate_scores(&rubric, &scores)?;
+
+    // The student can watch the feedback capsule.
+    let feedback_capsule = match form.feedback_capsule {
+        Some(id) => {
+            let (capsule, _) = user
+                .get_capsule_with_permission(*id, Role::Write, &db)
+                .await?;
+
+            let student = answer.student(&db).await?;
+            if !capsule
+                .users(&db)
+                .await?
+                .iter()
+                .any(|(x, _)| x.id == student.id)
+            {
+                capsule.add_user(&student, Role::Read, &db).await?;
+            }
+
+            Some(capsule.id)
+        }
+        None => None,
+    };
+
+    let mut evaluation = match previous {
+        Some(mut evaluation) => {
+            evaluation.feedback_capsule = feedback_capsule;
+            evaluation
+        }
+        None => {
+            Evaluation::create(&answer, &user, false, feedback_capsule, false)
+                .save(&db)
+                .await?
+        }
+    };
+
+    evaluation.set_scores(&rubric, &scores, &db).await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::AnswerEvaluated,
+        Target::capsule(answer.capsule(&db).await?.id),
+        None,
+        Some(json!({
+            "answer": answer.id,
+            "peer": evaluation.peer,
+            "scores": scores,
+        })),
+        &db,
+    )
+    .await?;
+
+    Ok(evaluation.to_json(true, &db).await?)
+}
+
+/// Form for configuring the peer review of an assignment.
+#[derive(Serialize, Deserialize)]
+pub struct PeerReviewForm {
+    /// The id of the assignment.
+    pub assignment_id: i32,
+
+    /// The number of students that review each answer, zero disables peer review.
+    pub peer_reviews: i32,
+
+    /// Whether students reviewing each other are anonymous to each other.
+    pub anonymous_reviews: bool,
+
+    /// The deadline of the reviews as a unix timestamp, if any.
+    pub review_deadline: Option<i64>,
+}
+
+/// Configures the peer review of an assignment, before its evaluation is opened.
+#[post("/configure-peer-review", data = "<form>")]
+pub async fn configure_peer_review(
+    user: User,
+    db: Db,
+    form: Json<PeerReviewForm>,
+) -> Result<Value> {
+    let form = form.into_inner();
+    let mut assignment = assignment_as_teacher(form.assignment_id, &user, &db).await?;
+
+    if assignment.state >= AssignmentState::Evaluation || form.peer_reviews < 0 {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let review_deadline = parse_timestamp(form.review_deadline)?;
+
+    assignment.peer_reviews = form.peer_reviews;
+    assignment.anonymous_reviews = form.anonymous_reviews;
+    assignment.review_deadline = review_deadline;
+    assignment.save(&db).await?;
+
+    Ok(assignment.to_json(&user, &db).await?)
+}
+
+/// Returns the peer reviews assigned to the user for an assignment.
+#[get("/peer-reviews/<assignment_id>")]
+pub async fn peer_reviews(user: User, db: Db, assignment_id: i32) -> Result<Value> {
+    let assignment = Assignment::get_by_id(assignment_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    let mut reviews = vec![];
+    for evaluation in user.evaluations(&db).await? {
+        if !evaluation.peer {
+            continue;
+        }
+
+        let answer = evaluation.answer(&db).await?;
+        if answer.assignment(&db).await?.id != assignment.id {
+            continue;
+        }
+
+        let author = if assignment.anonymous_reviews {
+            None
+        } else {
+            Some(answer.student(&db).await?.username)
+        };
+
+        reviews.push(json!({
+            "evaluation": evaluation.to_json(true, &db).await?,
+            "capsule": HARSH.encode(answer.capsule(&db).await?.id),
+            "author": author,
+        }));
+    }
+
+    let criteria = assignment.rubric(&db).await?;
+
+    Ok(json!({
+        "assignment": assignment.id,
+        "criteria": criteria.iter().map(Criterion::to_json).collect::<Vec<_>>(),
+        "open": assignment.accepts_peer_reviews(),
+        "review_deadline": assignment.review_deadline.map(|x| x.timestamp()),
+        "reviews": reviews,
+    }))
+}
+
+/// Closes an assignment, which makes the grades visible to the students.
+#[post("/close-assignment", data = "<form>")]
+pub async fn close_assignment(user: User, db: Db, form: Json<AssignmentForm>) -> Result<Value> {
+    let mut assignment = assignment_as_teacher(form.assignment_id, &user, &db).await?;
+
+    assignment
+        .transition(AssignmentState::Finished, Some(&user), &db)
+        .await?;
+
+    Ok(assignment.to_json(&user, &db).await?)
+}
+
+/// Returns the grades of an assignment.
+///
+/// Teachers see the evaluations of every answer, students only see the evaluations of their own
+/// answer, once the assignment is finished.
+#[get("/grades/<assignment_id>")]
+pub async fn grades(user: User, db: Db, assignment_id: i32) -> Result<Value> {
+    let assignment = Assignment::get_by_id(assignment_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    let group = assignment.group(&db).await?;
+    let teacher = group.is_teacher(&user, &db).await?;
+
+    if !teacher {
+        let member = group
+            .participants(&db)
+            .await?
+            .iter()
+            .any(|(x, _)| x.id == user.id);
+
+        if !member {
+            return Err(Error(Status::NotFound));
+        }
+
+        if assignment.state != AssignmentState::Finished {
+            return Err(Error(Status::Forbidden));
+        }
+    }
+
+    let criteria = assignment.rubric(&db).await?;
+
+    let mut grades = vec![];
+    for answer in assignment.answers(&db).await? {
+        let student = answer.student(&db).await?;
+
+        if !teacher && student.id != user.id {
+            continue;
+        }
+
+        let evaluations = answer.evaluations(&db).await?;
+
+        let (peer_scores, teacher_scores) = answer.partial_scores(&criteria, &db).await?;
+        let scores = answer.final_scores(&criteria, &db).await?;
+        let total = scores.as_ref().map(|x| weighted_total(&criteria, x));
+
+        // Students do not know who reviewed them if peer reviews are anonymous.
+        let mut evaluations_json = vec![];
+        for evaluation in &evaluations {
+            let show_reviewer = teacher || !evaluation.peer || !assignment.anonymous_reviews;
+            evaluations_json.push(evaluation.to_json(show_reviewer, &db).await?);
+        }
+
+        grades.push(json!({
+            "answer": answer.id,
+            "student": student.username,
+            "capsule": HARSH.encode(answer.capsule(&db).await?.id),
+            "evaluations": evaluations_json,
+            "peer_scores": peer_scores,
+            "teacher_scores": teacher_scores,
+            "scores": scores,
+            "total": total,
+        }));
+    }
+
+    Ok(json!({
+        "assignment": assignment.id,
+        "criteria": criteria.iter().map(Criterion::to_json).collect::<Vec<_>>(),
+        "max_total": weighted_total(&criteria, &criteria.iter().map(|x| x.max_points).collect::<Vec<_>>()),
+        "state": assignment.state,
+        "grades": grades,
+    }))
+}
+
+/// Route to export the watch progress of the students of a group as CSV.
+#[get("/group-progress/<group_id>")]
+pub async fn group_progress(user: User, db: Db, group_id: i32) -> Result<Attachment> {
+    let group = group_as_teacher(group_id, &user, &db).await?;
+
+    let mut content = csv::row(&[
+        "assignment",
+        "subject",
+        "username",
+        "email",
+        "watched (%)",
+        "position (s)",
+        "last watched",
+    ]);
+
+    for assignment in group.assignments(&db).await? {
+        let subject = assignment.subject(&db).await?;
+
+        for (student, progress) in assignment.watch_progress(&db).await? {
+            let watched = progress
+                .as_ref()
+                .map(|x| x.ratio(subject.duration_ms))
+                .unwrap_or(0.0);
+
+            content.push_str(&csv::row(&[
+                assignment.id.to_string(),
+                subject.name.clone(),
+                student.username,
+                student.email,
+                format!("{:.0}", watched * 100.0),
+                format!(
+                    "{}",
+                    progress.as_ref().map(|x| x.position).unwrap_or(0) / 1000
+                ),
+                progress
+                    .as_ref()
+                    .map(|x| x.updated.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+            ]));
+        }
+    }
+
+    Ok(Attachment::csv(format!("{}.csv", group.name), content))
+}
+
+/// Returns the answers of the students of a group to the quizzes of the subjects of its
+/// assignments.
+///
+/// For each assignment whose subject has quizzes, every student is listed with their first answer
+/// to each quiz and the number of correct answers.
+#[get("/group-quizzes/<group_id>")]
+pub async fn group_quizzes(user: User, db: Db, group_id: i32) -> Result<Value> {
+    let group = group_as_teacher(group_id, &user, &db).await?;
+
+    let mut students = group
+        .participants(&db)
+        .await?
+        .into_iter()
+        .filter(|(_, role)| *role == ParticipantRole::Student)
+        .map(|(student, _)| student)
+        .collect::<Vec<_>>();
+    students.sort_by(|a, b| a.username.cmp(&b.username));
+
+    let mut assignments = vec![];
+    for assignment in group.assignments(&db).await? {
+        let subject = assignment.subject(&db).await?;
+        let quizzes = subject.quizzes();
+
+        if quizzes.is_empty() {
+            continue;
+        }
+
+        let answers = QuizAnswer::by_user(&subject, &db).await?;
+
+        let results = students
+            .iter()
+            .map(|student| {
+                let answers = quizzes
+                    .iter()
+                    .map(|(_, quiz)| {
+                        answers
+                            .get(&(student.id, quiz.id.to_string()))
+                            .map(|x| json!({ "option": x.option, "correct": x.correct }))
+                    })
+                    .collect::<Vec<_>>();
+
+                let score = answers
+                    .iter()
+                    .flatten()
+                    .filter(|x| x["correct"] == true)
+                    .count();
+
+                json!({
+                    "username": student.username,
+                    "answers": answers,
+                    "score": score,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        assignments.push(json!({
+            "assignment": assignment.id,
+            "subject": HARSH.encode(subject.id),
+            "name": subject.name,
+            "quizzes": quizzes.iter().map(|(_, quiz)| json!({
+                "id": quiz.id,
+                "question": quiz.question,
+                "options": quiz.options,
+                "answer": quiz.answer,
+            })).collect::<Vec<_>>(),
+            "students": results,
+        }));
+    }
+
+    Ok(json!({
+        "group": group.id,
+        "assignments": assignments,
+    }))
+}
+
+/// Route to export the grade book of a group.
+///
+/// There is one row per student, and for each assignment the score of each criterion, the total,
+/// whether the answer was submitted and whether it was late. The format is either `csv` (default)
+/// or `ods`.
+#[get("/grade-book/<group_id>?<format>")]
+pub async fn grade_book(
+    user: User,
+    db: Db,
+    group_id: i32,
+    format: Option<String>,
+) -> Result<Attachment> {
+    let group = group_as_teacher(group_id, &user, &db).await?;
+
+    let mut students = group
+        .participants(&db)
+        .await?
+        .into_iter()
+        .filter(|(_, role)| *role == ParticipantRole::Student)
+        .map(|(student, _)| student)
+        .collect::<Vec<_>>();
+    students.sort_by(|a, b| a.username.cmp(&b.username));
+
+    let mut header: Vec<Cell> = vec!["username".into(), "email".into()];
+    let mut rows = students
+        .iter()
+        .map(|x| vec![Cell::from(x.username.clone()), Cell::from(x.email.clone())])
+        .collect::<Vec<_>>();
+
+    for assignment in group.assignments(&db).await? {
+        let subject = assignment.subject(&db).await?;
+        let criteria = assignment.rubric(&db).await?;
+
+        // Several assignments can share the name of their subject, the id tells them apart.
+        let title = format!("{} (#{})", subject.name, assignment.id);
+
+        for criterion in &criteria {
+            header.push(format!("{} - {}", title, criterion.label).into());
+        }
+        header.push(format!("{} - total", title).into());
+        header.push(format!("{} - finished", title).into());
+        header.push(format!("{} - late", title).into());
+
+        let mut answers = HashMap::new();
+        for answer in assignment.answers(&db).await? {
+            answers.insert(answer.student(&db).await?.id, answer);
+        }
+
+        for (student, row) in students.iter().zip(rows.iter_mut()) {
+            let answer = match answers.get(&student.id) {
+                Some(answer) => answer,
+                None => {
+                    row.extend((0..criteria.len() + 3).map(|_| Cell::Empty));
+                    continue;
+                }
+            };
+
+            match answer.final_scores(&criteria, &db).await? {
+                Some(scores) => {
+                    let total = weighted_total(&criteria, &scores);
+                    row.extend(scores.into_iter().map(Cell::Float));
+                    row.push(Cell::Float(total));
+                }
+                None => row.extend((0..criteria.len() + 1).map(|_| Cell::Empty)),
+            }
+
+            let yes_no = |x: bool| Cell::from(if x { "yes" } else { "no" });
+            row.push(yes_no(answer.finished));
+            row.push(yes_no(answer.late));
+        }
+    }
+
+    rows.insert(0, header);
+
+    match format.as_deref() {
+        None | Some("csv") => {
+            let content = rows
+                .iter()
+                .map(|row| csv::row(&row.iter().map(Cell::to_string).collect::<Vec<_>>()))
+                .collect::<String>();
+
+            Ok(Attachment::csv(format!("{}.csv", group.name), content))
+        }
+
+        Some("ods") => Ok(Attachment::ods(
+            format!("{}.ods", group.name),
+            ods::write(&group.name, &rows)?,
+        )),
+
+        Some(_) => Err(Error(Status::BadRequest)),
+    }
+}
@@ -1,15 +1,23 @@
 //! This module contains all the routes of the app.
 
-use std::io::SeekFrom;
+use std::future::Future;
+use std::io::{self, Cursor, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::result::Result as StdResult;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use chrono::{DateTime, Utc};
+
+use uuid::Uuid;
 
 use tokio::fs::File;
+use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncSeekExt;
+use tokio::io::ReadBuf;
 
-use rocket::fs::NamedFile;
-use rocket::http::{Header, Status};
+use rocket::http::{ContentType, Header, Status};
 use rocket::request::{FromRequest, Outcome, Request};
 use rocket::response::content::RawHtml as Html;
 use rocket::response::{self, Redirect, Responder, Response};
@@ -19,13 +27,17 @@ use rocket::State as S;
 use crate::config::Config;
 use crate::db::capsule::Role;
 use crate::db::user::{Plan, User};
+use crate::storage::{self, capsule_key, file_info, FileInfo, Storage};
 use crate::templates::index_html;
 use crate::{Db, Error, HashId, Lang, Result};
 
 pub mod admin;
 pub mod capsule;
+pub mod comment;
+pub mod feed;
 pub mod group;
 pub mod notification;
+pub mod project;
 pub mod user;
 pub mod watch;
 
@@ -60,6 +72,77 @@ where
     }
 }
 
+/// A file that is sent to be downloaded.
+pub struct Attachment {
+    /// The name under which the file is saved.
+    pub filename: String,
+
+    /// The content type of the file.
+    pub content_type: ContentType,
+
+    /// The content of the file.
+    pub content: Vec<u8>,
+}
+
+impl Attachment {
+    /// Creates a CSV attachment.
+    pub fn csv<S: Into<String>>(filename: S, content: String) -> Attachment {
+        Attachment {
+            filename: filename.into(),
+            content_type: ContentType::CSV,
+            content: content.into_bytes(),
+        }
+    }
+
+    /// Creates an OpenDocument spreadsheet attachment.
+    pub fn ods<S: Into<String>>(filename: S, content: Vec<u8>) -> Attachment {
+        Attachment {
+            filename: filename.into(),
+            content_type: ContentType::new("application", "vnd.oasis.opendocument.spreadsheet"),
+            content,
+        }
+    }
+}
+
+impl<'r, 'o: 'r> Responder<'r, 'o> for Attachment {
+    fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'o> {
+        // The plain filename is an ascii fallback without quotes nor control characters, the
+        // exact filename is given percent encoded as described in RFC 5987.
+        let fallback = self
+            .filename
+            .chars()
+            .map(|c| {
+                if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+
+        let mut encoded = String::new();
+        for byte in self.filename.bytes() {
+            if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                encoded.push(byte as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+
+        Ok(Response::build()
+            .header(self.content_type)
+            .header(Header::new(
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+                    fallback, encoded
+                ),
+            ))
+            .sized_body(self.content.len(), Cursor::new(self.content))
+            .finalize())
+    }
+}
+
 impl<R> Cors<R> {
     /// Creates a new cors response.
     pub fn new(home: &Option<String>, r: R) -> Cors<R> {
@@ -378,18 +461,27 @@ pub async fn not_found<'a>(request: &'_ Request<'a>) -> Either<Html<String>, Red
 /// A struct for managing the partial content range header.
 #[derive(Debug)]
 pub struct Range {
-    /// The first byte to send.
+    /// The first byte to send, or None for a range at the end of the file.
     pub start: Option<u64>,
 
-    /// The last byte to send.
+    /// The last byte to send, or the number of bytes to send for a range at the end of the file.
     pub end: Option<u64>,
 }
 
-/// The different ranges that have been send in the partial content header.
+/// The range and conditional headers of a request for a file.
 #[derive(Debug)]
 pub struct PartialContent {
     /// The ranges.
     pub ranges: Vec<Range>,
+
+    /// The value of the If-None-Match header.
+    pub if_none_match: Option<String>,
+
+    /// The value of the If-Modified-Since header.
+    pub if_modified_since: Option<String>,
+
+    /// The value of the If-Range header.
+    pub if_range: Option<String>,
 }
 
 impl Range {
@@ -401,58 +493,77 @@ impl Range {
 
         match (vec[0], vec[1]) {
             (None, None) => None,
-            (Some(x), Some(y)) if x >= y => None,
+            (Some(x), Some(y)) if x > y => None,
             _ => Some(Range {
                 start: vec[0],
                 end: vec[1],
             }),
         }
     }
+
+    /// Returns the first and last bytes of the range in a file of a given length, or None if the
+    /// range is not satisfiable.
+    pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        match (self.start, self.end) {
+            (Some(start), _) if start >= len => None,
+            (Some(start), end) => Some((start, end.unwrap_or(len - 1).min(len - 1))),
+            (None, Some(suffix)) if suffix > 0 && len > 0 => Some((len - suffix.min(len), len - 1)),
+            (None, _) => None,
+        }
+    }
 }
 
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for PartialContent {
-    type Error = ();
-    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let header = request.headers().get_one("range");
-        let header = match header {
-            Some(h) => h,
-            None => return Outcome::Success(PartialContent { ranges: vec![] }),
-        };
+/// Parses the value of a range header, returns None if the header must be ignored.
+fn parse_ranges(header: &str) -> Option<Vec<Range>> {
+    let (unit, ranges) = header.split_once('=')?;
 
-        if !header.starts_with("bytes=") {
-            return Outcome::Failure((Status::NotImplemented, ()));
-        }
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
 
-        let ranges = header[6..]
-            .split(",")
-            .map(|x| {
-                x.split("-")
-                    .map(|y| {
-                        let y = y.trim();
-                        if y == "" {
-                            Ok(None)
-                        } else {
-                            y.parse::<u64>().map(Some)
-                        }
-                    })
-                    .collect::<StdResult<Vec<_>, _>>()
-                    .ok()
-            })
-            .map(|x| x.and_then(Range::from_vec))
-            .collect::<Option<Vec<_>>>();
+    let parse = |x: &str| {
+        let x = x.trim();
+        if x.is_empty() {
+            Some(None)
+        } else {
+            x.parse::<u64>().ok().map(Some)
+        }
+    };
 
-        let ranges = match ranges {
-            Some(r) => r,
-            None => return Outcome::Failure((Status::BadRequest, ())),
-        };
+    ranges
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            let (start, end) = x.split_once('-')?;
+            Range::from_vec(vec![parse(start)?, parse(end)?])
+        })
+        .collect()
+}
 
-        Outcome::Success(PartialContent { ranges })
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PartialContent {
+    type Error = ();
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+
+        // Range headers that cannot be understood are ignored, as required by RFC 9110.
+        let ranges = headers
+            .get_one("range")
+            .and_then(parse_ranges)
+            .unwrap_or_default();
+
+        Outcome::Success(PartialContent {
+            ranges,
+            if_none_match: headers.get_one("if-none-match").map(String::from),
+            if_modified_since: headers.get_one("if-modified-since").map(String::from),
+            if_range: headers.get_one("if-range").map(String::from),
+        })
     }
 }
 
 /// Helper to respond with partial content.
-pub type PartialContentResponse<'a> = Either<NamedFile, FullResponse<'a>>;
+pub type PartialContentResponse<'a> = FullResponse<'a>;
 
 /// Responder with response.
 pub struct FullResponse<'a> {
@@ -466,51 +577,321 @@ impl<'r, 'o: 'r> Responder<'r, 'o> for FullResponse<'o> {
     }
 }
 
-impl PartialContent {
-    /// Reads a file and responds with the right content range.
-    pub async fn read<'a, P: AsRef<Path>>(self, p: P) -> StdResult<Response<'a>, tokio::io::Error> {
-        let metadata = tokio::fs::metadata(p.as_ref()).await?;
-        let mut file = File::open(p.as_ref()).await?;
+/// Guesses the content type of a file from its extension.
+fn content_type(path: &Path) -> ContentType {
+    path.extension()
+        .and_then(|x| x.to_str())
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Binary)
+}
+
+/// Formats a date as an HTTP date.
+fn http_date(date: &DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
 
-        let start = self.ranges[0]
-            .start
-            .unwrap_or_else(|| metadata.len() - self.ranges[0].end.unwrap());
+/// Compares two entity tags with the weak comparison of RFC 9110.
+fn weak_match(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
 
-        let end = self.ranges[0].end.unwrap_or(metadata.len() - 1);
+/// The maximum number of ranges served in a multipart response, requests with more ranges receive
+/// the whole file.
+const MAX_RANGES: usize = 16;
 
-        file.seek(SeekFrom::Start(start)).await?;
-        let file = file.take(end - start + 1);
+/// The number of bytes read at once from a storage that is not on the local filesystem.
+const STORAGE_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
 
-        Ok(Response::build()
-            .status(Status::PartialContent)
-            .header(Header::new(
-                "Content-Range",
-                format!("bytes {}-{}/{}", start, end, metadata.len()),
-            ))
-            .header(Header::new(
-                "Content-Length",
-                format!("{}", end - start + 1),
-            ))
-            .streamed_body(file)
-            .finalize())
+/// Resolves the ranges of a request in a file of a given length, sorts them and merges those that
+/// overlap or are contiguous.
+fn coalesce(ranges: &[Range], len: u64) -> Vec<(u64, u64)> {
+    let mut resolved = ranges
+        .iter()
+        .filter_map(|x| x.resolve(len))
+        .collect::<Vec<_>>();
+
+    resolved.sort_unstable();
+
+    let mut coalesced: Vec<(u64, u64)> = vec![];
+
+    for (start, end) in resolved {
+        match coalesced.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => coalesced.push((start, end)),
+        }
     }
 
-    /// Responds to an HTTP request.
-    pub async fn respond<'a, P: AsRef<Path>>(self, p: P) -> Result<PartialContentResponse<'a>> {
-        match self.ranges.len() {
-            0 => Ok(Either::Left(
-                NamedFile::open(p.as_ref())
+    coalesced
+}
+
+/// The file sent in a response.
+enum Source {
+    /// A file of the local filesystem.
+    Local(PathBuf),
+
+    /// A file of a storage that is not on the local filesystem.
+    Storage(Arc<dyn Storage>, String),
+}
+
+impl Source {
+    /// Returns a reader over some bytes of the file.
+    async fn open(&self, start: u64, len: u64) -> Result<Box<dyn AsyncRead + Send + Unpin>> {
+        match self {
+            Source::Local(path) => {
+                let mut file = File::open(path)
                     .await
-                    .map_err(|_| Error(Status::NotFound))?,
-            )),
-            1 => Ok(Either::Right(FullResponse {
-                response: self.read(p).await.map_err(|_| Error(Status::BadRequest))?,
+                    .map_err(|_| Error(Status::NotFound))?;
+                file.seek(SeekFrom::Start(start)).await?;
+                Ok(Box::new(file.take(len)))
+            }
+            Source::Storage(storage, key) => Ok(Box::new(StorageReader {
+                storage: storage.clone(),
+                key: key.clone(),
+                next: start,
+                end: start + len,
+                chunk: Cursor::new(vec![]),
+                pending: None,
             })),
-            _ => Err(Error(Status::NotImplemented)),
         }
     }
 }
 
+/// A reader over some bytes of a file of a storage, that reads the file chunk by chunk so that
+/// large files are never loaded in memory.
+struct StorageReader {
+    /// The storage that contains the file.
+    storage: Arc<dyn Storage>,
+
+    /// The key of the file.
+    key: String,
+
+    /// The first byte that was not read from the storage yet.
+    next: u64,
+
+    /// The byte after the last byte to read.
+    end: u64,
+
+    /// The chunk being sent.
+    chunk: Cursor<Vec<u8>>,
+
+    /// The read of the next chunk, if it is running.
+    pending: Option<Pin<Box<dyn Future<Output = Result<Vec<u8>>> + Send>>>,
+}
+
+impl AsyncRead for StorageReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.chunk.position() < this.chunk.get_ref().len() as u64 {
+                return Pin::new(&mut this.chunk).poll_read(cx, buf);
+            }
+
+            if this.pending.is_none() {
+                if this.next >= this.end {
+                    return Poll::Ready(Ok(()));
+                }
+
+                let storage = this.storage.clone();
+                let key = this.key.clone();
+                let start = this.next;
+                let end = (start + STORAGE_CHUNK_SIZE).min(this.end) - 1;
+                this.next = end + 1;
+                this.pending = Some(Box::pin(async move {
+                    storage.read_range(&key, start, end).await
+                }));
+            }
+
+            let content = match this.pending.as_mut().map(|x| x.as_mut().poll(cx)) {
+                Some(Poll::Ready(content)) => content,
+                _ => return Poll::Pending,
+            };
+
+            this.pending = None;
+
+            match content {
+                Ok(content) if !content.is_empty() => this.chunk = Cursor::new(content),
+                Ok(_) => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+                Err(Error(status)) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("cannot read {}: {}", this.key, status),
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl PartialContent {
+    /// Returns true if the client already has the current version of the file.
+    fn not_modified(&self, info: &FileInfo) -> bool {
+        // If-Modified-Since is ignored when If-None-Match is present.
+        if let Some(tags) = &self.if_none_match {
+            return tags
+                .split(',')
+                .map(|x| x.trim())
+                .any(|x| x == "*" || weak_match(x, &info.etag));
+        }
+
+        match (&self.if_modified_since, info.last_modified) {
+            (Some(since), Some(modified)) => DateTime::parse_from_rfc2822(since)
+                .map(|since| modified.timestamp() <= since.timestamp())
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// Returns true if the ranges of the request apply to the current version of the file.
+    ///
+    /// When the If-Range header does not match, the whole file must be sent.
+    fn ranges_apply(&self, info: &FileInfo) -> bool {
+        match &self.if_range {
+            None => true,
+            Some(tag) if tag.starts_with('"') => *tag == info.etag,
+            Some(tag) if tag.starts_with("W/") => false,
+            Some(date) => match (DateTime::parse_from_rfc2822(date), info.last_modified) {
+                (Ok(date), Some(modified)) => date.timestamp() == modified.timestamp(),
+                _ => false,
+            },
+        }
+    }
+
+    /// Responds to an HTTP request with a file of the local filesystem.
+    pub async fn respond<'a, P: AsRef<Path>>(self, p: P) -> Result<PartialContentResponse<'a>> {
+        let path = p.as_ref();
+        let info = file_info(path).await?.ok_or(Error(Status::NotFound))?;
+        self.answer(info, content_type(path), Source::Local(path.to_owned()))
+            .await
+    }
+
+    /// Responds to an HTTP request with a file of the storage.
+    ///
+    /// Files stored on the local filesystem are served directly, the others are read from the
+    /// storage.
+    pub async fn serve<'a>(
+        self,
+        storage: Box<dyn Storage>,
+        key: &str,
+    ) -> Result<PartialContentResponse<'a>> {
+        if let Some(path) = storage.local_path(key) {
+            return self.respond(path).await;
+        }
+
+        let info = storage.stat(key).await?.ok_or(Error(Status::NotFound))?;
+        self.answer(
+            info,
+            content_type(Path::new(key)),
+            Source::Storage(Arc::from(storage), key.to_owned()),
+        )
+        .await
+    }
+
+    /// Builds the response to a request for a file.
+    async fn answer<'a>(
+        self,
+        info: FileInfo,
+        content_type: ContentType,
+        source: Source,
+    ) -> Result<PartialContentResponse<'a>> {
+        let mut response = Response::build();
+        response
+            .header(Header::new("Accept-Ranges", "bytes"))
+            .header(Header::new("ETag", info.etag.clone()));
+
+        if let Some(modified) = &info.last_modified {
+            response.header(Header::new("Last-Modified", http_date(modified)));
+        }
+
+        if self.not_modified(&info) {
+            response.status(Status::NotModified);
+            return Ok(FullResponse {
+                response: response.finalize(),
+            });
+        }
+
+        let ranges = if self.ranges.is_empty() || !self.ranges_apply(&info) {
+            None
+        } else {
+            Some(coalesce(&self.ranges, info.len)).filter(|x| x.len() <= MAX_RANGES)
+        };
+
+        match ranges.as_deref() {
+            // Without ranges, or with too many of them, the whole file is sent.
+            None => {
+                response
+                    .header(content_type)
+                    .header(Header::new("Content-Length", format!("{}", info.len)))
+                    .streamed_body(source.open(0, info.len).await?);
+            }
+
+            Some([]) => {
+                response
+                    .status(Status::RangeNotSatisfiable)
+                    .header(Header::new(
+                        "Content-Range",
+                        format!("bytes */{}", info.len),
+                    ));
+            }
+
+            Some([(start, end)]) => {
+                response
+                    .status(Status::PartialContent)
+                    .header(content_type)
+                    .header(Header::new(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, info.len),
+                    ))
+                    .header(Header::new(
+                        "Content-Length",
+                        format!("{}", end - start + 1),
+                    ))
+                    .streamed_body(source.open(*start, end - start + 1).await?);
+            }
+
+            Some(ranges) => {
+                let boundary = Uuid::new_v4().simple().to_string();
+                let mut body: Box<dyn AsyncRead + Send + Unpin> = Box::new(Cursor::new(vec![]));
+                let mut len = 0;
+
+                for (start, end) in ranges {
+                    let header = format!(
+                        "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                        boundary, content_type, start, end, info.len
+                    );
+
+                    len += header.len() as u64 + end - start + 1;
+                    body = Box::new(
+                        body.chain(Cursor::new(header))
+                            .chain(source.open(*start, end - start + 1).await?),
+                    );
+                }
+
+                let footer = format!("\r\n--{}--\r\n", boundary);
+                len += footer.len() as u64;
+                body = Box::new(body.chain(Cursor::new(footer)));
+
+                response
+                    .status(Status::PartialContent)
+                    .header(
+                        ContentType::new("multipart", "byteranges")
+                            .with_params(("boundary", boundary)),
+                    )
+                    .header(Header::new("Content-Length", format!("{}", len)))
+                    .streamed_body(body);
+            }
+        }
+
+        Ok(FullResponse {
+            response: response.finalize(),
+        })
+    }
+}
+
 /// Helper type to respond managing partial content.
 pub struct PartialContentResponder {
     /// The portion of the content to send.
@@ -534,15 +915,9 @@ pub async fn assets<'a>(
         .get_capsule_with_permission(*capsule_id, Role::Read, &db)
         .await?;
 
-    partial_content
-        .respond(
-            config
-                .data_path
-                .join(format!("{}", *capsule_id))
-                .join("assets")
-                .join(path),
-        )
-        .await
+    let storage = storage::open(config)?;
+    let key = capsule_key(*capsule_id, Path::new("assets").join(path))?;
+    partial_content.serve(storage, &key).await
 }
 
 /// The route for the output video of a capsule that requires authorization.
@@ -558,14 +933,9 @@ pub async fn produced_video<'a>(
         .get_capsule_with_permission(*capsule_id, Role::Read, &db)
         .await?;
 
-    partial_content
-        .respond(
-            config
-                .data_path
-                .join(format!("{}", *capsule_id))
-                .join("output.mp4"),
-        )
-        .await
+    let storage = storage::open(config)?;
+    let key = capsule_key(*capsule_id, "output.mp4")?;
+    partial_content.serve(storage, &key).await
 }
 
 /// The route for temporary static files that require authorization.
@@ -582,15 +952,9 @@ pub async fn tmp<'a>(
         .get_capsule_with_permission(*capsule_id, Role::Read, &db)
         .await?;
 
-    partial_content
-        .respond(
-            config
-                .data_path
-                .join(format!("{}", *capsule_id))
-                .join("tmp")
-                .join(path),
-        )
-        .await
+    let storage = storage::open(config)?;
+    let key = capsule_key(*capsule_id, Path::new("tmp").join(path))?;
+    partial_content.serve(storage, &key).await
 }
 
 /// The route for static files.
@@ -606,3 +970,81 @@ pub async fn dist<'a>(
 
     Cors::new(&config.home, partial_content)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{coalesce, parse_ranges, Range};
+
+    /// Parses a range header and resolves its ranges in a file of a given length.
+    fn resolve(header: &str, len: u64) -> Vec<Option<(u64, u64)>> {
+        parse_ranges(header)
+            .unwrap()
+            .iter()
+            .map(|x| x.resolve(len))
+            .collect()
+    }
+
+    #[test]
+    fn parse_ignores_invalid_headers() {
+        assert!(parse_ranges("items=0-10").is_none());
+        assert!(parse_ranges("bytes=10-5").is_none());
+        assert!(parse_ranges("bytes=-").is_none());
+        assert!(parse_ranges("bytes=a-b").is_none());
+        assert!(parse_ranges("bytes=0-10,foo").is_none());
+    }
+
+    #[test]
+    fn resolve_bounded_and_open_ranges() {
+        assert_eq!(resolve("bytes=0-9", 100), vec![Some((0, 9))]);
+        assert_eq!(resolve("bytes=50-", 100), vec![Some((50, 99))]);
+        assert_eq!(resolve("BYTES = 10 - 19", 100), vec![Some((10, 19))]);
+    }
+
+    #[test]
+    fn resolve_suffix_ranges() {
+        assert_eq!(resolve("bytes=-10", 100), vec![Some((90, 99))]);
+        assert_eq!(resolve("bytes=-500", 100), vec![Some((0, 99))]);
+        assert_eq!(resolve("bytes=-0", 100), vec![None]);
+        assert_eq!(resolve("bytes=-10", 0), vec![None]);
+    }
+
+    #[test]
+    fn resolve_ranges_past_the_end() {
+        assert_eq!(resolve("bytes=90-500", 100), vec![Some((90, 99))]);
+        assert_eq!(
+            resolve(&format!("bytes=0-{}", u64::MAX), 100),
+            vec![Some((0, 99))]
+        );
+        assert!(parse_ranges("bytes=0-18446744073709551616").is_none());
+    }
+
+    #[test]
+    fn resolve_unsatisfiable_ranges() {
+        assert_eq!(resolve("bytes=100-", 100), vec![None]);
+        assert_eq!(resolve("bytes=100-200", 100), vec![None]);
+        assert_eq!(resolve("bytes=0-", 0), vec![None]);
+    }
+
+    #[test]
+    fn resolve_multiple_ranges() {
+        assert_eq!(
+            resolve("bytes=0-9, 20-29,-5,200-", 100),
+            vec![Some((0, 9)), Some((20, 29)), Some((95, 99)), None]
+        );
+    }
+
+    #[test]
+    fn coalesce_overlapping_ranges() {
+        let ranges = parse_ranges("bytes=50-59,0-9,5-14,15-19,-5,0-,0-").unwrap();
+        assert_eq!(coalesce(&ranges, 100), vec![(0, 99)]);
+
+        let ranges = parse_ranges("bytes=50-59,0-9,5-14,200-,-5").unwrap();
+        assert_eq!(coalesce(&ranges, 100), vec![(0, 14), (50, 59), (95, 99)]);
+
+        let ranges = vec![Range {
+            start: Some(100),
+            end: None,
+        }];
+        assert!(coalesce(&ranges, 100).is_empty());
+    }
+}
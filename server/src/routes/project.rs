@@ -0,0 +1,266 @@
+//! This module contains the routes to manage the projects.
+
+use chrono::Utc;
+
+use serde::{Deserialize, Serialize};
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Json, Value};
+
+use crate::db::audit::{AuditAction, AuditLog, Target};
+use crate::db::capsule::Role;
+use crate::db::project::Project;
+use crate::db::user::User;
+use crate::{Db, Error, HashId, Result};
+
+/// Returns a project of a user, if they have at least a given role on it.
+async fn project_with_permission(
+    user: &User,
+    id: i32,
+    permission: Role,
+    db: &Db,
+) -> Result<(Project, Role)> {
+    user.projects(&db)
+        .await?
+        .into_iter()
+        .find(|(x, role)| x.id == id && *role >= permission)
+        .ok_or(Error(Status::NotFound))
+}
+
+/// Returns true if the owner of a project has another project with a given name.
+///
+/// The names of the projects of a user must be unique since they are used to find them.
+async fn name_taken(owner: &User, name: &str, except: Option<i32>, db: &Db) -> Result<bool> {
+    Ok(owner
+        .projects(&db)
+        .await?
+        .into_iter()
+        .any(|(x, role)| role == Role::Owner && x.name == name && Some(x.id) != except))
+}
+
+/// The route that gives the project information.
+#[get("/project/<id>")]
+pub async fn get_project(user: User, id: HashId, db: Db) -> Result<Value> {
+    let (project, role) = project_with_permission(&user, *id, Role::Read, &db).await?;
+    project.to_json(role, &db).await
+}
+
+/// The data of the new project form.
+#[derive(Serialize, Deserialize)]
+pub struct NewProjectForm {
+    /// The name of the project.
+    pub name: String,
+
+    /// The description of the project.
+    #[serde(default)]
+    pub description: String,
+}
+
+/// The route that creates an empty project.
+#[post("/new-project", data = "<form>")]
+pub async fn new_project(user: User, db: Db, form: Json<NewProjectForm>) -> Result<Value> {
+    let NewProjectForm { name, description } = form.into_inner();
+
+    if name.trim().is_empty() || name_taken(&user, &name, None, &db).await? {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let project = Project::new(name, description, &user, &db).await?;
+    project.to_json(Role::Owner, &db).await
+}
+
+/// The json format to edit a project.
+#[derive(Serialize, Deserialize)]
+pub struct ProjectEdit {
+    /// The new name of the project, unchanged if absent.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// The new description of the project, unchanged if absent.
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// The new order of the capsules of the project, unchanged if absent.
+    #[serde(default)]
+    pub capsules: Option<Vec<HashId>>,
+}
+
+/// The route that renames, describes or reorders a project.
+///
+/// Renaming a project renames it for all of its capsules.
+#[post("/update-project/<id>", data = "<data>")]
+pub async fn edit_project(user: User, id: HashId, db: Db, data: Json<ProjectEdit>) -> Result<()> {
+    let ProjectEdit {
+        name,
+        description,
+        capsules,
+    } = data.into_inner();
+
+    let (mut project, _) = project_with_permission(&user, *id, Role::Write, &db).await?;
+
+    if let Some(name) = name {
+        let owner = project.owner(&db).await?;
+
+        if name.trim().is_empty() || name_taken(&owner, &name, Some(project.id), &db).await? {
+            return Err(Error(Status::BadRequest));
+        }
+
+        project.name = name;
+    }
+
+    if let Some(description) = description {
+        project.description = description;
+    }
+
+    if let Some(capsules) = capsules {
+        let ids = project
+            .capsules(&db)
+            .await?
+            .into_iter()
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+
+        let order = capsules.into_iter().map(|x| *x).collect::<Vec<_>>();
+
+        if !order.iter().all(|x| ids.contains(x)) {
+            return Err(Error(Status::BadRequest));
+        }
+
+        project.capsule_order.0 = order;
+    }
+
+    project.last_modified = Utc::now().naive_utc();
+    project.save(&db).await?;
+
+    Ok(())
+}
+
+/// The invitation to a project.
+#[derive(Serialize, Deserialize)]
+pub struct ProjectInvite {
+    /// The username or email of the invited user.
+    username: String,
+
+    /// The role given to the user, on the project and its capsules.
+    role: Role,
+}
+
+/// The route that invites a user to collaborate on a project.
+#[post("/invite-project/<id>", data = "<data>")]
+pub async fn invite_project(
+    user: User,
+    id: HashId,
+    db: Db,
+    data: Json<ProjectInvite>,
+) -> Result<()> {
+    let (project, _) = project_with_permission(&user, *id, Role::Owner, &db).await?;
+
+    let ProjectInvite { username, role } = data.into_inner();
+    let invited = User::get_by_username_or_email(&username, &db)
+        .await?
+        .ok_or(Error(Status::BadRequest))?;
+
+    // A project has a single owner, and users cannot be invited twice.
+    if role == Role::Owner
+        || project_with_permission(&invited, *id, Role::Read, &db)
+            .await
+            .is_ok()
+    {
+        return Err(Error(Status::BadRequest));
+    }
+
+    project.add_user(&invited, role, &db).await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::CollaboratorAdded,
+        Target::user(invited.id),
+        None,
+        Some(json!({ "project": id, "role": role })),
+        &db,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The route that changes the role of a collaborator of a project.
+#[post("/change-project-role/<id>", data = "<data>")]
+pub async fn change_project_role(
+    user: User,
+    id: HashId,
+    db: Db,
+    data: Json<ProjectInvite>,
+) -> Result<()> {
+    let (project, _) = project_with_permission(&user, *id, Role::Owner, &db).await?;
+
+    let ProjectInvite { username, role } = data.into_inner();
+    let invited = User::get_by_username_or_email(&username, &db)
+        .await?
+        .ok_or(Error(Status::BadRequest))?;
+
+    let (_, before) = project_with_permission(&invited, *id, Role::Read, &db)
+        .await
+        .map_err(|_| Error(Status::BadRequest))?;
+
+    if role == Role::Owner || before == Role::Owner {
+        return Err(Error(Status::BadRequest));
+    }
+
+    project.update_role(&invited, role, &db).await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::RoleChanged,
+        Target::user(invited.id),
+        Some(json!({ "project": id, "role": before })),
+        Some(json!({ "project": id, "role": role })),
+        &db,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// The data to remove a collaborator from a project.
+#[derive(Serialize, Deserialize)]
+pub struct ProjectDeinvite {
+    /// The username or email of the user to remove.
+    username: String,
+}
+
+/// The route that removes a collaborator from a project.
+#[post("/deinvite-project/<id>", data = "<data>")]
+pub async fn deinvite_project(
+    user: User,
+    id: HashId,
+    db: Db,
+    data: Json<ProjectDeinvite>,
+) -> Result<()> {
+    let (project, _) = project_with_permission(&user, *id, Role::Owner, &db).await?;
+
+    let ProjectDeinvite { username } = data.into_inner();
+    let deinvited = User::get_by_username_or_email(&username, &db)
+        .await?
+        .ok_or(Error(Status::BadRequest))?;
+
+    let (_, role) = project_with_permission(&deinvited, *id, Role::Read, &db).await?;
+
+    if role == Role::Owner {
+        return Err(Error(Status::BadRequest));
+    }
+
+    project.remove_user(&deinvited, &db).await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::CollaboratorRemoved,
+        Target::user(deinvited.id),
+        Some(json!({ "project": id, "role": role })),
+        None,
+        &db,
+    )
+    .await?;
+
+    Ok(())
+}
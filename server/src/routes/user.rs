@@ -1,6 +1,7 @@
 //! This module contains all the routes that deal with the user.
 
 use std::borrow::Cow;
+use std::net::IpAddr;
 
 use time::Duration;
 
@@ -8,8 +9,6 @@ use chrono::Utc;
 
 use serde::{Deserialize, Serialize};
 
-use tokio::fs::remove_dir_all;
-
 use rocket::form::Form;
 use rocket::http::{Cookie, CookieJar, SameSite, Status};
 use rocket::response::content::RawHtml as Html;
@@ -18,11 +17,14 @@ use rocket::serde::json::{json, Json, Value};
 use rocket::State as S;
 
 use crate::config::Config;
+use crate::db::audit::{user_summary, AuditAction, AuditLog, Target};
 use crate::db::capsule::Role;
 use crate::db::session::Session;
 use crate::db::user::User;
+use crate::rate_limit::RateLimiter;
 use crate::routes::global_flags;
 use crate::routes::Cors;
+use crate::storage;
 use crate::templates::index_html;
 use crate::{Db, Error, Lang, Result};
 
@@ -156,26 +158,18 @@ pub async fn login_external<'a>(
     db: Db,
     cookies: &CookieJar<'_>,
     config: &S<Config>,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
     login: Form<LoginForm>,
 ) -> Cors<Result<Redirect>> {
-    let user = match User::get_by_username(&login.username, &db).await {
-        Ok(u) => u,
-        _ => return Cors::err(&config.home, Status::Unauthorized),
-    };
-
-    let user = match user {
-        Some(user) => user,
-        None => match User::get_by_email(&login.username, &db).await {
-            Ok(Some(u)) => u,
-            _ => return Cors::err(&config.home, Status::Unauthorized),
-        },
+    let user = match limiter
+        .authenticate(&login.username, &login.password, ip, config, &db)
+        .await
+    {
+        Ok(user) => user,
+        Err(Error(s)) => return Cors::err(&config.home, s),
     };
 
-    match user.test_password(&login.password) {
-        Ok(()) => (),
-        _ => return Cors::err(&config.home, Status::Unauthorized),
-    }
-
     if !user.activated {
         return Cors::err(&config.home, Status::Unauthorized);
     }
@@ -201,10 +195,12 @@ pub fn login_cors(config: &S<Config>) -> Cors<()> {
 pub async fn login(
     db: Db,
     config: &S<Config>,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
     cookies: &CookieJar<'_>,
     login: Json<LoginForm>,
 ) -> Cors<Result<Value>> {
-    match login_wrapper(db, config, cookies, login).await {
+    match login_wrapper(db, config, limiter, ip, cookies, login).await {
         Ok(v) => Cors::ok(&config.home, v),
         Err(Error(e)) => Cors::err(&config.home, e),
     }
@@ -214,22 +210,14 @@ pub async fn login(
 pub async fn login_wrapper(
     db: Db,
     config: &S<Config>,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
     cookies: &CookieJar<'_>,
     login: Json<LoginForm>,
 ) -> Result<Value> {
-    let user = User::get_by_username(&login.username, &db)
-        .await
-        .map_err(|_| Error(Status::Unauthorized))?;
-
-    let user = match user {
-        Some(user) => user,
-        None => match User::get_by_email(&login.username, &db).await {
-            Ok(Some(u)) => u,
-            _ => return Err(Error(Status::Unauthorized)),
-        },
-    };
-
-    user.test_password(&login.password)?;
+    let user = limiter
+        .authenticate(&login.username, &login.password, ip, config, &db)
+        .await?;
 
     if !user.activated {
         return Err(Error(Status::Unauthorized));
@@ -274,12 +262,23 @@ pub struct RequestNewPasswordForm {
 }
 
 /// The route that requests an email to change a password.
+///
+/// The response is the same whether the email exists or not.
 #[post("/request-new-password", data = "<form>")]
 pub async fn request_new_password<'a>(
     config: &S<Config>,
     db: Db,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
     form: Json<RequestNewPasswordForm>,
 ) -> Cors<Status> {
+    // Every request is counted since each of them may send an email.
+    let keys = RateLimiter::reset_keys(ip, &form.email);
+    let max = config.reset_max_requests;
+    if let Err(Error(s)) = limiter.request(&keys, max, config).await {
+        return Cors::new(&config.home, s);
+    }
+
     let mut user = match User::get_by_email(&form.email, &db).await {
         Ok(Some(user)) => user,
         _ => return Cors::new(&config.home, Status::Ok),
@@ -313,12 +312,16 @@ pub async fn change_password(
     db: Db,
     form: Json<ChangePasswordForm>,
     config: &S<Config>,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
     cookies: &CookieJar<'_>,
 ) -> Result<Value> {
     let mut user = match (&form.username_and_old_password, &form.key) {
         (None, None) => return Err(Error(Status::BadRequest)),
         (Some((username, old_password)), _) => {
-            User::authenticate(username, old_password, &db).await?
+            limiter
+                .authenticate(username, old_password, ip, config, &db)
+                .await?
         }
         (_, Some(key)) => User::get_by_reset_password_key(Some(key.to_string()), &db)
             .await?
@@ -330,6 +333,16 @@ pub async fn change_password(
     let session = user.save_session(&db).await?;
     add_cookies(&session.secret, &config, cookies);
     user.save(&db).await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::PasswordChanged,
+        Target::user(user.id),
+        None,
+        None,
+        &db,
+    )
+    .await?;
     let json = user.to_json(&db).await?;
 
     Ok(json)
@@ -374,8 +387,22 @@ pub async fn request_change_email(
         return Err(Error(Status::BadRequest));
     }
 
+    let before = json!({ "email": user.email });
+    let after = json!({ "email": form.new_email });
+
     user.request_change_email(form.0.new_email, &config.mailer, &db)
         .await?;
+
+    AuditLog::record(
+        Some(&user),
+        AuditAction::EmailChangeRequested,
+        Target::user(user.id),
+        Some(before),
+        Some(after),
+        &db,
+    )
+    .await?;
+
     Ok(())
 }
 
@@ -408,12 +435,21 @@ pub async fn delete(
 
     for (capsule, role) in capsules {
         if role == Role::Owner {
-            let dir = config.data_path.join(format!("{}", capsule.id));
-            remove_dir_all(dir).await?;
+            storage::delete_capsule(capsule.id, &config).await?;
             capsule.delete(&db).await?;
         }
     }
 
+    AuditLog::record(
+        Some(&user),
+        AuditAction::UserDeleted,
+        Target::user(user.id),
+        Some(user_summary(&user)),
+        None,
+        &db,
+    )
+    .await?;
+
     user.delete(&db).await?;
     remove_cookies("", &config, cookies);
 
@@ -1,40 +1,201 @@
 //! This module contains the route to watch videos.
 
+use std::collections::HashMap;
 use std::io::Cursor;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
 
-use rocket::http::{ContentType, Status};
+use serde::Deserialize;
+
+use tokio::fs::{copy, create_dir_all, remove_dir_all, remove_file, write};
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+
+use rocket::form::Form;
+use rocket::http::{ContentType, Cookie, CookieJar, Header, Status};
 use rocket::request::Request;
 use rocket::response::{self, Responder, Response};
+use rocket::serde::json::{json, Json, Value};
 use rocket::State as S;
 
+use rand::distributions::Alphanumeric;
+use rand::rngs::OsRng;
+use rand::Rng;
+
+use uuid::Uuid;
+
+use ergol::Pool;
+
 use crate::config::Config;
 use crate::db::capsule::{Capsule, Privacy, Role};
+use crate::db::export::{Export, ExportFormat};
+use crate::db::quiz::QuizAnswer;
+use crate::db::share::ShareToken;
+use crate::db::stats::{TaskStat, TaskStatType};
 use crate::db::task_status::TaskStatus;
 use crate::db::user::Plan;
 use crate::db::user::User;
-use crate::routes::{Cors, PartialContent, PartialContentResponse};
-use crate::templates::video_html;
-use crate::{Db, Error, HashId, Result};
+use crate::db::view::View;
+use crate::rate_limit::RateLimiter;
+use crate::routes::capsule::pull_file;
+use crate::routes::{Cors, FullResponse, PartialContent, PartialContentResponse};
+use crate::signing::{
+    playlist_uris, rewrite_playlist, sign_slides, sign_thumbnails, SegmentSigner, SegmentToken,
+};
+use crate::storage::{self, capsule_key};
+use crate::templates::{
+    escape_html, scorm_index_html, scorm_manifest_xml, share_password_html, video_html,
+};
+use crate::{Db, Error, HashId, Result, HARSH};
 
 /// A custom response type for allowing iframes on the watch route.
-pub struct CustomResponse(String);
+pub struct CustomResponse {
+    /// The HTML content of the response.
+    body: String,
+
+    /// The Content-Security-Policy that restricts the websites that can embed the page, if any.
+    frame_ancestors: Option<String>,
+}
+
+impl CustomResponse {
+    /// Creates a response that can be embedded by any website.
+    pub fn new(body: String) -> CustomResponse {
+        CustomResponse {
+            body,
+            frame_ancestors: None,
+        }
+    }
+
+    /// Restricts the websites that can embed the response to the embed origins of the capsule.
+    pub fn for_capsule(body: String, capsule: &Capsule) -> CustomResponse {
+        CustomResponse {
+            body,
+            frame_ancestors: capsule.frame_ancestors(),
+        }
+    }
+}
 
 impl<'r, 'o: 'r> Responder<'r, 'o> for CustomResponse {
     fn respond_to(self, _request: &'r Request<'_>) -> response::Result<'o> {
-        Ok(Response::build()
-            .sized_body(self.0.len(), Cursor::new(self.0))
-            .header(ContentType::HTML)
-            .finalize())
+        let mut response = Response::build();
+        response
+            .sized_body(self.body.len(), Cursor::new(self.body))
+            .header(ContentType::HTML);
+
+        if let Some(frame_ancestors) = self.frame_ancestors {
+            response.header(Header::new("Content-Security-Policy", frame_ancestors));
+        }
+
+        Ok(response.finalize())
+    }
+}
+
+/// The query parameters of the watch route.
+#[derive(FromForm)]
+pub struct WatchOptions {
+    /// The share token that gives access to a private capsule.
+    share: Option<String>,
+
+    /// The time at which the video starts, in seconds.
+    t: Option<f64>,
+
+    /// Whether the video starts playing automatically.
+    autoplay: Option<bool>,
+
+    /// Whether the subtitle track is hidden from the player.
+    #[field(name = "hide-subtitles")]
+    hide_subtitles: Option<bool>,
+}
+
+/// Returns the name of the cookie that holds the share token of a capsule.
+fn share_cookie_name(capsule_id: HashId) -> String {
+    format!("share-{}", capsule_id.hash())
+}
+
+/// Returns true if the user is allowed to watch the capsule as a collaborator.
+async fn is_collaborator(user: &Option<User>, capsule_id: HashId, db: &Db) -> bool {
+    match user {
+        Some(user) => user
+            .get_capsule_with_permission(*capsule_id, Role::Read, &db)
+            .await
+            .is_ok(),
+        None => false,
     }
 }
 
 /// The route that serves HTML to watch videos.
-#[get("/v/<capsule_id>", rank = 1)]
+#[get("/v/<capsule_id>?<options..>", rank = 1)]
 pub async fn watch<'a>(
     config: &S<Config>,
+    signer: &S<SegmentSigner>,
+    user: Option<User>,
+    capsule_id: HashId,
+    options: WatchOptions,
+    cookies: &CookieJar<'_>,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
+    db: Db,
+) -> Result<CustomResponse> {
+    watch_aux(
+        config, signer, user, capsule_id, options, None, cookies, limiter, ip, db,
+    )
+    .await
+}
+
+/// The form that unlocks a share token protected by a password.
+#[derive(FromForm)]
+pub struct SharePasswordForm {
+    /// The password of the share token.
+    password: String,
+}
+
+/// The route that serves HTML to watch videos shared with a password.
+#[post("/v/<capsule_id>?<options..>", data = "<form>", rank = 1)]
+pub async fn watch_with_password<'a>(
+    config: &S<Config>,
+    signer: &S<SegmentSigner>,
+    user: Option<User>,
+    capsule_id: HashId,
+    options: WatchOptions,
+    cookies: &CookieJar<'_>,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
+    db: Db,
+    form: Form<SharePasswordForm>,
+) -> Result<CustomResponse> {
+    let password = form.into_inner().password;
+    watch_aux(
+        config,
+        signer,
+        user,
+        capsule_id,
+        options,
+        Some(password),
+        cookies,
+        limiter,
+        ip,
+        db,
+    )
+    .await
+}
+
+/// Helper function to the routes that serve HTML to watch videos.
+///
+/// Private capsules can be watched by their collaborators, or by anyone with a valid share token.
+/// Once a share token has been accepted, it is stored in a private cookie so that the files of the
+/// video can be served. Wrong share passwords are rate limited like failed logins.
+pub async fn watch_aux(
+    config: &S<Config>,
+    signer: &S<SegmentSigner>,
     user: Option<User>,
     capsule_id: HashId,
+    options: WatchOptions,
+    password: Option<String>,
+    cookies: &CookieJar<'_>,
+    limiter: &RateLimiter,
+    ip: Option<IpAddr>,
     db: Db,
 ) -> Result<CustomResponse> {
     let capsule = Capsule::get_by_id(*capsule_id as i32, &db)
@@ -46,14 +207,46 @@ pub async fn watch<'a>(
     }
 
     // Check authorization.
-    if capsule.privacy == Privacy::Private {
-        match user {
-            None => return Err(Error(Status::Unauthorized)),
-            Some(user) => {
-                user.get_capsule_with_permission(*capsule_id, Role::Read, &db)
-                    .await?;
+    if capsule.privacy == Privacy::Private && !is_collaborator(&user, capsule_id, &db).await {
+        let share = options.share.ok_or(Error(Status::Unauthorized))?;
+        let mut token = ShareToken::get_by_token(share, &db)
+            .await?
+            .ok_or(Error(Status::Unauthorized))?;
+
+        if token.capsule(&db).await?.id != capsule.id || token.is_expired() || token.is_exhausted()
+        {
+            return Err(Error(Status::Unauthorized));
+        }
+
+        if token.hashed_password.is_some() {
+            match password {
+                None => {
+                    let html = share_password_html(false);
+                    return Ok(CustomResponse::for_capsule(html, &capsule));
+                }
+                Some(password) => {
+                    let keys = RateLimiter::share_keys(ip, &token.token);
+                    limiter.check(&keys, config, &db).await?;
+
+                    if token.test_password(Some(&password)).is_err() {
+                        limiter.failure(&keys, config, &db).await?;
+                        let html = share_password_html(true);
+                        return Ok(CustomResponse::for_capsule(html, &capsule));
+                    }
+
+                    limiter.success(&keys).await;
+                }
             }
         }
+
+        if !token.count_view(&db).await? {
+            return Err(Error(Status::Unauthorized));
+        }
+
+        cookies.add_private(Cookie::new(
+            share_cookie_name(capsule_id),
+            token.token.clone(),
+        ));
     }
 
     // Check if video is on current host or other host.
@@ -71,26 +264,188 @@ pub async fn watch<'a>(
         }
     }
 
-    Ok(CustomResponse(video_html(&format!(
-        "{}/v/{}/manifest.m3u8",
-        host,
-        capsule_id.hash()
-    ))))
+    let token = signer.sign(*capsule_id);
+    let mut url = token.append_to(&format!("{}/v/{}/manifest.m3u8", host, capsule_id.hash()));
+
+    if options.hide_subtitles.unwrap_or(false) {
+        url.push_str("&subtitles=false");
+    }
+
+    let session = OsRng
+        .sample_iter(&Alphanumeric)
+        .map(char::from)
+        .take(VIEW_SESSION_LENGTH)
+        .collect::<String>();
+
+    let signature = signer.sign_session(capsule.id, &session, user.as_ref().map(|x| x.id));
+
+    // Videos published before the thumbnails were generated have neither poster nor thumbnails
+    // track.
+    let storage = storage::open(config)?;
+    let poster = storage
+        .stat(&capsule_key(capsule.id, "output/poster.jpg")?)
+        .await?
+        .map(|_| token.append_to(&format!("{}/v/{}/poster.jpg", host, capsule_id.hash())));
+    let thumbnails = storage
+        .stat(&capsule_key(capsule.id, "output/thumbnails.vtt")?)
+        .await?
+        .map(|_| token.append_to(&format!("{}/v/{}/thumbnails.vtt", host, capsule_id.hash())));
+    let chapters = token.append_to(&format!("{}/v/{}/chapters.vtt", host, capsule_id.hash()));
+    let slides = token.append_to(&format!("{}/v/{}/slides.json", host, capsule_id.hash()));
+    let quizzes = token.append_to(&format!("{}/v/{}/quizzes.json", host, capsule_id.hash()));
+
+    let html = video_html(
+        &url,
+        poster.as_deref(),
+        thumbnails.as_deref(),
+        &chapters,
+        &slides,
+        &quizzes,
+        &format!("/v/{}/quiz", capsule_id.hash()),
+        &format!("/v/{}/comments", capsule_id.hash()),
+        options.t,
+        options.autoplay.unwrap_or(false),
+        &format!("/v/{}/view", capsule_id.hash()),
+        &session,
+        &signature,
+    );
+
+    Ok(CustomResponse::for_capsule(html, &capsule))
+}
+
+/// The default width of embedded videos.
+const OEMBED_WIDTH: u32 = 1280;
+
+/// The default height of embedded videos.
+const OEMBED_HEIGHT: u32 = 720;
+
+/// The oEmbed endpoint, that gives the embed code of a video from its url.
+///
+/// See https://oembed.com for the specification.
+#[get("/oembed?<url>&<maxwidth>&<maxheight>&<format>")]
+pub async fn oembed(
+    config: &S<Config>,
+    url: String,
+    maxwidth: Option<u32>,
+    maxheight: Option<u32>,
+    format: Option<String>,
+    db: Db,
+) -> Cors<Result<Value>> {
+    Cors::new(
+        &Some("*".to_string()),
+        oembed_aux(config, url, maxwidth, maxheight, format, db).await,
+    )
+}
+
+/// Helper function to the oEmbed endpoint.
+///
+/// Makes us able to easily wrap cors.
+pub async fn oembed_aux(
+    config: &S<Config>,
+    url: String,
+    maxwidth: Option<u32>,
+    maxheight: Option<u32>,
+    format: Option<String>,
+    db: Db,
+) -> Result<Value> {
+    if format.map(|x| x != "json").unwrap_or(false) {
+        return Err(Error(Status::NotImplemented));
+    }
+
+    // Extracts the hash id from an url like https://polymny.studio/v/<id>/.
+    let hash = url
+        .split("/v/")
+        .nth(1)
+        .and_then(|x| x.split(|c| c == '/' || c == '?' || c == '#').next())
+        .ok_or(Error(Status::NotFound))?;
+
+    let capsule_id = HARSH.decode(hash)?;
+
+    let capsule = Capsule::get_by_id(capsule_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if capsule.published != TaskStatus::Done {
+        return Err(Error(Status::NotFound));
+    }
+
+    if capsule.privacy == Privacy::Private {
+        return Err(Error(Status::Unauthorized));
+    }
+
+    // Fits the video in the requested size, keeping its aspect ratio.
+    let mut width = OEMBED_WIDTH;
+    let mut height = OEMBED_HEIGHT;
+
+    if let Some(maxwidth) = maxwidth {
+        if maxwidth < width {
+            width = maxwidth;
+            height = width * OEMBED_HEIGHT / OEMBED_WIDTH;
+        }
+    }
+
+    if let Some(maxheight) = maxheight {
+        if maxheight < height {
+            height = maxheight;
+            width = height * OEMBED_WIDTH / OEMBED_HEIGHT;
+        }
+    }
+
+    let title = escape_html(&capsule.name);
+
+    let html = format!(
+        r#"<iframe width="{}" height="{}" src="{}/v/{}/" title="{}" frameborder="0" allowfullscreen></iframe>"#,
+        width,
+        height,
+        config.root,
+        HARSH.encode(capsule.id),
+        title
+    );
+
+    Ok(json!({
+        "version": "1.0",
+        "type": "video",
+        "provider_name": "Polymny Studio",
+        "provider_url": config.root,
+        "title": capsule.name,
+        "width": width,
+        "height": height,
+        "html": html,
+    }))
 }
 
 /// The route that serves files inside published videos.
-#[get("/v/<capsule_id>/<path..>", rank = 2)]
+///
+/// Requests that carry a valid signature are served without touching the session, as long as the
+/// capsule is still published and has not been made private since the signature was minted.
+#[get("/v/<capsule_id>/<path..>?<exp>&<sig>&<subtitles>", rank = 2)]
 pub async fn watch_asset<'a>(
-    user: Option<User>,
     capsule_id: HashId,
     path: PathBuf,
+    exp: Option<i64>,
+    sig: Option<String>,
+    subtitles: Option<bool>,
     config: &S<Config>,
-    db: Db,
+    signer: &S<SegmentSigner>,
+    cookies: &CookieJar<'_>,
+    pool: &S<Pool>,
     partial_content: PartialContent,
 ) -> Cors<Result<PartialContentResponse<'a>>> {
     Cors::new(
         &Some("*".to_string()),
-        watch_asset_aux(user, capsule_id, path, config, db, partial_content).await,
+        watch_asset_aux(
+            capsule_id,
+            path,
+            exp,
+            sig,
+            subtitles,
+            config,
+            signer,
+            cookies,
+            pool,
+            partial_content,
+        )
+        .await,
     )
 }
 
@@ -98,13 +453,166 @@ pub async fn watch_asset<'a>(
 ///
 /// Makes us able to easily wrap cors.
 pub async fn watch_asset_aux<'a>(
-    user: Option<User>,
     capsule_id: HashId,
     path: PathBuf,
+    exp: Option<i64>,
+    sig: Option<String>,
+    subtitles: Option<bool>,
     config: &S<Config>,
-    db: Db,
+    signer: &S<SegmentSigner>,
+    cookies: &CookieJar<'_>,
+    pool: &S<Pool>,
     partial_content: PartialContent,
 ) -> Result<PartialContentResponse<'a>> {
+    let signed = match (exp, &sig) {
+        (Some(exp), Some(sig)) => signer.verify(*capsule_id, exp, sig),
+        _ => false,
+    };
+
+    let db = Db::from_pool(pool.inner().clone()).await?;
+
+    let token = if signed {
+        // The signature only tells that the video could be watched when it was minted, the capsule
+        // may have been unpublished or made private since.
+        let capsule = Capsule::get_by_id(*capsule_id as i32, &db)
+            .await?
+            .ok_or(Error(Status::NotFound))?;
+
+        if capsule.published != TaskStatus::Done {
+            return Err(Error(Status::NotFound));
+        }
+
+        if capsule.privacy == Privacy::Private {
+            check_asset_access(capsule_id, cookies, &db).await?;
+        }
+
+        SegmentToken {
+            exp: exp.unwrap(),
+            sig: sig.unwrap(),
+        }
+    } else {
+        // Without a valid signature, only collaborators and holders of a share link of a private
+        // capsule can access the files, and they get a fresh token for the playlists.
+        check_asset_access(capsule_id, cookies, &db).await?;
+        signer.sign(*capsule_id)
+    };
+
+    let storage = storage::open(config)?;
+    let key = capsule_key(*capsule_id, PathBuf::from("output").join(&path))?;
+
+    // Playlists are rewritten so that the urls they contain carry the token.
+    if path.extension().and_then(|x| x.to_str()) == Some("m3u8") {
+        let playlist = String::from_utf8(storage.read(&key).await?)
+            .map_err(|_| Error(Status::InternalServerError))?;
+
+        // When the storage supports it, the segments are fetched directly from the storage with
+        // presigned urls, the playlists still go through the server to be rewritten.
+        let mut presigned = HashMap::new();
+        if storage.local_path(&key).is_none() {
+            let dir = key.rsplit_once('/').map(|x| x.0).unwrap_or("");
+
+            for uri in playlist_uris(&playlist) {
+                if uri.ends_with(".m3u8") || presigned.contains_key(&uri) {
+                    continue;
+                }
+
+                let segment = format!("{}/{}", dir, uri);
+                if let Some(url) = storage
+                    .presign(&segment, config.segment_token_duration)
+                    .await?
+                {
+                    presigned.insert(uri, url);
+                }
+            }
+        }
+
+        let mut playlist = rewrite_playlist(&playlist, |uri| {
+            presigned
+                .get(uri)
+                .cloned()
+                .unwrap_or_else(|| token.append_to(uri))
+        });
+
+        if subtitles == Some(false) {
+            playlist = strip_subtitles(&playlist);
+        }
+
+        return Ok(FullResponse {
+            response: Response::build()
+                .header(ContentType::new("application", "vnd.apple.mpegurl"))
+                .sized_body(playlist.len(), Cursor::new(playlist))
+                .finalize(),
+        });
+    }
+
+    // The slide index is rewritten so that the urls of the thumbnails carry the token.
+    if path.file_name().and_then(|x| x.to_str()) == Some("slides.json") {
+        let index = String::from_utf8(storage.read(&key).await?)
+            .map_err(|_| Error(Status::InternalServerError))?;
+
+        let index = sign_slides(&index, &token).ok_or(Error(Status::InternalServerError))?;
+
+        return Ok(FullResponse {
+            response: Response::build()
+                .header(ContentType::JSON)
+                .sized_body(index.len(), Cursor::new(index))
+                .finalize(),
+        });
+    }
+
+    // The thumbnails track is rewritten so that the url of the sprite sheet carries the token.
+    if path.file_name().and_then(|x| x.to_str()) == Some("thumbnails.vtt") {
+        let track = String::from_utf8(storage.read(&key).await?)
+            .map_err(|_| Error(Status::InternalServerError))?;
+
+        let track = sign_thumbnails(&track, &token);
+
+        return Ok(FullResponse {
+            response: Response::build()
+                .header(ContentType::new("text", "vtt"))
+                .sized_body(track.len(), Cursor::new(track))
+                .finalize(),
+        });
+    }
+
+    partial_content.serve(storage, &key).await
+}
+
+/// Removes the subtitle tracks from an HLS master playlist.
+fn strip_subtitles(playlist: &str) -> String {
+    let mut output = String::with_capacity(playlist.len());
+
+    for line in playlist.lines() {
+        if line.starts_with("#EXT-X-MEDIA:") && line.contains("TYPE=SUBTITLES") {
+            continue;
+        }
+
+        match line.find(",SUBTITLES=\"") {
+            Some(start) => {
+                let value = start + ",SUBTITLES=\"".len();
+                let end = line[value..]
+                    .find('"')
+                    .map(|x| value + x + 1)
+                    .unwrap_or(line.len());
+                output.push_str(&line[..start]);
+                output.push_str(&line[end..]);
+            }
+            None => output.push_str(line),
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Checks that the user of the session, or the share token stored in cookies, gives access to the
+/// files of a published capsule.
+pub async fn check_asset_access(
+    capsule_id: HashId,
+    cookies: &CookieJar<'_>,
+    db: &Db,
+) -> Result<()> {
     let capsule = Capsule::get_by_id(*capsule_id as i32, &db)
         .await?
         .ok_or(Error(Status::NotFound))?;
@@ -113,26 +621,556 @@ pub async fn watch_asset_aux<'a>(
         return Err(Error(Status::NotFound));
     }
 
-    // Check authorization.
+    let user = match cookies.get_private("EXAUTH") {
+        Some(cookie) => User::get_from_session(cookie.value(), &db).await?,
+        None => None,
+    };
+
+    if is_collaborator(&user, capsule_id, &db).await {
+        return Ok(());
+    }
+
+    if capsule.privacy != Privacy::Private {
+        return Err(Error(Status::Unauthorized));
+    }
+
+    let cookie = cookies
+        .get_private(&share_cookie_name(capsule_id))
+        .ok_or(Error(Status::Unauthorized))?;
+
+    let token = ShareToken::get_by_token(cookie.value().to_string(), &db)
+        .await?
+        .ok_or(Error(Status::Unauthorized))?;
+
+    // The views are counted by the watch route, so exhausted tokens can still fetch the files of
+    // the video being watched.
+    if token.capsule(&db).await?.id != capsule.id || token.is_expired() {
+        return Err(Error(Status::Unauthorized));
+    }
+
+    Ok(())
+}
+
+/// The length of the identifiers of views.
+const VIEW_SESSION_LENGTH: usize = 32;
+
+/// The maximum number of ranges a beacon can report.
+const MAX_BEACON_RANGES: usize = 64;
+
+/// The beacon sent by the player to report what has been watched.
+#[derive(Deserialize)]
+pub struct ViewBeacon {
+    /// The identifier of the play, given by the watch route.
+    session: String,
+
+    /// The signature of the play, given by the watch route.
+    signature: String,
+
+    /// The ranges of the video that were played, in seconds.
+    played: Vec<(f64, f64)>,
+}
+
+/// The route that receives the beacons of the player.
+///
+/// Like the answers to the quizzes, the beacons can only be sent for the plays started by the
+/// watch route, by the viewer that started them.
+#[post("/v/<capsule_id>/view", data = "<data>")]
+pub async fn view_beacon(
+    user: Option<User>,
+    capsule_id: HashId,
+    config: &S<Config>,
+    signer: &S<SegmentSigner>,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
+    cookies: &CookieJar<'_>,
+    db: Db,
+    data: Json<ViewBeacon>,
+) -> Result<()> {
+    let ViewBeacon {
+        session,
+        signature,
+        played,
+    } = data.into_inner();
+
+    if session.len() != VIEW_SESSION_LENGTH || !session.chars().all(|x| x.is_ascii_alphanumeric()) {
+        return Err(Error(Status::BadRequest));
+    }
+
+    if played.len() > MAX_BEACON_RANGES {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let user_id = user.as_ref().map(|x| x.id);
+    if !signer.verify_session(*capsule_id, &session, user_id, &signature) {
+        return Err(Error(Status::Forbidden));
+    }
+
+    let key = match ip {
+        Some(ip) => format!("view:{}", ip),
+        None => format!("view-session:{}", session),
+    };
+
+    limiter
+        .request(&[key], config.view_max_beacons, config)
+        .await?;
+
+    let capsule = Capsule::get_by_id(*capsule_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if capsule.published != TaskStatus::Done {
+        return Err(Error(Status::NotFound));
+    }
+
     if capsule.privacy == Privacy::Private {
-        match user {
-            None => return Err(Error(Status::Unauthorized)),
-            Some(user) => {
-                user.get_capsule_with_permission(*capsule_id, Role::Read, &db)
-                    .await?;
+        check_asset_access(capsule_id, cookies, &db).await?;
+    }
+
+    let ranges = played
+        .into_iter()
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .map(|(x, y)| ((x * 1000.0) as i32, (y * 1000.0) as i32))
+        .collect::<Vec<_>>();
+
+    View::record(session, &capsule, user.as_ref(), ranges, &db).await
+}
+
+/// The answer of a viewer to a quiz, sent by the player.
+#[derive(Deserialize)]
+pub struct QuizAnswerForm {
+    /// The identifier of the play, given by the watch route.
+    session: String,
+
+    /// The signature of the play, given by the watch route.
+    signature: String,
+
+    /// The index of the chosen option.
+    option: usize,
+}
+
+/// The route that receives the answers to the quizzes, and tells whether they are correct.
+///
+/// The answers can only be sent for the plays started by the watch route, by the viewer that
+/// started them. The quizzes of the subjects of assignments are graded, so their correct option is
+/// only given to logged in viewers, whose first answer is the one kept: a student cannot learn it
+/// anonymously before answering.
+#[post("/v/<capsule_id>/quiz/<quiz_id>", data = "<data>")]
+pub async fn answer_quiz(
+    user: Option<User>,
+    capsule_id: HashId,
+    quiz_id: String,
+    config: &S<Config>,
+    signer: &S<SegmentSigner>,
+    limiter: &S<RateLimiter>,
+    ip: Option<IpAddr>,
+    cookies: &CookieJar<'_>,
+    db: Db,
+    data: Json<QuizAnswerForm>,
+) -> Result<Value> {
+    let QuizAnswerForm {
+        session,
+        signature,
+        option,
+    } = data.into_inner();
+
+    if session.len() != VIEW_SESSION_LENGTH || !session.chars().all(|x| x.is_ascii_alphanumeric()) {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let user_id = user.as_ref().map(|x| x.id);
+    if !signer.verify_session(*capsule_id, &session, user_id, &signature) {
+        return Err(Error(Status::Forbidden));
+    }
+
+    let key = match ip {
+        Some(ip) => format!("quiz:{}", ip),
+        None => format!("quiz-session:{}", session),
+    };
+
+    limiter
+        .request(&[key], config.quiz_max_answers, config)
+        .await?;
+
+    let capsule = Capsule::get_by_id(*capsule_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if capsule.published != TaskStatus::Done {
+        return Err(Error(Status::NotFound));
+    }
+
+    if capsule.privacy == Privacy::Private {
+        check_asset_access(capsule_id, cookies, &db).await?;
+    }
+
+    let quiz_id = Uuid::parse_str(&quiz_id).map_err(|_| Error(Status::NotFound))?;
+    let quiz = capsule
+        .quiz(quiz_id)
+        .ok_or(Error(Status::NotFound))?
+        .clone();
+
+    if option >= quiz.options.len() {
+        return Err(Error(Status::BadRequest));
+    }
+
+    let answer = QuizAnswer::record(&capsule, &quiz, session, user.as_ref(), option, &db).await?;
+
+    if user.is_none() && !capsule.assignments(&db).await?.is_empty() {
+        return Ok(json!({ "option": answer.option }));
+    }
+
+    Ok(json!({
+        "option": answer.option,
+        "correct": answer.correct,
+        "answer": quiz.answer,
+    }))
+}
+
+/// Checks that the user of the session, or the share token stored in cookies, allows to download
+/// the video of a published capsule, and returns the capsule.
+///
+/// Collaborators can always download the video, other viewers only if the capsule allows it.
+async fn check_download_access(
+    capsule_id: HashId,
+    user: &Option<User>,
+    cookies: &CookieJar<'_>,
+    db: &Db,
+) -> Result<Capsule> {
+    let capsule = Capsule::get_by_id(*capsule_id as i32, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if capsule.published != TaskStatus::Done {
+        return Err(Error(Status::NotFound));
+    }
+
+    if is_collaborator(user, capsule_id, db).await {
+        return Ok(capsule);
+    }
+
+    if !capsule.allow_download {
+        return Err(Error(Status::Forbidden));
+    }
+
+    if capsule.privacy == Privacy::Private {
+        check_asset_access(capsule_id, cookies, db).await?;
+    }
+
+    Ok(capsule)
+}
+
+/// Returns the heights in which the video of a capsule can be exported, which are the ones of the
+/// renditions of its publication.
+async fn export_heights(capsule: &Capsule, config: &Config, db: &Db) -> Result<Vec<i32>> {
+    let owner = capsule.owner(&db).await?;
+    let (_, profile) = config
+        .publication_profile(owner.plan, capsule.publication_profile.as_deref())
+        .ok_or(Error(Status::InternalServerError))?;
+
+    Ok(profile
+        .renditions
+        .iter()
+        .filter_map(|x| x.height.map(|x| x as i32))
+        .collect())
+}
+
+/// The route that lists the exports of a video that are ready or being generated.
+#[get("/v/<capsule_id>/exports", rank = 1)]
+pub async fn exports(
+    user: Option<User>,
+    capsule_id: HashId,
+    config: &S<Config>,
+    cookies: &CookieJar<'_>,
+    db: Db,
+) -> Result<Value> {
+    let capsule = check_download_access(capsule_id, &user, cookies, &db).await?;
+
+    let exports = capsule
+        .exports(&db)
+        .await?
+        .into_iter()
+        .filter(|x| x.status != TaskStatus::Failed)
+        .map(|x| x.to_json())
+        .collect::<Vec<_>>();
+
+    Ok(json!({
+        "heights": export_heights(&capsule, &config, &db).await?,
+        "exports": exports,
+    }))
+}
+
+/// The request of an export of a video.
+#[derive(Deserialize)]
+pub struct ExportRequest {
+    /// The format of the export.
+    format: ExportFormat,
+
+    /// The height of the video, the highest one available if absent. Ignored for audio formats.
+    #[serde(default)]
+    height: Option<i32>,
+}
+
+/// Returns the height of the video of an export, checking that it is one of the heights in which
+/// the capsule can be exported, or the highest one if none is requested.
+pub async fn export_height(
+    capsule: &Capsule,
+    format: ExportFormat,
+    height: Option<i32>,
+    config: &Config,
+    db: &Db,
+) -> Result<Option<i32>> {
+    if !format.has_video() {
+        return Ok(None);
+    }
+
+    let heights = export_heights(capsule, config, db).await?;
+    let height = match height {
+        Some(height) => height,
+        None => heights
+            .iter()
+            .max()
+            .copied()
+            .ok_or(Error(Status::BadRequest))?,
+    };
+
+    if !heights.contains(&height) {
+        return Err(Error(Status::BadRequest));
+    }
+
+    Ok(Some(height))
+}
+
+/// Returns the json of the export of a capsule in a format, starting its generation in the
+/// background if it does not exist yet or if it failed.
+///
+/// Exports are shared by every viewer, so a video is only exported once in each format.
+pub async fn find_or_start_export(
+    capsule: Capsule,
+    format: ExportFormat,
+    height: Option<i32>,
+    config: &Config,
+    sem: &Arc<Semaphore>,
+    db: Db,
+) -> Result<Value> {
+    for export in capsule.exports(&db).await? {
+        if export.format == format && export.height == height {
+            if export.status != TaskStatus::Failed {
+                return Ok(export.to_json());
             }
+
+            export.delete(&db).await?;
         }
     }
 
-    partial_content
-        .respond(
-            config
-                .data_path
-                .join(format!("{}", *capsule_id))
-                .join("output")
-                .join(path),
+    let mut export = Export::new(&capsule, format, height, &db).await?;
+    let json = export.to_json();
+
+    let config = config.clone();
+    let sem = sem.clone();
+
+    tokio::spawn(async move {
+        let succeed = match generate_export(&mut export, &capsule, &config, &sem, &db).await {
+            Ok(succeed) => succeed,
+            Err(e) => {
+                error!("Failed to export capsule {}: {}", capsule.id, e);
+                false
+            }
+        };
+
+        export.status = if succeed {
+            TaskStatus::Done
+        } else {
+            TaskStatus::Failed
+        };
+
+        export.pid = None;
+        export.save(&db).await.ok();
+    });
+
+    Ok(json)
+}
+
+/// The route that requests an export of a video, which is generated in the background.
+#[post("/v/<capsule_id>/exports", data = "<data>", rank = 1)]
+pub async fn request_export(
+    user: Option<User>,
+    capsule_id: HashId,
+    data: Json<ExportRequest>,
+    config: &S<Config>,
+    sem: &S<Arc<Semaphore>>,
+    cookies: &CookieJar<'_>,
+    db: Db,
+) -> Result<Value> {
+    let ExportRequest { format, height } = data.into_inner();
+    let capsule = check_download_access(capsule_id, &user, cookies, &db).await?;
+    let height = export_height(&capsule, format, height, &config, &db).await?;
+
+    find_or_start_export(capsule, format, height, &config, &sem, db).await
+}
+
+/// Generates the file of an export and stores it.
+///
+/// Returns false if the export script failed.
+async fn generate_export(
+    export: &mut Export,
+    capsule: &Capsule,
+    config: &Config,
+    sem: &Semaphore,
+    db: &Db,
+) -> Result<bool> {
+    let mut stat = TaskStat::new(TaskStatType::Export, &db).await?;
+    let succeed = run_export(export, capsule, config, sem, &mut stat, db).await;
+    stat.end(&db).await?;
+    succeed
+}
+
+/// Runs the export script and stores the file it generates, unless the export has been deleted in
+/// the meantime because the video changed.
+async fn run_export(
+    export: &mut Export,
+    capsule: &Capsule,
+    config: &Config,
+    sem: &Semaphore,
+    stat: &mut TaskStat,
+    db: &Db,
+) -> Result<bool> {
+    let dir = config.data_path.join(format!("{}", capsule.id));
+    let output = dir.join(export.path());
+    let package = dir.join("exports").join(format!("{}", export.id));
+
+    pull_file(capsule.id, "output.mp4", config).await?;
+    create_dir_all(&dir.join("exports")).await?;
+
+    let mut command = Command::new("../scripts/psh");
+    command
+        .arg("export")
+        .arg(dir.join("output.mp4"))
+        .arg(&output)
+        .arg(export.format.name())
+        .arg(format!("{}", export.height.unwrap_or(0)))
+        .stdin(Stdio::null());
+
+    if export.format == ExportFormat::Scorm {
+        create_dir_all(&package).await?;
+
+        match pull_file(capsule.id, "output/subtitles.webvtt", config).await {
+            Err(e) if e.0 != Status::NotFound => return Err(e),
+            _ => (),
+        }
+
+        let subtitles = dir.join("output").join("subtitles.webvtt");
+        let has_subtitles = subtitles.is_file();
+
+        if has_subtitles {
+            copy(&subtitles, package.join("subtitles.vtt")).await?;
+        }
+
+        let identifier = format!("polymny-{}", HashId(capsule.id).hash());
+
+        write(
+            package.join("imsmanifest.xml"),
+            scorm_manifest_xml(&identifier, &capsule.name, has_subtitles),
         )
-        .await
+        .await?;
+
+        write(
+            package.join("index.html"),
+            scorm_index_html(&capsule.name, has_subtitles),
+        )
+        .await?;
+
+        command.arg(&package);
+    }
+
+    let succeed = {
+        let _permit = sem
+            .acquire()
+            .await
+            .map_err(|_| Error(Status::InternalServerError))?;
+
+        export.status = TaskStatus::Running;
+        export.save(&db).await?;
+        stat.start(&db).await?;
+
+        let mut child = command.spawn()?;
+        export.pid = child.id().map(|x| x as i32);
+        export.save(&db).await?;
+
+        child.wait().await.map(|x| x.success()).unwrap_or(false)
+    };
+
+    if export.format == ExportFormat::Scorm {
+        remove_dir_all(&package).await.ok();
+    }
+
+    if Export::get_by_id(export.id, &db).await?.is_none() {
+        remove_file(&output).await.ok();
+        return Ok(false);
+    }
+
+    if succeed {
+        storage::open(config)?
+            .put_file(&capsule_key(capsule.id, export.path())?, &output)
+            .await?;
+    }
+
+    Ok(succeed)
+}
+
+/// The route that gives the status of an export.
+#[get("/v/<capsule_id>/exports/<export_id>", rank = 1)]
+pub async fn export_status(
+    user: Option<User>,
+    capsule_id: HashId,
+    export_id: i32,
+    cookies: &CookieJar<'_>,
+    db: Db,
+) -> Result<Value> {
+    let capsule = check_download_access(capsule_id, &user, cookies, &db).await?;
+
+    let export = Export::get_by_id(export_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if export.capsule(&db).await?.id != capsule.id {
+        return Err(Error(Status::NotFound));
+    }
+
+    Ok(export.to_json())
+}
+
+/// The route that downloads the file of an export.
+#[get("/v/<capsule_id>/exports/<export_id>/download", rank = 1)]
+pub async fn download_export<'a>(
+    user: Option<User>,
+    capsule_id: HashId,
+    export_id: i32,
+    config: &S<Config>,
+    cookies: &CookieJar<'_>,
+    db: Db,
+    partial_content: PartialContent,
+) -> Result<PartialContentResponse<'a>> {
+    let capsule = check_download_access(capsule_id, &user, cookies, &db).await?;
+
+    let export = Export::get_by_id(export_id, &db)
+        .await?
+        .ok_or(Error(Status::NotFound))?;
+
+    if export.capsule(&db).await?.id != capsule.id || export.status != TaskStatus::Done {
+        return Err(Error(Status::NotFound));
+    }
+
+    let storage = storage::open(&config)?;
+    let mut response = partial_content
+        .serve(storage, &capsule_key(capsule.id, export.path())?)
+        .await?;
+
+    response.response.set_header(Header::new(
+        "Content-Disposition",
+        format!("attachment; filename=\"{}\"", export.file_name(&capsule)),
+    ));
+
+    Ok(response)
 }
 
 /// The route for the js file that contains elm-video.
@@ -0,0 +1,327 @@
+//! This module contains the scheduler, that performs the automatic transitions of assignments and
+//! sends reminders to students before their deadlines. It also removes the unused blobs of the
+//! blob store and, when the files are kept in an object storage, the unused working copies from
+//! time to time.
+
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDateTime, Utc};
+
+use ergol::prelude::*;
+use ergol::tokio;
+
+use tokio::fs::{read_dir, remove_dir_all};
+
+use crate::blobs::BlobStore;
+use crate::config::{Config, StorageConfig};
+use crate::db::capsule::Capsule;
+use crate::db::group::{assignment, Answer, Assignment, AssignmentState};
+use crate::db::task_status::TaskStatus;
+use crate::storage::{self, walk};
+use crate::templates::{assignment_reminder_html, assignment_reminder_plain_text};
+use crate::websockets::WebSockets;
+use crate::{Db, Result, HARSH};
+
+/// Delay between two runs of the scheduler.
+const TICK: Duration = Duration::from_secs(60);
+
+/// The key of the advisory lock held by the server running the scheduler.
+const SCHEDULER_LOCK: i64 = 0x706f6c796d6e79;
+
+/// Number of ticks between two garbage collections of the blob store.
+const GC_TICKS: u64 = 60;
+
+/// Starts the scheduler.
+pub async fn run(socks: WebSockets, pool: ergol::Pool, config: Config) {
+    let mut interval = tokio::time::interval(TICK);
+    let mut ticks: u64 = 0;
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = tick(&socks, pool.clone(), &config).await {
+            error!("Scheduler failed: {}", e);
+        }
+
+        if ticks % GC_TICKS == 0 {
+            match BlobStore::new(&config).gc().await {
+                Ok((0, _)) => (),
+                Ok((removed, freed)) => {
+                    info!("Removed {} unused blobs, {} bytes freed", removed, freed)
+                }
+                Err(e) => error!("Blob store garbage collection failed: {}", e),
+            }
+
+            if let StorageConfig::S3(s3) = &config.storage {
+                match evict(s3.working_copy_lifetime, pool.clone(), &config).await {
+                    Ok(0) => (),
+                    Ok(evicted) => info!("Removed {} unused working copies", evicted),
+                    Err(e) => error!("Removal of the working copies failed: {}", e),
+                }
+            }
+        }
+
+        ticks += 1;
+    }
+}
+
+/// Runs the scheduler once.
+///
+/// Several servers can share the same database, so the scheduler only runs on the one that holds
+/// an advisory lock, which prevents an assignment from being started twice.
+async fn tick(socks: &WebSockets, pool: ergol::Pool, config: &Config) -> Result<()> {
+    let db = Db::from_pool(pool).await?;
+
+    let locked: bool = db
+        .query_one("SELECT pg_try_advisory_lock($1)", &[&SCHEDULER_LOCK])
+        .await?
+        .get(0);
+
+    if !locked {
+        return Ok(());
+    }
+
+    let result = tick_locked(socks, config, &db).await;
+
+    db.query_one("SELECT pg_advisory_unlock($1)", &[&SCHEDULER_LOCK])
+        .await?;
+
+    result
+}
+
+/// Runs the scheduler once, while holding the advisory lock.
+async fn tick_locked(socks: &WebSockets, config: &Config, db: &Db) -> Result<()> {
+    let now = Utc::now().naive_utc();
+
+    // Only the assignments that should open and the ones that are running need to be handled.
+    let opening = Assignment::select()
+        .filter(
+            assignment::state::eq(AssignmentState::Preparation)
+                .or(assignment::state::eq(AssignmentState::Prepared))
+                .and(assignment::opens::leq(Some(now))),
+        )
+        .execute(&db)
+        .await?;
+
+    let working = Assignment::select()
+        .filter(assignment::state::eq(AssignmentState::Working))
+        .execute(&db)
+        .await?;
+
+    for mut assignment in opening.into_iter().chain(working) {
+        // An error on an assignment must not prevent the others from being handled.
+        if let Err(e) = handle(&mut assignment, now, socks, config, db).await {
+            error!("Scheduler failed on assignment {}: {}", assignment.id, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Performs the transition or sends the reminders due for an assignment.
+async fn handle(
+    assignment: &mut Assignment,
+    now: NaiveDateTime,
+    socks: &WebSockets,
+    config: &Config,
+    db: &Db,
+) -> Result<()> {
+    match assignment.state {
+        AssignmentState::Preparation | AssignmentState::Prepared => {
+            if assignment.opens.map(|x| x <= now).unwrap_or(false) {
+                info!("Starting assignment {}", assignment.id);
+                assignment.start(None, config, socks, db).await?;
+            }
+        }
+
+        AssignmentState::Working => {
+            let closing_time = assignment.closing_time(db).await?;
+
+            if closing_time.map(|x| x <= now).unwrap_or(false) {
+                info!("Closing assignment {}", assignment.id);
+                assignment.open_evaluation(None, db).await?;
+                return Ok(());
+            }
+
+            for mut answer in assignment.answers(db).await? {
+                if answer.finished {
+                    continue;
+                }
+
+                if let Some(deadline) = assignment.deadline_for(&answer) {
+                    remind(&mut answer, deadline, now, socks, config, db).await?;
+                }
+            }
+        }
+
+        _ => (),
+    }
+
+    Ok(())
+}
+
+/// Returns the number of reminder thresholds, in hours before a deadline, that have been crossed.
+///
+/// No reminder is due once the deadline has passed.
+fn crossed_reminders(deadline: NaiveDateTime, now: NaiveDateTime, reminders: &[u64]) -> i32 {
+    if deadline <= now {
+        return 0;
+    }
+
+    reminders
+        .iter()
+        .filter(|x| deadline - ChronoDuration::hours(**x as i64) <= now)
+        .count() as i32
+}
+
+/// Reminds a student of their deadline if a reminder threshold was crossed since the last one.
+async fn remind(
+    answer: &mut Answer,
+    deadline: NaiveDateTime,
+    now: NaiveDateTime,
+    socks: &WebSockets,
+    config: &Config,
+    db: &Db,
+) -> Result<()> {
+    let crossed = crossed_reminders(deadline, now, &config.assignment_reminders);
+
+    if crossed <= answer.reminders_sent {
+        return Ok(());
+    }
+
+    // Only one reminder is sent even if several thresholds were crossed at once.
+    answer.reminders_sent = crossed;
+    answer.save(db).await?;
+
+    let student = answer.student(db).await?;
+    let capsule = answer.capsule(db).await?;
+    let deadline = deadline.format("%Y-%m-%d %H:%M").to_string();
+
+    student
+        .notify(
+            socks,
+            "Rendu bientôt attendu",
+            &format!(
+                "Votre rendu \"{}\" doit être validé avant le {} (UTC).",
+                capsule.name, deadline
+            ),
+            db,
+        )
+        .await?;
+
+    if let Some(mailer) = &config.mailer {
+        let url = format!(
+            "{}/capsule/preparation/{}",
+            config.root,
+            HARSH.encode(capsule.id)
+        );
+
+        mailer.send_mail(
+            &student.email,
+            String::from("Your answer is due soon"),
+            assignment_reminder_plain_text(&capsule.name, &deadline, &url),
+            assignment_reminder_html(&capsule.name, &deadline, &url),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Removes the working copies of the capsules that have not been used for some time.
+///
+/// Returns the number of working copies removed.
+async fn evict(lifetime: u64, pool: ergol::Pool, config: &Config) -> Result<usize> {
+    let db = Db::from_pool(pool).await?;
+    let limit = Utc::now() - ChronoDuration::seconds(lifetime as i64);
+    let mut evicted = 0;
+
+    let mut entries = read_dir(&config.data_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let id = match entry
+            .file_name()
+            .to_str()
+            .and_then(|x| x.parse::<i32>().ok())
+        {
+            Some(id) => id,
+            None => continue,
+        };
+
+        // An error on a capsule must not prevent the others from being handled.
+        match evict_capsule(id, &entry.path(), limit, config, &db).await {
+            Ok(true) => evicted += 1,
+            Ok(false) => (),
+            Err(e) => error!("Failed to remove the working copy of capsule {}: {}", id, e),
+        }
+    }
+
+    Ok(evicted)
+}
+
+/// Removes the working copy of a capsule if it was not modified since the limit, and returns
+/// whether it was removed.
+async fn evict_capsule(
+    id: i32,
+    dir: &Path,
+    limit: DateTime<Utc>,
+    config: &Config,
+    db: &Db,
+) -> Result<bool> {
+    let last_modified = walk(dir)
+        .await?
+        .iter()
+        .filter_map(|(_, meta)| meta.modified().ok())
+        .map(DateTime::<Utc>::from)
+        .max();
+
+    if last_modified.map(|x| x > limit).unwrap_or(false) {
+        return Ok(false);
+    }
+
+    // The working copy of a deleted capsule is removed without being stored again.
+    if let Some(capsule) = Capsule::get_by_id(id, &db).await? {
+        // The production and the publication use the working copy until they end.
+        if capsule.produced == TaskStatus::Running || capsule.published == TaskStatus::Running {
+            return Ok(false);
+        }
+
+        // Nothing is removed before it is stored.
+        storage::open(config)?
+            .push_dir(&format!("{}", id), dir)
+            .await?;
+    }
+
+    remove_dir_all(dir).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, NaiveDate};
+
+    use super::crossed_reminders;
+
+    #[test]
+    fn reminders_are_due_as_the_deadline_approaches() {
+        let deadline = NaiveDate::from_ymd_opt(2023, 6, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let reminders = [48, 24, 1];
+        let crossed = |minutes: i64| {
+            crossed_reminders(deadline, deadline - Duration::minutes(minutes), &reminders)
+        };
+
+        assert_eq!(crossed(72 * 60), 0);
+        assert_eq!(crossed(48 * 60), 1);
+        assert_eq!(crossed(30 * 60), 1);
+        assert_eq!(crossed(2 * 60), 2);
+        assert_eq!(crossed(30), 3);
+        assert_eq!(crossed(0), 0);
+        assert_eq!(crossed(-60), 0);
+        assert_eq!(
+            crossed_reminders(deadline, deadline - Duration::hours(1), &[]),
+            0
+        );
+    }
+}
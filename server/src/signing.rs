@@ -0,0 +1,371 @@
+//! This module contains the signer that mints the tokens of video segment urls.
+//!
+//! When a video is watched, the watch route mints a token made of an expiration timestamp and an
+//! HMAC-SHA256 signature of the capsule id and this timestamp. The token is appended to the urls of
+//! the playlists and segments, which can then be validated without any session lookup, or by a
+//! static file server or a CDN that shares the secret.
+//!
+//! A token stays valid until it expires, so the server still checks that the capsule is published
+//! and, for private capsules, that the viewer still has access before serving signed files.
+//!
+//! The same secret signs the identifiers of the plays started by the watch route, together with the
+//! capsule and the viewer, so that the answers to the quizzes can only be sent for those plays.
+
+use std::fmt::Write;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use chrono::Utc;
+
+use rocket::serde::json::{from_str, Value};
+
+use crate::config::Config;
+
+/// The HMAC type used to sign segment urls.
+type HmacSha256 = Hmac<Sha256>;
+
+/// A token that grants access to the files of a published video until it expires.
+#[derive(Debug, Clone)]
+pub struct SegmentToken {
+    /// The unix timestamp after which the token is no longer valid.
+    pub exp: i64,
+
+    /// The hex encoded signature of the token.
+    pub sig: String,
+}
+
+impl SegmentToken {
+    /// Returns the query string that must be appended to urls.
+    pub fn query(&self) -> String {
+        format!("exp={}&sig={}", self.exp, self.sig)
+    }
+
+    /// Appends the token to a url.
+    pub fn append_to(&self, url: &str) -> String {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", url, separator, self.query())
+    }
+}
+
+/// The struct that signs and verifies the tokens of segment urls.
+#[derive(Clone)]
+pub struct SegmentSigner {
+    /// The secret key of the HMAC.
+    key: Vec<u8>,
+
+    /// The number of seconds during which a token is valid.
+    duration: i64,
+}
+
+impl SegmentSigner {
+    /// Creates the signer from the config.
+    ///
+    /// If no secret is configured, a random one is generated, which means that tokens will not
+    /// survive restarts and will not be shared with other hosts.
+    pub fn from_config(config: &Config) -> SegmentSigner {
+        let key = match &config.segment_secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                warn!("No segment secret configured, generating a random one");
+                let mut key = vec![0; 32];
+                OsRng.fill_bytes(&mut key);
+                key
+            }
+        };
+
+        SegmentSigner {
+            key,
+            duration: config.segment_token_duration as i64,
+        }
+    }
+
+    /// Creates the HMAC of a message.
+    fn mac(&self, message: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+        mac.update(message.as_bytes());
+        mac
+    }
+
+    /// Returns the hex encoded HMAC of a message.
+    fn hex_mac(&self, message: &str) -> String {
+        let bytes = self.mac(message).finalize().into_bytes();
+
+        let mut sig = String::with_capacity(2 * bytes.len());
+        for byte in bytes {
+            write!(sig, "{:02x}", byte).unwrap();
+        }
+
+        sig
+    }
+
+    /// Returns true if a hex encoded signature is the HMAC of a message.
+    fn verify_mac(&self, message: &str, sig: &str) -> bool {
+        match decode_hex(sig) {
+            // verify_slice compares in constant time.
+            Some(bytes) => self.mac(message).verify_slice(&bytes).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Mints a new token for the files of a capsule.
+    pub fn sign(&self, capsule_id: i32) -> SegmentToken {
+        let exp = Utc::now().timestamp() + self.duration;
+        let sig = self.hex_mac(&format!("{}:{}", capsule_id, exp));
+        SegmentToken { exp, sig }
+    }
+
+    /// Returns true if the token is a valid and unexpired token for the files of the capsule.
+    pub fn verify(&self, capsule_id: i32, exp: i64, sig: &str) -> bool {
+        exp >= Utc::now().timestamp() && self.verify_mac(&format!("{}:{}", capsule_id, exp), sig)
+    }
+
+    /// Signs the identifier of a play of a capsule by a viewer, None for anonymous viewers.
+    pub fn sign_session(&self, capsule_id: i32, session: &str, user_id: Option<i32>) -> String {
+        self.hex_mac(&session_message(capsule_id, session, user_id))
+    }
+
+    /// Returns true if the signature is the one given by the watch route to the viewer for this
+    /// play of the capsule.
+    pub fn verify_session(
+        &self,
+        capsule_id: i32,
+        session: &str,
+        user_id: Option<i32>,
+        sig: &str,
+    ) -> bool {
+        self.verify_mac(&session_message(capsule_id, session, user_id), sig)
+    }
+}
+
+/// Returns the message signed for a play of a capsule by a viewer.
+///
+/// The message is prefixed so that it can never be mistaken for the one of a segment token.
+fn session_message(capsule_id: i32, session: &str, user_id: Option<i32>) -> String {
+    match user_id {
+        Some(user_id) => format!("session:{}:{}:{}", capsule_id, session, user_id),
+        None => format!("session:{}:{}:", capsule_id, session),
+    }
+}
+
+/// Decodes a hex string, returning None if the string is not valid hex.
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    if input.len() % 2 == 1 {
+        return None;
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(input.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Appends a token to every uri of an HLS playlist.
+///
+/// This covers the uri lines of the playlist, and the `URI` attributes of tags such as
+/// `#EXT-X-MEDIA`.
+pub fn sign_playlist(playlist: &str, token: &SegmentToken) -> String {
+    rewrite_playlist(playlist, |uri| token.append_to(uri))
+}
+
+/// Returns the uris of an HLS playlist.
+pub fn playlist_uris(playlist: &str) -> Vec<String> {
+    let mut uris = vec![];
+    rewrite_playlist(playlist, |uri| {
+        uris.push(uri.to_string());
+        uri.to_string()
+    });
+    uris
+}
+
+/// Replaces every uri of an HLS playlist.
+pub fn rewrite_playlist<F: FnMut(&str) -> String>(playlist: &str, mut rewrite: F) -> String {
+    let mut output = String::with_capacity(playlist.len());
+
+    for line in playlist.lines() {
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            output.push_str(line);
+        } else if trimmed.starts_with('#') {
+            match line.find("URI=\"") {
+                Some(start) => {
+                    let start = start + "URI=\"".len();
+                    match line[start..].find('"') {
+                        Some(len) => {
+                            output.push_str(&line[..start]);
+                            output.push_str(&rewrite(&line[start..start + len]));
+                            output.push_str(&line[start + len..]);
+                        }
+                        None => output.push_str(line),
+                    }
+                }
+                None => output.push_str(line),
+            }
+        } else {
+            output.push_str(&rewrite(trimmed));
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+/// Appends a token to the url of the thumbnail of every slide of a slide index.
+pub fn sign_slides(index: &str, token: &SegmentToken) -> Option<String> {
+    let mut index: Value = from_str(index).ok()?;
+
+    for slide in index.get_mut("slides")?.as_array_mut()? {
+        if let Some(thumbnail) = slide.get_mut("thumbnail") {
+            if let Some(url) = thumbnail.as_str() {
+                *thumbnail = Value::String(token.append_to(url));
+            }
+        }
+    }
+
+    Some(index.to_string())
+}
+
+/// Appends a token to the url of the sprite sheet in every cue of a WebVTT thumbnails track.
+///
+/// The cues of such a track look like `preview.jpg#xywh=0,0,160,90`, the token must be inserted
+/// before the fragment.
+pub fn sign_thumbnails(track: &str, token: &SegmentToken) -> String {
+    let mut output = String::with_capacity(track.len());
+
+    for line in track.lines() {
+        match line.find("#xywh=") {
+            Some(fragment) => {
+                output.push_str(&token.append_to(&line[..fragment]));
+                output.push_str(&line[fragment..]);
+            }
+            None => output.push_str(line),
+        }
+
+        output.push('\n');
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::{playlist_uris, sign_playlist, sign_thumbnails, SegmentSigner, SegmentToken};
+
+    /// Creates a signer whose tokens are valid during a given number of seconds.
+    fn signer(duration: i64) -> SegmentSigner {
+        SegmentSigner {
+            key: b"secret".to_vec(),
+            duration,
+        }
+    }
+
+    /// Returns a signature with its first hex digit changed.
+    fn tamper(sig: &str) -> String {
+        let first = if sig.starts_with('0') { "1" } else { "0" };
+        format!("{}{}", first, &sig[1..])
+    }
+
+    #[test]
+    fn tokens_verify_for_their_capsule() {
+        let signer = signer(60);
+        let token = signer.sign(1);
+        assert!(signer.verify(1, token.exp, &token.sig));
+        assert!(!signer.verify(2, token.exp, &token.sig));
+        assert!(!signer.verify(1, token.exp + 1, &token.sig));
+    }
+
+    #[test]
+    fn tokens_expire() {
+        let signer = signer(-1);
+        let token = signer.sign(1);
+        assert!(token.exp < Utc::now().timestamp());
+        assert!(!signer.verify(1, token.exp, &token.sig));
+    }
+
+    #[test]
+    fn tampered_tokens_are_refused() {
+        let signer = signer(60);
+        let token = signer.sign(1);
+        assert!(!signer.verify(1, token.exp, &tamper(&token.sig)));
+        assert!(!signer.verify(1, token.exp, &token.sig[1..]));
+        assert!(!signer.verify(1, token.exp, "zz"));
+        assert!(!signer.verify(1, token.exp, ""));
+
+        let other = SegmentSigner {
+            key: b"other".to_vec(),
+            duration: 60,
+        };
+        assert!(!other.verify(1, token.exp, &token.sig));
+    }
+
+    #[test]
+    fn sessions_verify_for_their_capsule_and_viewer() {
+        let signer = signer(60);
+        let sig = signer.sign_session(1, "abc", Some(7));
+        assert!(signer.verify_session(1, "abc", Some(7), &sig));
+        assert!(!signer.verify_session(2, "abc", Some(7), &sig));
+        assert!(!signer.verify_session(1, "abd", Some(7), &sig));
+        assert!(!signer.verify_session(1, "abc", Some(8), &sig));
+        assert!(!signer.verify_session(1, "abc", None, &sig));
+        assert!(!signer.verify_session(1, "abc", Some(7), &tamper(&sig)));
+
+        let sig = signer.sign_session(1, "abc", None);
+        assert!(signer.verify_session(1, "abc", None, &sig));
+        assert!(!signer.verify_session(1, "abc", Some(7), &sig));
+    }
+
+    #[test]
+    fn sessions_and_tokens_are_not_interchangeable() {
+        let signer = signer(60);
+
+        // A session whose identifier looks like an expiration timestamp.
+        let exp = Utc::now().timestamp() + 60;
+        let sig = signer.sign_session(1, &exp.to_string(), None);
+        assert!(!signer.verify(1, exp, &sig));
+
+        let token = signer.sign(1);
+        assert!(!signer.verify_session(1, &token.exp.to_string(), None, &token.sig));
+    }
+
+    #[test]
+    fn playlists_are_signed() {
+        let token = SegmentToken {
+            exp: 10,
+            sig: "ab".to_string(),
+        };
+        let playlist =
+            "#EXTM3U\n#EXT-X-MEDIA:TYPE=SUBTITLES,URI=\"subs.m3u8\"\n\n720p/index.m3u8\n";
+
+        let signed = sign_playlist(playlist, &token);
+        assert_eq!(
+            signed,
+            "#EXTM3U\n#EXT-X-MEDIA:TYPE=SUBTITLES,URI=\"subs.m3u8?exp=10&sig=ab\"\n\n\
+             720p/index.m3u8?exp=10&sig=ab\n"
+        );
+        assert_eq!(
+            playlist_uris(playlist),
+            vec!["subs.m3u8", "720p/index.m3u8"]
+        );
+    }
+
+    #[test]
+    fn thumbnails_are_signed_before_the_fragment() {
+        let token = SegmentToken {
+            exp: 10,
+            sig: "ab".to_string(),
+        };
+        let track = "WEBVTT\n\n00:00.000 --> 00:05.000\npreview.jpg#xywh=0,0,160,90\n";
+
+        assert_eq!(
+            sign_thumbnails(track, &token),
+            "WEBVTT\n\n00:00.000 --> 00:05.000\npreview.jpg?exp=10&sig=ab#xywh=0,0,160,90\n"
+        );
+    }
+}
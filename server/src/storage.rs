@@ -0,0 +1,772 @@
+//! This module contains the storage backends in which the files of the capsules are kept.
+//!
+//! Files are identified by keys such as `<capsule_id>/assets/<uuid>.png`. Whatever the backend,
+//! the media pipeline works on a local working copy of the files, located in the data path: the
+//! routes push the files they create to the storage, and pull the files they need before running
+//! the scripts. With the local backend, the working copy is the storage itself, so pushing and
+//! pulling do nothing. With an object storage, files are synced when their size or modification
+//! time differ, since the scripts rewrite some of them in place, and the working copies that are
+//! no longer used are removed by the scheduler. Once synced, a working copy takes the modification
+//! time of the stored file, so that it is not transferred back.
+
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{
+    copy, create_dir_all, metadata, read_dir, remove_dir_all, remove_file, rename, File,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::task::spawn_blocking;
+
+use uuid::Uuid;
+
+use chrono::{DateTime, Utc};
+
+use s3::bucket::Bucket;
+use s3::creds::Credentials;
+use s3::region::Region;
+
+use rocket::http::Status;
+
+use crate::blobs::BlobStore;
+use crate::config::{Config, S3Config, StorageConfig};
+use crate::{Error, Result};
+
+/// Returns the key of a file of a capsule.
+pub fn capsule_key<P: AsRef<Path>>(capsule_id: i32, path: P) -> Result<String> {
+    let path = path.as_ref().to_str().ok_or(Error(Status::BadRequest))?;
+    Ok(format!("{}/{}", capsule_id, path))
+}
+
+/// Opens the storage configured for the files of the capsules.
+pub fn open(config: &Config) -> Result<Box<dyn Storage>> {
+    match &config.storage {
+        StorageConfig::Local => Ok(Box::new(LocalStorage::new(config))),
+        StorageConfig::S3(s3) => Ok(Box::new(S3Storage::new(s3)?)),
+    }
+}
+
+/// Deletes every file of a capsule, from the storage and from its working copy.
+pub async fn delete_capsule(capsule_id: i32, config: &Config) -> Result<()> {
+    open(config)?.delete_dir(&format!("{}", capsule_id)).await?;
+
+    match remove_dir_all(config.data_path.join(format!("{}", capsule_id))).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// The information needed to answer conditional and range requests on a file.
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    /// The size of the file in bytes.
+    pub len: u64,
+
+    /// The strong entity tag of the file, with its quotes.
+    pub etag: String,
+
+    /// The time at which the file was last modified, if known.
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Returns the information about a file of the local filesystem, or None if it does not exist.
+///
+/// Assets are named after a uuid and never rewritten, so their name identifies their content.
+/// Other files are identified by their size and modification time.
+pub async fn file_info(path: &Path) -> Result<Option<FileInfo>> {
+    let meta = match metadata(path).await {
+        Ok(meta) if meta.is_file() => meta,
+        Ok(_) => return Ok(None),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let last_modified = modified(&meta);
+
+    let in_assets = path
+        .parent()
+        .and_then(|x| x.file_name())
+        .map(|x| x == "assets")
+        .unwrap_or(false);
+
+    let name = path.file_name().and_then(|x| x.to_str()).unwrap_or("");
+    let named_after_uuid = name
+        .split('.')
+        .next()
+        .map(|x| Uuid::parse_str(x).is_ok())
+        .unwrap_or(false);
+
+    let etag = if in_assets && named_after_uuid {
+        format!("\"{}\"", name)
+    } else {
+        format!(
+            "\"{:x}-{:x}\"",
+            last_modified.map(|x| x.timestamp_nanos()).unwrap_or(0),
+            meta.len()
+        )
+    };
+
+    Ok(Some(FileInfo {
+        len: meta.len(),
+        etag,
+        last_modified,
+    }))
+}
+
+/// A file listed in a storage.
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    /// The key of the file.
+    pub key: String,
+
+    /// The size of the file in bytes.
+    pub len: u64,
+
+    /// The time at which the file was last modified, if known.
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Returns the time at which a local file was last modified, if known.
+fn modified(meta: &Metadata) -> Option<DateTime<Utc>> {
+    meta.modified().ok().map(DateTime::<Utc>::from)
+}
+
+/// Sets the time at which a local file was last modified.
+async fn set_modified(path: &Path, date: DateTime<Utc>) -> Result<()> {
+    let path = path.to_owned();
+
+    spawn_blocking(move || {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_modified(date.into())
+    })
+    .await
+    .map_err(|_| Error(Status::InternalServerError))??;
+
+    Ok(())
+}
+
+/// Returns true if a copy of a file must be replaced by another one.
+///
+/// Files are rewritten in place, so a file of the same size is outdated if it was modified before
+/// the other one. When a date is unknown, the copy is replaced to be safe.
+fn outdated(copy: (u64, Option<DateTime<Utc>>), other: (u64, Option<DateTime<Utc>>)) -> bool {
+    match (copy, other) {
+        ((len, _), (other_len, _)) if len != other_len => true,
+        ((_, Some(date)), (_, Some(other_date))) => date < other_date,
+        _ => true,
+    }
+}
+
+/// Returns every file of a directory and of its subdirectories, with their metadata.
+///
+/// Returns an empty list if the directory does not exist.
+pub async fn walk(dir: &Path) -> Result<Vec<(PathBuf, Metadata)>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_owned()];
+
+    while let Some(dir) = dirs.pop() {
+        let mut iter = match read_dir(&dir).await {
+            Ok(iter) => iter,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(entry) = iter.next_entry().await? {
+            let meta = entry.metadata().await?;
+            if meta.is_dir() {
+                dirs.push(entry.path());
+            } else if meta.is_file() {
+                files.push((entry.path(), meta));
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Maps the errors of missing files to a not found status.
+fn not_found(e: std::io::Error) -> Error {
+    if e.kind() == ErrorKind::NotFound {
+        Error(Status::NotFound)
+    } else {
+        e.into()
+    }
+}
+
+/// A backend in which the files of the capsules are stored.
+#[rocket::async_trait]
+pub trait Storage: Send + Sync {
+    /// Returns the path of a file if it is stored on the local filesystem.
+    fn local_path(&self, key: &str) -> Option<PathBuf>;
+
+    /// Returns the information about a file, or None if it does not exist.
+    async fn stat(&self, key: &str) -> Result<Option<FileInfo>>;
+
+    /// Reads the whole content of a file.
+    async fn read(&self, key: &str) -> Result<Vec<u8>>;
+
+    /// Reads the bytes of a file between start and end, both included.
+    async fn read_range(&self, key: &str, start: u64, end: u64) -> Result<Vec<u8>>;
+
+    /// Lists the files under a prefix.
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredFile>>;
+
+    /// Stores a local file.
+    async fn put_file(&self, key: &str, path: &Path) -> Result<()>;
+
+    /// Downloads a file to the local filesystem.
+    async fn get_file(&self, key: &str, path: &Path) -> Result<()>;
+
+    /// Deletes a file, if it exists.
+    async fn delete(&self, key: &str) -> Result<()>;
+
+    /// Copies every file under a prefix to another prefix.
+    async fn copy_dir(&self, from: &str, to: &str) -> Result<()>;
+
+    /// Deletes every file under a prefix.
+    async fn delete_dir(&self, prefix: &str) -> Result<()>;
+
+    /// Returns a url that gives access to a file for some seconds without going through the
+    /// server, if the backend supports it.
+    async fn presign(&self, key: &str, duration: u64) -> Result<Option<String>>;
+
+    /// Stores the files of the working copy of a prefix that are missing from the storage or that
+    /// changed since they were stored.
+    async fn push_dir(&self, prefix: &str, dir: &Path) -> Result<()> {
+        let stored = self
+            .list(prefix)
+            .await?
+            .into_iter()
+            .map(|x| (x.key.clone(), x))
+            .collect::<HashMap<_, _>>();
+
+        let mut pushed = vec![];
+
+        for (path, meta) in walk(dir).await? {
+            let relative = path
+                .strip_prefix(dir)
+                .map_err(|_| Error(Status::InternalServerError))?;
+
+            let key = format!(
+                "{}/{}",
+                prefix,
+                relative
+                    .to_str()
+                    .ok_or(Error(Status::InternalServerError))?
+            );
+
+            let push = match stored.get(&key) {
+                Some(file) => outdated(
+                    (file.len, file.last_modified),
+                    (meta.len(), modified(&meta)),
+                ),
+                None => true,
+            };
+
+            if push {
+                self.put_file(&key, &path).await?;
+                pushed.push((key, path, modified(&meta)));
+            }
+        }
+
+        if pushed.is_empty() {
+            return Ok(());
+        }
+
+        let stored = self
+            .list(prefix)
+            .await?
+            .into_iter()
+            .map(|x| (x.key, x.last_modified))
+            .collect::<HashMap<_, _>>();
+
+        for (key, path, date) in pushed {
+            // A file rewritten while it was pushed differs from the stored one and keeps its date.
+            if let Some(Some(stored_date)) = stored.get(&key) {
+                if modified(&metadata(&path).await?) == date {
+                    set_modified(&path, *stored_date).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Downloads the files of a prefix that are missing from its working copy or that changed
+    /// since they were downloaded.
+    async fn pull_dir(&self, prefix: &str, dir: &Path) -> Result<()> {
+        for file in self.list(prefix).await? {
+            let relative = file
+                .key
+                .strip_prefix(prefix)
+                .map(|x| x.trim_start_matches('/'))
+                .ok_or(Error(Status::InternalServerError))?;
+
+            let path = dir.join(relative);
+
+            let pull = match metadata(&path).await {
+                Ok(meta) => outdated(
+                    (meta.len(), modified(&meta)),
+                    (file.len, file.last_modified),
+                ),
+                Err(_) => true,
+            };
+
+            if pull {
+                self.get_file(&file.key, &path).await?;
+
+                if let Some(date) = file.last_modified {
+                    set_modified(&path, date).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The storage that keeps the files in the data path.
+pub struct LocalStorage {
+    /// The directory in which the files are stored.
+    root: PathBuf,
+
+    /// The blob store used to share files between copies.
+    blobs: BlobStore,
+}
+
+impl LocalStorage {
+    /// Creates the local storage of the data path of the config.
+    pub fn new(config: &Config) -> LocalStorage {
+        LocalStorage {
+            root: config.data_path.clone(),
+            blobs: BlobStore::new(config),
+        }
+    }
+
+    /// Returns the path of the file of a key.
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for LocalStorage {
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+
+    async fn stat(&self, key: &str) -> Result<Option<FileInfo>> {
+        file_info(&self.path(key)).await
+    }
+
+    async fn read(&self, key: &str) -> Result<Vec<u8>> {
+        tokio::fs::read(self.path(key)).await.map_err(not_found)
+    }
+
+    async fn read_range(&self, key: &str, start: u64, end: u64) -> Result<Vec<u8>> {
+        let mut file = File::open(self.path(key)).await.map_err(not_found)?;
+        file.seek(SeekFrom::Start(start)).await?;
+
+        let mut content = vec![];
+        file.take(end - start + 1).read_to_end(&mut content).await?;
+        Ok(content)
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredFile>> {
+        let mut files = vec![];
+
+        for (path, meta) in walk(&self.path(prefix)).await? {
+            let key = path
+                .strip_prefix(&self.root)
+                .ok()
+                .and_then(|x| x.to_str())
+                .ok_or(Error(Status::InternalServerError))?;
+
+            files.push(StoredFile {
+                key: key.to_string(),
+                len: meta.len(),
+                last_modified: modified(&meta),
+            });
+        }
+
+        Ok(files)
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<()> {
+        let dest = self.path(key);
+
+        if dest != path {
+            create_dir_all(dest.parent().ok_or(Error(Status::InternalServerError))?).await?;
+            copy(path, dest).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn get_file(&self, key: &str, path: &Path) -> Result<()> {
+        let orig = self.path(key);
+
+        if orig != path {
+            create_dir_all(path.parent().ok_or(Error(Status::InternalServerError))?).await?;
+            copy(orig, path).await.map_err(not_found)?;
+        }
+
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match remove_file(self.path(key)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn copy_dir(&self, from: &str, to: &str) -> Result<()> {
+        let from = self.path(from);
+        let to = self.path(to);
+
+        if !from.is_dir() {
+            return Ok(());
+        }
+
+        // The copies share their content with the original files until one of them is rewritten.
+        let mut dirs = vec![from.clone()];
+        while let Some(dir) = dirs.pop() {
+            let dest = to.join(
+                dir.strip_prefix(&from)
+                    .map_err(|_| Error(Status::InternalServerError))?,
+            );
+
+            self.blobs.link_dir(&dir, &dest).await?;
+
+            let mut iter = read_dir(&dir).await?;
+            while let Some(entry) = iter.next_entry().await? {
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(entry.path());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn delete_dir(&self, prefix: &str) -> Result<()> {
+        match remove_dir_all(self.path(prefix)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn presign(&self, _key: &str, _duration: u64) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn push_dir(&self, _prefix: &str, _dir: &Path) -> Result<()> {
+        // The working copy is the storage itself.
+        Ok(())
+    }
+
+    async fn pull_dir(&self, _prefix: &str, _dir: &Path) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Checks the status of a response of the object storage.
+fn check(status: u16) -> Result<()> {
+    match status {
+        200..=299 => Ok(()),
+        404 => Err(Error(Status::NotFound)),
+        _ => {
+            error!("Object storage responded with status {}", status);
+            Err(Error(Status::InternalServerError))
+        }
+    }
+}
+
+/// The storage that keeps the files in a bucket of an S3 compatible object storage.
+pub struct S3Storage {
+    /// The bucket in which the files are stored.
+    bucket: Bucket,
+}
+
+impl S3Storage {
+    /// Creates the storage from its config.
+    pub fn new(config: &S3Config) -> Result<S3Storage> {
+        let region = Region::Custom {
+            region: config.region.clone(),
+            endpoint: config.endpoint.clone(),
+        };
+
+        let credentials = Credentials::new(
+            Some(&config.access_key),
+            Some(&config.secret_key),
+            None,
+            None,
+            None,
+        )?;
+
+        let bucket = Bucket::new(&config.bucket, region, credentials)?;
+
+        // Path style urls are required by most self hosted object storages, such as MinIO.
+        let bucket = if config.path_style {
+            bucket.with_path_style()
+        } else {
+            bucket
+        };
+
+        Ok(S3Storage { bucket })
+    }
+}
+
+#[rocket::async_trait]
+impl Storage for S3Storage {
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+
+    async fn stat(&self, key: &str) -> Result<Option<FileInfo>> {
+        let (head, status) = self.bucket.head_object(key).await?;
+
+        if status == 404 {
+            return Ok(None);
+        }
+
+        check(status)?;
+
+        let last_modified = head
+            .last_modified
+            .and_then(|x| DateTime::parse_from_rfc2822(&x).ok())
+            .map(|x| x.with_timezone(&Utc));
+
+        Ok(Some(FileInfo {
+            len: head.content_length.unwrap_or(0) as u64,
+            etag: head.e_tag.unwrap_or_else(|| format!("\"{}\"", key)),
+            last_modified,
+        }))
+    }
+
+    async fn read(&self, key: &str) -> Result<Vec<u8>> {
+        let response = self.bucket.get_object(key).await?;
+        check(response.status_code())?;
+        Ok(response.bytes().to_vec())
+    }
+
+    async fn read_range(&self, key: &str, start: u64, end: u64) -> Result<Vec<u8>> {
+        let response = self.bucket.get_object_range(key, start, Some(end)).await?;
+        check(response.status_code())?;
+        Ok(response.bytes().to_vec())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<StoredFile>> {
+        let results = self.bucket.list(format!("{}/", prefix), None).await?;
+
+        Ok(results
+            .into_iter()
+            .flat_map(|x| x.contents)
+            .map(|x| StoredFile {
+                last_modified: DateTime::parse_from_rfc3339(&x.last_modified)
+                    .ok()
+                    .map(|x| x.with_timezone(&Utc)),
+                key: x.key,
+                len: x.size,
+            })
+            .collect())
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<()> {
+        let mut file = File::open(path).await?;
+        check(self.bucket.put_object_stream(&mut file, key).await?)
+    }
+
+    async fn get_file(&self, key: &str, path: &Path) -> Result<()> {
+        create_dir_all(path.parent().ok_or(Error(Status::InternalServerError))?).await?;
+
+        // The file is downloaded next to its destination, so that a failed download does not
+        // leave a truncated file in the working copy.
+        let tmp = path.with_extension(format!("{}.part", Uuid::new_v4()));
+        let mut file = File::create(&tmp).await?;
+        let status = self.bucket.get_object_to_writer(key, &mut file).await?;
+
+        if let Err(e) = check(status) {
+            tokio::fs::remove_file(&tmp).await.ok();
+            return Err(e);
+        }
+
+        rename(&tmp, path).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match check(self.bucket.delete_object(key).await?.status_code()) {
+            Err(Error(Status::NotFound)) => Ok(()),
+            x => x,
+        }
+    }
+
+    async fn copy_dir(&self, from: &str, to: &str) -> Result<()> {
+        for file in self.list(from).await? {
+            let dest = format!("{}{}", to, &file.key[from.len()..]);
+            check(self.bucket.copy_object_internal(&file.key, &dest).await?)?;
+        }
+
+        Ok(())
+    }
+
+    async fn delete_dir(&self, prefix: &str) -> Result<()> {
+        for file in self.list(prefix).await? {
+            self.delete(&file.key).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn presign(&self, key: &str, duration: u64) -> Result<Option<String>> {
+        Ok(Some(self.bucket.presign_get(key, duration as u32, None)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::fs::{copy, create_dir_all, remove_dir_all, write};
+
+    use chrono::{TimeZone, Utc};
+
+    use uuid::Uuid;
+
+    use super::{modified, set_modified, walk, FileInfo, Storage, StoredFile};
+    use crate::Result;
+
+    /// A storage that keeps its files in a directory like an object storage does, and counts the
+    /// transfers.
+    struct MockStorage {
+        root: PathBuf,
+        transfers: AtomicUsize,
+    }
+
+    impl MockStorage {
+        fn transfers(&self) -> usize {
+            self.transfers.load(Ordering::SeqCst)
+        }
+    }
+
+    #[rocket::async_trait]
+    impl Storage for MockStorage {
+        fn local_path(&self, _key: &str) -> Option<PathBuf> {
+            None
+        }
+
+        async fn stat(&self, _key: &str) -> Result<Option<FileInfo>> {
+            unreachable!()
+        }
+
+        async fn read(&self, _key: &str) -> Result<Vec<u8>> {
+            unreachable!()
+        }
+
+        async fn read_range(&self, _key: &str, _start: u64, _end: u64) -> Result<Vec<u8>> {
+            unreachable!()
+        }
+
+        async fn list(&self, prefix: &str) -> Result<Vec<StoredFile>> {
+            let mut files = vec![];
+
+            for (path, meta) in walk(&self.root.join(prefix)).await? {
+                // Object storages only keep the dates to the second.
+                files.push(StoredFile {
+                    key: path.strip_prefix(&self.root).unwrap().display().to_string(),
+                    len: meta.len(),
+                    last_modified: modified(&meta)
+                        .map(|x| Utc.timestamp_opt(x.timestamp(), 0).unwrap()),
+                });
+            }
+
+            Ok(files)
+        }
+
+        async fn put_file(&self, key: &str, path: &Path) -> Result<()> {
+            self.transfers.fetch_add(1, Ordering::SeqCst);
+            let dest = self.root.join(key);
+            create_dir_all(dest.parent().unwrap()).await?;
+            copy(path, dest).await?;
+            Ok(())
+        }
+
+        async fn get_file(&self, key: &str, path: &Path) -> Result<()> {
+            self.transfers.fetch_add(1, Ordering::SeqCst);
+            create_dir_all(path.parent().unwrap()).await?;
+            copy(self.root.join(key), path).await?;
+            Ok(())
+        }
+
+        async fn delete(&self, _key: &str) -> Result<()> {
+            unreachable!()
+        }
+
+        async fn copy_dir(&self, _from: &str, _to: &str) -> Result<()> {
+            unreachable!()
+        }
+
+        async fn delete_dir(&self, _prefix: &str) -> Result<()> {
+            unreachable!()
+        }
+
+        async fn presign(&self, _key: &str, _duration: u64) -> Result<Option<String>> {
+            unreachable!()
+        }
+    }
+
+    #[rocket::async_test]
+    async fn synced_files_are_not_transferred_again() {
+        let dir = std::env::temp_dir().join(format!("polymny-storage-{}", Uuid::new_v4()));
+        let storage = MockStorage {
+            root: dir.join("bucket"),
+            transfers: AtomicUsize::new(0),
+        };
+
+        // The files of the working copy were written before they are pushed.
+        let copy = dir.join("copy");
+        let output = copy.join("output.mp4");
+        let slide = copy.join("assets/slide.png");
+        create_dir_all(copy.join("assets")).await.unwrap();
+        for path in &[&output, &slide] {
+            write(path, b"content").await.unwrap();
+            set_modified(path, Utc.timestamp_opt(1_600_000_000, 0).unwrap())
+                .await
+                .unwrap();
+        }
+
+        storage.push_dir("1", &copy).await.unwrap();
+        assert_eq!(storage.transfers(), 2);
+
+        storage.pull_dir("1", &copy).await.unwrap();
+        storage.push_dir("1", &copy).await.unwrap();
+        assert_eq!(storage.transfers(), 2);
+
+        // Another working copy is pulled once.
+        let other = dir.join("other");
+        storage.pull_dir("1", &other).await.unwrap();
+        assert_eq!(storage.transfers(), 4);
+
+        storage.push_dir("1", &other).await.unwrap();
+        storage.pull_dir("1", &other).await.unwrap();
+        storage.pull_dir("1", &copy).await.unwrap();
+        assert_eq!(storage.transfers(), 4);
+
+        // A file rewritten in place is pushed again.
+        write(&output, b"CONTENT").await.unwrap();
+        storage.push_dir("1", &copy).await.unwrap();
+        assert_eq!(storage.transfers(), 5);
+
+        storage.pull_dir("1", &copy).await.unwrap();
+        assert_eq!(storage.transfers(), 5);
+
+        remove_dir_all(dir).await.unwrap();
+    }
+}
@@ -65,6 +65,22 @@ pub fn reset_password_email_plain_text(url: &str) -> String {
     )
 }
 
+/// This function formats an assignment reminder email with HTML format.
+pub fn assignment_reminder_html(capsule_name: &str, deadline: &str, url: &str) -> String {
+    format!(
+        "<h1>Your answer is due soon</h1><p>Your answer <b>{}</b> has not been submitted yet, it is due on {} (UTC).</p><p><a href=\"{}\">Click here to open it</a></p>",
+        escape_html(capsule_name), deadline, url
+    )
+}
+
+/// This function formats an assignment reminder email with plain text format.
+pub fn assignment_reminder_plain_text(capsule_name: &str, deadline: &str, url: &str) -> String {
+    format!(
+        "Your answer is due soon\n\nYour answer {} has not been submitted yet, it is due on {} (UTC).\n\nGo on the following link to open it:\n{}",
+        capsule_name, deadline, url
+    )
+}
+
 /// Content of the test email in HTML format.
 pub const TEST_EMAIL_HTML: &str =
     "<h1>Congratulations!</h1><p>If you received this email, it means that the mailer is working!</p>";
@@ -357,30 +373,534 @@ pub fn setup_html() -> &'static str {
 }
 
 /// The HTML page that shows a video.
-pub fn video_html(url: &str) -> String {
+///
+/// The start time and autoplay given by the server take precedence over the legacy `t` and `a`
+/// arguments read from the url by the player. The page regularly sends the played ranges of the
+/// video to `view_url`, identified by `session` and its `signature`. The poster is shown before the video starts, and
+/// the thumbnails track gives the previews shown when seeking, videos produced before they existed
+/// have neither. The chapters track and the slide
+/// index fill a menu to jump to a chapter or to a slide, which stays hidden if they are missing.
+/// The video pauses at each quiz to ask its question, whose answer is posted to `quiz_url` with the
+/// `signature` of the session, and whose correct option is only shown if the server gives it. The
+/// comments read from `comments_url` are listed in the menu, where logged in viewers can ask a
+/// question about the moment being watched.
+pub fn video_html(
+    url: &str,
+    poster: Option<&str>,
+    thumbnails: Option<&str>,
+    chapters: &str,
+    slides: &str,
+    quizzes: &str,
+    quiz_url: &str,
+    comments_url: &str,
+    start_time: Option<f64>,
+    autoplay: bool,
+    view_url: &str,
+    session: &str,
+    signature: &str,
+) -> String {
+    let start_time = match start_time {
+        Some(t) => format!("\"{}\"", t),
+        None => String::from(r#"PolymnyVideo.getArgumentFromUrl("t")"#),
+    };
+
+    let autoplay = if autoplay {
+        String::from("true")
+    } else {
+        String::from(r#"PolymnyVideo.getArgumentFromUrl("a") !== null"#)
+    };
+
+    let og_image = poster
+        .map(|x| {
+            format!(
+                r#"<meta property="og:image" content="{}">"#,
+                x.replace('&', "&amp;")
+            )
+        })
+        .unwrap_or_default();
+
+    let js_string = |x: Option<&str>| {
+        x.map(|x| format!("\"{}\"", x))
+            .unwrap_or_else(|| String::from("undefined"))
+    };
+
     format!(
         r#"<!doctype HTML>
 <html>
     <head>
         <title>video.polymny.studio</title>
         <meta charset="utf-8">
+        {}
+        <style>
+            #navigation-toggle {{ position: fixed; top: 10px; right: 10px; z-index: 10; display: none; padding: 6px 12px; border: none; border-radius: 4px; background-color: rgba(0, 0, 0, 0.6); color: white; font-family: sans-serif; cursor: pointer; }}
+            #navigation {{ position: fixed; top: 0; right: 0; bottom: 0; z-index: 9; display: none; width: 340px; max-width: 100vw; overflow-y: auto; padding: 50px 10px 10px; box-sizing: border-box; background-color: rgba(0, 0, 0, 0.85); color: white; font-family: sans-serif; }}
+            #navigation.open {{ display: block; }}
+            #navigation h2 {{ font-size: 1em; margin: 10px 0; }}
+            #navigation button {{ display: block; width: 100%; padding: 6px; border: none; background: none; color: inherit; text-align: left; font: inherit; cursor: pointer; }}
+            #navigation button:hover, #navigation button.current {{ background-color: rgba(255, 255, 255, 0.2); }}
+            #navigation img {{ display: block; width: 100%; margin-bottom: 4px; }}
+            #navigation .reply {{ margin-left: 20px; padding: 4px 6px; font-size: 0.9em; opacity: 0.8; }}
+            #navigation .reply-link {{ text-decoration: underline; font-size: 0.8em; }}
+            #comment-form {{ display: none; }}
+            #comment-form textarea {{ width: 100%; box-sizing: border-box; }}
+            #quiz {{ position: fixed; top: 0; left: 0; right: 0; bottom: 0; z-index: 11; display: none; justify-content: center; align-items: center; background-color: rgba(0, 0, 0, 0.7); font-family: sans-serif; }}
+            #quiz.open {{ display: flex; }}
+            #quiz > div {{ width: 500px; max-width: 90vw; padding: 20px; border-radius: 8px; background-color: white; }}
+            #quiz button {{ display: block; width: 100%; margin: 8px 0; padding: 10px; border: 1px solid #ccc; border-radius: 4px; background-color: white; font: inherit; text-align: left; cursor: pointer; }}
+            #quiz button.correct {{ border-color: green; background-color: #dfd; }}
+            #quiz button.wrong {{ border-color: red; background-color: #fdd; }}
+            #quiz button.chosen {{ border-color: #333; background-color: #eee; }}
+        </style>
     </head>
     <body>
         <div id="container"></div>
+        <button id="navigation-toggle">Chapitres</button>
+        <div id="navigation">
+            <h2>Chapitres</h2>
+            <div id="chapters"></div>
+            <h2>Diapositives</h2>
+            <div id="slides"></div>
+            <div id="comment-form">
+                <h2>Questions</h2>
+                <div id="comments"></div>
+                <textarea id="comment-content" rows="3" placeholder="Poser une question sur ce moment de la vidéo"></textarea>
+                <button id="comment-send">Envoyer</button>
+                <p id="comment-error"></p>
+            </div>
+        </div>
+        <div id="quiz">
+            <div>
+                <p id="quiz-question"></p>
+                <div id="quiz-options"></div>
+                <p id="quiz-result"></p>
+                <button id="quiz-continue">Continuer</button>
+            </div>
+        </div>
         <script src="/v/polymny-video-full.min.js"></script>
         <script>
             PolymnyVideo.fullpage({{
                 node: document.getElementById("container"),
                 url: "{}",
-                autoplay: PolymnyVideo.getArgumentFromUrl("a") !== null,
-                startTime: PolymnyVideo.getArgumentFromUrl("t"),
+                poster: {},
+                thumbnails: {},
+                autoplay: {},
+                startTime: {},
                 enableMiniatures: true,
                 muted: PolymnyVideo.getArgumentFromUrl("m") !== null
             }});
+
+            (function() {{
+                var send = function() {{
+                    var video = document.querySelector("video");
+                    if (video === null || video.played.length === 0) {{
+                        return;
+                    }}
+                    var played = [];
+                    for (var i = 0; i < video.played.length; i++) {{
+                        played.push([video.played.start(i), video.played.end(i)]);
+                    }}
+                    navigator.sendBeacon("{}", JSON.stringify({{ session: "{}", signature: "{}", played: played }}));
+                }};
+                setInterval(send, 15000);
+                document.addEventListener("visibilitychange", function() {{
+                    if (document.visibilityState === "hidden") {{
+                        send();
+                    }}
+                }});
+            }})();
+
+            (function() {{
+                var navigation = document.getElementById("navigation");
+                var toggle = document.getElementById("navigation-toggle");
+                var entries = [];
+
+                var seek = function(time) {{
+                    var video = document.querySelector("video");
+                    if (video !== null) {{
+                        video.currentTime = time;
+                        video.play();
+                    }}
+                }};
+
+                var seconds = function(timestamp) {{
+                    var parts = timestamp.trim().split(":").map(parseFloat);
+                    return parts.reduce(function(acc, x) {{ return acc * 60 + x; }}, 0);
+                }};
+
+                var entry = function(parent, time, content) {{
+                    var button = document.createElement("button");
+                    button.appendChild(content);
+                    button.addEventListener("click", function() {{ seek(time); }});
+                    parent.appendChild(button);
+                    entries.push({{ time: time, button: button, parent: parent }});
+                    toggle.style.display = "block";
+                }};
+
+                toggle.addEventListener("click", function() {{
+                    navigation.classList.toggle("open");
+                }});
+
+                fetch("{}").then(function(response) {{
+                    return response.ok ? response.text() : "";
+                }}).then(function(track) {{
+                    track.split(/\r?\n\r?\n/).forEach(function(cue) {{
+                        var lines = cue.split(/\r?\n/);
+                        var index = lines.findIndex(function(x) {{ return x.indexOf("-->") !== -1; }});
+                        if (index !== -1) {{
+                            var time = seconds(lines[index].split("-->")[0]);
+                            var title = lines.slice(index + 1).join(" ");
+                            entry(document.getElementById("chapters"), time, document.createTextNode(title));
+                        }}
+                    }});
+                }}).catch(function() {{}});
+
+                var slidesUrl = new URL("{}", window.location.href);
+
+                fetch(slidesUrl).then(function(response) {{
+                    return response.ok ? response.json() : {{ slides: [] }};
+                }}).then(function(index) {{
+                    index.slides.forEach(function(slide) {{
+                        var content = document.createElement("div");
+                        var image = document.createElement("img");
+                        image.src = new URL(slide.thumbnail, slidesUrl).href;
+                        image.loading = "lazy";
+                        content.appendChild(image);
+                        content.appendChild(document.createTextNode(slide.title));
+                        entry(document.getElementById("slides"), slide.time, content);
+                    }});
+                }}).catch(function() {{}});
+
+                var commentsUrl = "{}";
+                var comments = document.getElementById("comments");
+                var commentError = document.getElementById("comment-error");
+
+                var post = function(body) {{
+                    commentError.textContent = "";
+                    return fetch(commentsUrl, {{
+                        method: "POST",
+                        headers: {{ "Content-Type": "application/json" }},
+                        body: JSON.stringify(body)
+                    }}).then(function(response) {{
+                        if (response.status === 401) {{
+                            commentError.textContent = "Connectez-vous pour poser une question.";
+                        }} else if (!response.ok) {{
+                            commentError.textContent = "Le commentaire n'a pas pu être envoyé.";
+                        }}
+                        return response.ok;
+                    }});
+                }};
+
+                var loadComments = function() {{
+                    fetch(commentsUrl).then(function(response) {{
+                        return response.ok ? response.json() : {{ visibility: "disabled", comments: [] }};
+                    }}).then(function(result) {{
+                        if (result.visibility === "disabled" && result.comments.length === 0) {{
+                            return;
+                        }}
+                        document.getElementById("comment-form").style.display = "block";
+                        toggle.style.display = "block";
+                        comments.innerHTML = "";
+                        entries = entries.filter(function(x) {{ return x.parent !== comments; }});
+                        result.comments.forEach(function(comment) {{
+                            var content = document.createElement("div");
+                            content.textContent = comment.author + " : " + comment.content;
+                            var reply = document.createElement("div");
+                            reply.className = "reply-link";
+                            reply.textContent = "Répondre";
+                            reply.addEventListener("click", function(event) {{
+                                event.stopPropagation();
+                                var text = window.prompt("Votre réponse");
+                                if (text) {{
+                                    post({{ time: comment.time, content: text, parent: comment.id }}).then(loadComments);
+                                }}
+                            }});
+                            content.appendChild(reply);
+                            entry(comments, comment.time, content);
+                            comment.replies.forEach(function(x) {{
+                                var reply = document.createElement("div");
+                                reply.className = "reply";
+                                reply.textContent = x.author + " : " + x.content;
+                                comments.appendChild(reply);
+                            }});
+                        }});
+                    }}).catch(function() {{}});
+                }};
+
+                document.getElementById("comment-send").addEventListener("click", function() {{
+                    var video = document.querySelector("video");
+                    var input = document.getElementById("comment-content");
+                    if (input.value.trim() === "") {{
+                        return;
+                    }}
+                    post({{ time: video === null ? 0 : video.currentTime, content: input.value }}).then(function(ok) {{
+                        if (ok) {{
+                            input.value = "";
+                            loadComments();
+                        }}
+                    }});
+                }});
+
+                loadComments();
+
+                // Highlights the chapter and the slide being watched.
+                setInterval(function() {{
+                    var video = document.querySelector("video");
+                    if (video === null) {{
+                        return;
+                    }}
+                    var current = new Map();
+                    entries.forEach(function(x) {{
+                        if (x.time <= video.currentTime) {{
+                            current.set(x.parent, x);
+                        }}
+                    }});
+                    entries.forEach(function(x) {{
+                        x.button.classList.toggle("current", current.get(x.parent) === x);
+                    }});
+                }}, 1000);
+            }})();
+
+            (function() {{
+                var overlay = document.getElementById("quiz");
+                var options = document.getElementById("quiz-options");
+                var result = document.getElementById("quiz-result");
+                var next = document.getElementById("quiz-continue");
+                var quizzes = [];
+                var asked = {{}};
+                var last = 0;
+
+                var ask = function(video, quiz) {{
+                    asked[quiz.id] = true;
+                    video.pause();
+                    document.getElementById("quiz-question").textContent = quiz.question;
+                    options.innerHTML = "";
+                    result.textContent = "";
+                    next.style.display = "none";
+
+                    var buttons = quiz.options.map(function(text, index) {{
+                        var button = document.createElement("button");
+                        button.textContent = text;
+                        button.addEventListener("click", function() {{
+                            buttons.forEach(function(x) {{ x.disabled = true; }});
+                            fetch("{}/" + quiz.id, {{
+                                method: "POST",
+                                headers: {{ "Content-Type": "application/json" }},
+                                body: JSON.stringify({{ session: "{}", signature: "{}", option: index }})
+                            }}).then(function(response) {{
+                                return response.json();
+                            }}).then(function(answer) {{
+                                if (answer.answer === undefined) {{
+                                    buttons[answer.option].classList.add("chosen");
+                                    result.textContent = "Réponse enregistrée.";
+                                }} else {{
+                                    buttons[answer.answer].classList.add("correct");
+                                    if (!answer.correct) {{
+                                        buttons[answer.option].classList.add("wrong");
+                                    }}
+                                    result.textContent = answer.correct ? "Bonne réponse !" : "Mauvaise réponse.";
+                                }}
+                                next.style.display = "block";
+                            }}).catch(function() {{
+                                next.style.display = "block";
+                            }});
+                        }});
+                        options.appendChild(button);
+                        return button;
+                    }});
+
+                    overlay.classList.add("open");
+                }};
+
+                next.addEventListener("click", function() {{
+                    overlay.classList.remove("open");
+                    var video = document.querySelector("video");
+                    if (video !== null) {{
+                        video.play();
+                    }}
+                }});
+
+                fetch("{}").then(function(response) {{
+                    return response.ok ? response.json() : {{ quizzes: [] }};
+                }}).then(function(index) {{
+                    quizzes = index.quizzes;
+                }}).catch(function() {{}});
+
+                // A quiz is asked when the video plays through its moment, not when seeking past it.
+                setInterval(function() {{
+                    var video = document.querySelector("video");
+                    if (video === null) {{
+                        return;
+                    }}
+                    var now = video.currentTime;
+                    if (!video.paused && now >= last && now - last < 1) {{
+                        var quiz = quizzes.find(function(x) {{
+                            return !asked[x.id] && x.time > last && x.time <= now;
+                        }});
+                        if (quiz !== undefined) {{
+                            ask(video, quiz);
+                        }}
+                    }}
+                    last = now;
+                }}, 200);
+            }})();
         </script>
     </body>
 </html>
 "#,
-        url
+        og_image,
+        url,
+        js_string(poster),
+        js_string(thumbnails),
+        autoplay,
+        start_time,
+        view_url,
+        session,
+        signature,
+        chapters,
+        slides,
+        comments_url,
+        quiz_url,
+        session,
+        signature,
+        quizzes
+    )
+}
+
+/// The HTML page that asks for the password of a share link.
+pub fn share_password_html(wrong_password: bool) -> String {
+    format!(
+        r#"<!doctype HTML>
+<html>
+    <head>
+        <title>video.polymny.studio</title>
+        <meta charset="utf-8">
+        <meta name="viewport" content="width=device-width, initial-scale=1">
+    </head>
+    <body style="font-family: sans-serif; display: flex; justify-content: center; align-items: center; height: 100vh; margin: 0;">
+        <form method="post">
+            <p>Cette vidéo est protégée par un mot de passe.</p>
+            {}
+            <input type="password" name="password" autofocus>
+            <input type="submit" value="Valider">
+        </form>
+    </body>
+</html>
+"#,
+        if wrong_password {
+            r#"<p style="color: red;">Mot de passe incorrect.</p>"#
+        } else {
+            ""
+        }
+    )
+}
+
+/// Escapes a string so that it can be put in HTML or XML content or attributes.
+pub fn escape_html(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// The manifest of the SCORM 1.2 package of a video.
+pub fn scorm_manifest_xml(identifier: &str, title: &str, subtitles: bool) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest identifier="{identifier}" version="1.0"
+    xmlns="http://www.imsproject.org/xsd/imscp_rootv1p1p2"
+    xmlns:adlcp="http://www.adlnet.org/xsd/adlcp_rootv1p2"
+    xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"
+    xsi:schemaLocation="http://www.imsproject.org/xsd/imscp_rootv1p1p2 imscp_rootv1p1p2.xsd http://www.adlnet.org/xsd/adlcp_rootv1p2 adlcp_rootv1p2.xsd">
+    <metadata>
+        <schema>ADL SCORM</schema>
+        <schemaversion>1.2</schemaversion>
+    </metadata>
+    <organizations default="{identifier}-organization">
+        <organization identifier="{identifier}-organization">
+            <title>{title}</title>
+            <item identifier="{identifier}-item" identifierref="{identifier}-resource">
+                <title>{title}</title>
+            </item>
+        </organization>
+    </organizations>
+    <resources>
+        <resource identifier="{identifier}-resource" type="webcontent" adlcp:scormtype="sco" href="index.html">
+            <file href="index.html"/>
+            <file href="video.mp4"/>{subtitles}
+        </resource>
+    </resources>
+</manifest>
+"#,
+        identifier = escape_html(identifier),
+        title = escape_html(title),
+        subtitles = if subtitles {
+            "\n            <file href=\"subtitles.vtt\"/>"
+        } else {
+            ""
+        },
+    )
+}
+
+/// The page of the SCORM package of a video, that reports to the learning management system that
+/// the lesson is completed once the video has been watched until the end.
+pub fn scorm_index_html(title: &str, subtitles: bool) -> String {
+    format!(
+        r#"<!doctype HTML>
+<html>
+    <head>
+        <title>{title}</title>
+        <meta charset="utf-8">
+        <meta name="viewport" content="width=device-width, initial-scale=1">
+    </head>
+    <body style="margin: 0; background-color: black;">
+        <video src="video.mp4" controls style="width: 100vw; height: 100vh;">{subtitles}
+        </video>
+        <script>
+            function findApi(win) {{
+                for (var i = 0; win && i < 10; i++) {{
+                    if (win.API) {{
+                        return win.API;
+                    }}
+                    if (win.parent === win) {{
+                        break;
+                    }}
+                    win = win.parent;
+                }}
+                return null;
+            }}
+
+            var api = findApi(window) || (window.opener && findApi(window.opener));
+            if (api) {{
+                api.LMSInitialize("");
+                if (api.LMSGetValue("cmi.core.lesson_status") !== "completed") {{
+                    api.LMSSetValue("cmi.core.lesson_status", "incomplete");
+                }}
+                api.LMSCommit("");
+            }}
+
+            document.querySelector("video").addEventListener("ended", function() {{
+                if (api) {{
+                    api.LMSSetValue("cmi.core.lesson_status", "completed");
+                    api.LMSCommit("");
+                }}
+            }});
+
+            window.addEventListener("unload", function() {{
+                if (api) {{
+                    api.LMSFinish("");
+                }}
+            }});
+        </script>
+    </body>
+</html>
+"#,
+        title = escape_html(title),
+        subtitles = if subtitles {
+            "\n            <track kind=\"subtitles\" src=\"subtitles.vtt\" default>"
+        } else {
+            ""
+        },
     )
 }
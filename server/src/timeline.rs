@@ -0,0 +1,409 @@
+//! This module contains the timeline of a produced capsule, that tells when each gos and each slide
+//! start in the video.
+//!
+//! The timeline gives the chapters, the slide index and the quizzes written with the publication,
+//! which let the player and the podcast applications navigate in the video.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::Path;
+
+use tokio::fs::{create_dir_all, write};
+use tokio::task::spawn_blocking;
+
+use rocket::http::Status;
+use rocket::serde::json::{json, Value};
+
+use crate::command::run_command;
+use crate::config::Config;
+use crate::db::capsule::{Capsule, Gos, SlideSpan};
+use crate::{Error, Result};
+
+/// The width of the thumbnails of the slide index.
+const SLIDE_THUMBNAIL_WIDTH: u32 = 320;
+
+/// The timeline of a produced capsule.
+pub struct Timeline {
+    /// The moments each gos starts, in ms.
+    pub gos: Vec<i32>,
+
+    /// The moments each slide is shown, in order.
+    pub slides: Vec<SlideSpan>,
+
+    /// The language of the default titles of the chapters and of the slides.
+    pub language: String,
+}
+
+impl Timeline {
+    /// Computes the timeline of a capsule from its structure.
+    ///
+    /// Unlike the timeline used for the analytics, the durations of the extra videos are read from
+    /// the working copy of the capsule, which blocks the thread.
+    pub fn new(capsule: &Capsule, config: &Config) -> Timeline {
+        let assets = config
+            .data_path
+            .join(format!("{}", capsule.id))
+            .join("assets");
+
+        let slides = capsule
+            .slide_timeline_with(|extra| extra_duration(&assets.join(format!("{}.mp4", extra))));
+
+        Timeline {
+            gos: gos_starts(&slides, capsule.structure.0.len()),
+            slides,
+            language: config.content_language.clone(),
+        }
+    }
+
+    /// Generates the chapters of the video, one per gos, in the JSON format of Podcasting 2.0.
+    pub fn chapters_json(&self, capsule: &Capsule) -> Value {
+        let chapters = capsule
+            .structure
+            .0
+            .iter()
+            .zip(&self.gos)
+            .enumerate()
+            .map(|(index, (gos, start))| {
+                json!({
+                    "startTime": *start as f64 / 1000.0,
+                    "title": gos_title(gos, index, &self.language),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "version": "1.2.0",
+            "chapters": chapters,
+        })
+    }
+
+    /// Generates the chapters of the video, one per gos, as a WebVTT chapters track.
+    pub fn chapters_webvtt(&self, capsule: &Capsule) -> String {
+        let mut track = String::from("WEBVTT\n");
+
+        for (index, (gos, start)) in capsule.structure.0.iter().zip(&self.gos).enumerate() {
+            let end = self
+                .gos
+                .get(index + 1)
+                .copied()
+                .unwrap_or(capsule.duration_ms)
+                .max(*start);
+
+            write!(
+                track,
+                "\n{}\n{} --> {}\n{}\n",
+                index + 1,
+                vtt_time(*start),
+                vtt_time(end),
+                gos_title(gos, index, &self.language)
+            )
+            .ok();
+        }
+
+        track
+    }
+
+    /// Generates the quizzes of the video, with the moment they are asked in the video but without
+    /// their answer, which is only given once the viewer answered.
+    pub fn quizzes_json(&self, capsule: &Capsule) -> Value {
+        let quizzes = capsule
+            .quizzes()
+            .into_iter()
+            .map(|(gos, quiz)| {
+                let start = self.gos.get(gos).copied().unwrap_or(0);
+
+                json!({
+                    "id": quiz.id,
+                    "time": (start + quiz.time) as f64 / 1000.0,
+                    "question": quiz.question,
+                    "options": quiz.options,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "quizzes": quizzes })
+    }
+
+    /// Generates the index of the slides of the video, that gives the moment each slide is shown
+    /// with its title and its thumbnail.
+    pub fn slides_json(&self, capsule: &Capsule) -> Value {
+        let slides = self
+            .slides
+            .iter()
+            .filter_map(|span| {
+                let gos = capsule.structure.0.get(span.gos)?;
+                let slide = gos.slides.get(span.slide)?;
+
+                Some(json!({
+                    "time": span.start as f64 / 1000.0,
+                    "gos": span.gos,
+                    "slide": span.slide,
+                    "title": slide_title(&slide.prompt, span.slide, &self.language),
+                    "thumbnail": format!("slides/{}.jpg", slide.uuid),
+                }))
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "slides": slides })
+    }
+}
+
+/// Returns the moments each gos starts from the timeline of the slides, in ms.
+///
+/// A gos starts with its first slide, or where the previous one ended if it has none.
+fn gos_starts(slides: &[SlideSpan], count: usize) -> Vec<i32> {
+    let mut gos = vec![];
+    let mut end = 0;
+
+    for index in 0..count {
+        let spans = slides.iter().filter(|x| x.gos == index);
+        let start = spans.clone().map(|x| x.start).min().unwrap_or(end);
+        end = spans.map(|x| x.end).max().unwrap_or(start);
+        gos.push(start);
+    }
+
+    gos
+}
+
+/// Formats a moment in ms as a WebVTT timestamp.
+fn vtt_time(ms: i32) -> String {
+    let ms = ms.max(0);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        (ms / 60_000) % 60,
+        (ms / 1000) % 60,
+        ms % 1000
+    )
+}
+
+/// Returns the duration of an extra video in ms, zero if it cannot be read.
+fn extra_duration(path: &Path) -> i32 {
+    let output = path
+        .to_str()
+        .and_then(|path| run_command(&vec!["../scripts/psh", "duration", path]).ok());
+
+    let duration = output.and_then(|x| {
+        std::str::from_utf8(&x.stdout)
+            .ok()
+            .and_then(|x| x.trim().parse::<f32>().ok())
+    });
+
+    match duration {
+        Some(duration) => (duration * 1000.0) as i32,
+        None => {
+            warn!("Failed to read the duration of {}", path.display());
+            0
+        }
+    }
+}
+
+/// Returns the first line of a prompt that is not empty.
+fn first_line(prompt: &str) -> Option<&str> {
+    prompt.lines().map(str::trim).find(|x| !x.is_empty())
+}
+
+/// Returns the words used in a language to name the gos and the slides that have no title.
+fn default_titles(language: &str) -> (&'static str, &'static str) {
+    if language.starts_with("fr") {
+        ("Partie", "Diapositive")
+    } else {
+        ("Part", "Slide")
+    }
+}
+
+/// Returns the title of a gos, which is the first line of the prompt of its first slide.
+pub fn gos_title(gos: &Gos, index: usize, language: &str) -> String {
+    gos.slides
+        .first()
+        .and_then(|x| first_line(&x.prompt))
+        .map(String::from)
+        .unwrap_or_else(|| format!("{} {}", default_titles(language).0, index + 1))
+}
+
+/// Returns the title of a slide, which is the first line of its prompt.
+pub fn slide_title(prompt: &str, index: usize, language: &str) -> String {
+    first_line(prompt)
+        .map(String::from)
+        .unwrap_or_else(|| format!("{} {}", default_titles(language).1, index + 1))
+}
+
+/// Generates the transcript of a capsule, made of the prompts of its slides.
+pub fn transcript(capsule: &Capsule) -> String {
+    capsule
+        .structure
+        .0
+        .iter()
+        .flat_map(|gos| gos.slides.iter())
+        .map(|slide| slide.prompt.trim())
+        .filter(|prompt| !prompt.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Writes the chapters, the slide index with its thumbnails, the quizzes and the transcript of a
+/// capsule in the output directory of its publication.
+pub async fn write_publication_files(
+    capsule: &Capsule,
+    config: &Config,
+    output: &Path,
+) -> Result<()> {
+    let timeline = {
+        let capsule = capsule.clone();
+        let config = config.clone();
+        spawn_blocking(move || Timeline::new(&capsule, &config))
+            .await
+            .map_err(|_| Error(Status::InternalServerError))?
+    };
+
+    write(
+        output.join("chapters.json"),
+        timeline.chapters_json(capsule).to_string(),
+    )
+    .await?;
+
+    write(
+        output.join("chapters.vtt"),
+        timeline.chapters_webvtt(capsule),
+    )
+    .await?;
+
+    write(
+        output.join("slides.json"),
+        timeline.slides_json(capsule).to_string(),
+    )
+    .await?;
+
+    write(
+        output.join("quizzes.json"),
+        timeline.quizzes_json(capsule).to_string(),
+    )
+    .await?;
+
+    write(output.join("transcript.txt"), transcript(capsule)).await?;
+
+    let assets = config
+        .data_path
+        .join(format!("{}", capsule.id))
+        .join("assets");
+
+    let thumbnails = output.join("slides");
+    create_dir_all(&thumbnails).await?;
+
+    let mut done = HashSet::new();
+    let mut slides = vec![];
+    for span in &timeline.slides {
+        let slide = match capsule
+            .structure
+            .0
+            .get(span.gos)
+            .and_then(|x| x.slides.get(span.slide))
+        {
+            Some(slide) => slide,
+            None => continue,
+        };
+
+        if done.insert(slide.uuid) {
+            slides.push(slide.uuid);
+        }
+    }
+
+    // The thumbnails are generated by blocking commands.
+    spawn_blocking(move || {
+        for uuid in slides {
+            let input = assets.join(format!("{}.png", uuid));
+            let thumbnail = thumbnails.join(format!("{}.jpg", uuid));
+
+            if let (Some(input), Some(thumbnail)) = (input.to_str(), thumbnail.to_str()) {
+                if run_command(&vec![
+                    "../scripts/psh",
+                    "slide-thumbnail",
+                    input,
+                    thumbnail,
+                    &format!("{}", SLIDE_THUMBNAIL_WIDTH),
+                ])
+                .is_err()
+                {
+                    warn!("Failed to generate the thumbnail of slide {}", uuid);
+                }
+            }
+        }
+    })
+    .await
+    .map_err(|_| Error(Status::InternalServerError))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{gos_starts, gos_title, slide_title, vtt_time};
+    use crate::db::capsule::{Gos, Slide, SlideSpan};
+
+    /// Creates a gos whose slides have some prompts.
+    fn gos(prompts: &[&str]) -> Gos {
+        let mut gos = Gos::new();
+        gos.slides = prompts
+            .iter()
+            .map(|prompt| Slide {
+                uuid: Uuid::nil(),
+                extra: None,
+                prompt: String::from(*prompt),
+            })
+            .collect();
+        gos
+    }
+
+    /// Creates the span of a slide.
+    fn span(gos: usize, slide: usize, start: i32, end: i32) -> SlideSpan {
+        SlideSpan {
+            gos,
+            slide,
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn vtt_times() {
+        assert_eq!(vtt_time(0), "00:00:00.000");
+        assert_eq!(vtt_time(3_723_004), "01:02:03.004");
+        assert_eq!(vtt_time(-10), "00:00:00.000");
+    }
+
+    #[test]
+    fn gos_are_named_after_their_first_slide() {
+        assert_eq!(
+            gos_title(&gos(&["\n  Intro \nrest", "Next"]), 0, "fr"),
+            "Intro"
+        );
+        assert_eq!(gos_title(&gos(&[" ", "Next"]), 1, "fr"), "Partie 2");
+        assert_eq!(gos_title(&gos(&[]), 2, "en"), "Part 3");
+    }
+
+    #[test]
+    fn slides_are_named_after_their_prompt() {
+        assert_eq!(slide_title("Title\nMore", 0, "fr"), "Title");
+        assert_eq!(slide_title("", 0, "fr-FR"), "Diapositive 1");
+        assert_eq!(slide_title("\n", 4, "en"), "Slide 5");
+    }
+
+    #[test]
+    fn gos_start_with_their_first_slide() {
+        // The first slide of the second gos is shown again before the end of the gos.
+        let slides = vec![
+            span(0, 0, 0, 3000),
+            span(0, 1, 3000, 5000),
+            span(1, 0, 5000, 7000),
+            span(1, 1, 7000, 8000),
+            span(1, 0, 8000, 9000),
+            span(3, 0, 9000, 12000),
+        ];
+
+        // The third gos has no slide, it starts where the second one ended.
+        assert_eq!(gos_starts(&slides, 4), vec![0, 5000, 9000, 9000]);
+        assert_eq!(gos_starts(&[], 2), vec![0, 0]);
+    }
+}